@@ -0,0 +1,64 @@
+//! WebAssembly bindings of [inferrust](../inferrust/index.html).
+//!
+//! This crate depends on inferrust without its default features
+//! (threads and memory maps are not available on `wasm32-unknown-unknown`),
+//! and is built as a cdylib for wasm-bindgen, e.g. `wasm-pack build inferrust-wasm`.
+
+use std::fmt::Write;
+
+use inferrust::{InfGraph, RuleProfile};
+use sophia::parser::turtle;
+use sophia_api::graph::Graph;
+use sophia_api::triple::Triple;
+use wasm_bindgen::prelude::*;
+
+/// Parse `turtle`, reason on it with the rule profile named `profile`,
+/// and return all the triples (explicit and inferred) in N-Triples.
+///
+/// The profile names are those of `RuleProfile::name`
+/// (`RDFS`, `RHODF`, `RDFSPLUS` and `RDFSPLUS-CANONICAL`), ignoring case.
+#[wasm_bindgen]
+pub fn reason(turtle: &str, profile: &str) -> Result<String, JsValue> {
+    reason_ntriples(turtle, profile).map_err(|e| JsValue::from_str(&e))
+}
+
+/// The implementation of `reason`, failing with a message.
+fn reason_ntriples(turtle: &str, profile: &str) -> Result<String, String> {
+    let profile = RuleProfile::by_name(profile).ok_or_else(|| format!("unknown rule profile {}", profile))?;
+    let graph = InfGraph::new(turtle::parse_str(turtle), &profile).map_err(|e| e.to_string())?;
+    let mut ntriples = String::new();
+    for t in graph.triples() {
+        let t = t.expect("InfGraph::triples is infallible");
+        writeln!(ntriples, "{} {} {} .", t.s(), t.p(), t.o()).expect("writing to a String never fails");
+    }
+    Ok(ntriples)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INPUT: &str = r#"
+        @prefix : <http://example.org/> .
+        @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+        :bart a :Boy .
+        :Boy rdfs:subClassOf :Person .
+    "#;
+
+    #[test]
+    fn test_reason() {
+        // NB: on other targets than wasm32, `JsValue`s can not be built,
+        // so only successful calls of `reason` can be tested
+        let ntriples = reason(INPUT, "rdfs").unwrap();
+        assert!(ntriples.lines().any(|line| {
+            line == "<http://example.org/bart> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <http://example.org/Person> ."
+        }));
+        assert!(ntriples.lines().all(|line| line.ends_with(" .")));
+
+        assert_eq!(
+            reason_ntriples(INPUT, "owl-full"),
+            Err("unknown rule profile owl-full".to_string())
+        );
+        assert!(reason_ntriples("this is not turtle", "rdfs").is_err());
+    }
+}
@@ -2,20 +2,21 @@ use criterion::{criterion_group, criterion_main, Criterion};
 
 use inferrust::inferray::*;
 use inferrust::rules::*;
-use rayon;
+use rayon::ThreadPoolBuilder;
 
 // retrieve the mirror at http://swat.cse.lehigh.edu/onto/univ-bench-dl.owl
 // and converted to N-Triples
 const UOBM_ONTO: &str = "benches/univ-bench-dl.nt";
 
 pub fn uobm_total(c: &mut Criterion) {
-    rayon::ThreadPoolBuilder::new().num_threads(1).build_global().unwrap();
+    let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
     let data = std::fs::read_to_string(UOBM_ONTO).expect("open file");
     c.bench_function("uobm_total", |b| {
         b.iter(|| {
-            let graph = InfGraph::new(
+            let graph = InfGraph::new_in_pool(
                 sophia_api::parser::nt::parse_str(&data),
-                &RuleProfile::RDFSPlus()
+                &RuleProfile::RDFSPlus(),
+                &pool,
             ).expect("error during parsing");
             assert!(graph.size() > 711);
         })
@@ -0,0 +1,339 @@
+//! Serialization of query results in the SPARQL 1.1 Query Results formats
+//! (JSON, XML and CSV), and of graphs in N-Triples.
+
+use std::fmt::Write;
+
+use inferrust::QueryResults;
+use sophia_api::term::{TTerm, TermKind};
+use sophia_term::ArcTerm;
+
+const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+
+/// A format of query results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Xml,
+    Csv,
+    NTriples,
+}
+
+impl Format {
+    /// The formats in which `results` can be serialized, by order of preference.
+    pub fn supported(results: &QueryResults) -> &'static [Format] {
+        match results {
+            QueryResults::Solutions { .. } => &[Format::Json, Format::Xml, Format::Csv],
+            // CSV has no representation for booleans
+            QueryResults::Boolean(_) => &[Format::Json, Format::Xml],
+            QueryResults::Graph(_) => &[Format::NTriples],
+        }
+    }
+
+    /// The format of `supported` with the highest quality value in the `Accept` header `accept`
+    /// (the first one if `accept` is empty, or between formats of equal quality).
+    ///
+    /// The quality of a format is that of the most specific media range matching it,
+    /// e.g. `text/csv` rather than `text/*` or `*/*`.
+    /// Return `None` if every supported format has a null quality.
+    pub fn negotiate(accept: &str, supported: &[Format]) -> Option<Self> {
+        if accept.trim().is_empty() {
+            return supported.first().copied();
+        }
+        let ranges: Vec<(String, f32)> = accept.split(',').map(media_range).collect();
+        let mut best: Option<(Format, f32)> = None;
+        for format in supported {
+            let quality = ranges
+                .iter()
+                .filter_map(|(range, q)| format.specificity(range).map(|specificity| (specificity, *q)))
+                .max_by_key(|(specificity, _)| *specificity)
+                .map_or(0.0, |(_, q)| q);
+            if quality > best.map_or(0.0, |(_, q)| q) {
+                best = Some((*format, quality));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    /// The media types of this format.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/sparql-results+json", "application/json"],
+            Format::Xml => &["application/sparql-results+xml", "application/xml", "text/xml"],
+            Format::Csv => &["text/csv"],
+            Format::NTriples => &["application/n-triples"],
+        }
+    }
+
+    /// How specifically the media range `range` matches this format
+    /// (2 for a media type, 1 for `type/*` and 0 for `*/*`), if it does.
+    fn specificity(self, range: &str) -> Option<u8> {
+        if range == "*/*" {
+            return Some(0);
+        }
+        let types = self.media_types();
+        if types.contains(&range) {
+            Some(2)
+        } else if range.ends_with("/*") && types.iter().any(|t| t.starts_with(&range[..range.len() - 1])) {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/sparql-results+json",
+            Format::Xml => "application/sparql-results+xml",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::NTriples => "application/n-triples",
+        }
+    }
+}
+
+/// The media range and quality value of an element of an `Accept` header
+/// (an invalid quality value counts as 1).
+fn media_range(element: &str) -> (String, f32) {
+    let mut parts = element.split(';');
+    let range = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+    let quality = parts
+        .filter_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            if name.trim().eq_ignore_ascii_case("q") {
+                value.trim().parse::<f32>().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+        .map_or(1.0, |q| q.clamp(0.0, 1.0));
+    (range, quality)
+}
+
+/// Serialize `results` in the preferred format of the `Accept` header `accept`
+/// among those supporting them (see `Format::negotiate`),
+/// returning the content type and the serialization.
+///
+/// Graphs (the results of CONSTRUCT queries) are serialized in N-Triples.
+/// Return `None` if no acceptable format supports `results`.
+pub fn serialize(results: &QueryResults, accept: &str) -> Option<(&'static str, String)> {
+    let format = Format::negotiate(accept, Format::supported(results))?;
+    let body = match (format, results) {
+        (Format::Json, QueryResults::Solutions { variables, rows }) => json_solutions(variables, rows),
+        (Format::Json, QueryResults::Boolean(b)) => format!("{{\"head\":{{}},\"boolean\":{}}}\n", b),
+        (Format::Xml, QueryResults::Solutions { variables, rows }) => xml_solutions(variables, rows),
+        (Format::Xml, QueryResults::Boolean(b)) => format!(
+            "<?xml version=\"1.0\"?>\n<sparql xmlns=\"http://www.w3.org/2005/sparql-results#\">\n\
+             <head/>\n<boolean>{}</boolean>\n</sparql>\n",
+            b
+        ),
+        (Format::Csv, QueryResults::Solutions { variables, rows }) => csv_solutions(variables, rows),
+        (Format::NTriples, QueryResults::Graph(triples)) => ntriples(triples),
+        _ => unreachable!("{:?} is not supported by these results", format),
+    };
+    Some((format.content_type(), body))
+}
+
+fn ntriples(triples: &[[ArcTerm; 3]]) -> String {
+    let mut out = String::new();
+    for [s, p, o] in triples {
+        writeln!(out, "{} {} {} .", s, p, o).expect("writing to a String never fails");
+    }
+    out
+}
+
+fn json_solutions(variables: &[String], rows: &[Vec<Option<ArcTerm>>]) -> String {
+    let vars: Vec<String> = variables.iter().map(|v| json_string(v)).collect();
+    let mut out = format!("{{\"head\":{{\"vars\":[{}]}},\"results\":{{\"bindings\":[", vars.join(","));
+    for (i, row) in rows.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let bindings: Vec<String> = vars
+            .iter()
+            .zip(row.iter())
+            .filter_map(|(var, value)| value.as_ref().map(|term| format!("{}:{}", var, json_term(term))))
+            .collect();
+        write!(out, "{{{}}}", bindings.join(",")).expect("writing to a String never fails");
+    }
+    out.push_str("]}}\n");
+    out
+}
+
+fn json_term(term: &ArcTerm) -> String {
+    let value = json_string(&term.value());
+    match term.kind() {
+        TermKind::BlankNode => format!("{{\"type\":\"bnode\",\"value\":{}}}", value),
+        TermKind::Literal => match (term.language(), term.datatype()) {
+            (Some(lang), _) => format!(
+                "{{\"type\":\"literal\",\"value\":{},\"xml:lang\":{}}}",
+                value,
+                json_string(lang)
+            ),
+            (None, Some(dt)) if &*dt.value() != XSD_STRING => format!(
+                "{{\"type\":\"literal\",\"value\":{},\"datatype\":{}}}",
+                value,
+                json_string(&dt.value())
+            ),
+            _ => format!("{{\"type\":\"literal\",\"value\":{}}}", value),
+        },
+        _ => format!("{{\"type\":\"uri\",\"value\":{}}}", value),
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).expect("writing to a String never fails")
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn xml_solutions(variables: &[String], rows: &[Vec<Option<ArcTerm>>]) -> String {
+    let mut out =
+        String::from("<?xml version=\"1.0\"?>\n<sparql xmlns=\"http://www.w3.org/2005/sparql-results#\">\n<head>\n");
+    for var in variables {
+        writeln!(out, "<variable name=\"{}\"/>", xml_escape(var)).expect("writing to a String never fails");
+    }
+    out.push_str("</head>\n<results>\n");
+    for row in rows {
+        out.push_str("<result>\n");
+        for (var, value) in variables.iter().zip(row.iter()) {
+            if let Some(term) = value {
+                writeln!(out, "<binding name=\"{}\">{}</binding>", xml_escape(var), xml_term(term))
+                    .expect("writing to a String never fails");
+            }
+        }
+        out.push_str("</result>\n");
+    }
+    out.push_str("</results>\n</sparql>\n");
+    out
+}
+
+fn xml_term(term: &ArcTerm) -> String {
+    let value = xml_escape(&term.value());
+    match term.kind() {
+        TermKind::BlankNode => format!("<bnode>{}</bnode>", value),
+        TermKind::Literal => match (term.language(), term.datatype()) {
+            (Some(lang), _) => format!("<literal xml:lang=\"{}\">{}</literal>", xml_escape(lang), value),
+            (None, Some(dt)) if &*dt.value() != XSD_STRING => {
+                format!("<literal datatype=\"{}\">{}</literal>", xml_escape(&dt.value()), value)
+            }
+            _ => format!("<literal>{}</literal>", value),
+        },
+        _ => format!("<uri>{}</uri>", value),
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn csv_solutions(variables: &[String], rows: &[Vec<Option<ArcTerm>>]) -> String {
+    let header: Vec<String> = variables.iter().map(|v| csv_field(v)).collect();
+    let mut out = header.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|value| match value {
+                None => String::new(),
+                Some(term) if term.kind() == TermKind::BlankNode => format!("_:{}", term.value()),
+                Some(term) => csv_field(&term.value()),
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let results = QueryResults::Solutions {
+            variables: vec!["x".into(), "y".into()],
+            rows: vec![
+                vec![Some(ArcTerm::new_iri_unchecked("http://example.org/a")), None],
+                vec![
+                    Some(ArcTerm::new_literal_lang_unchecked("a,\"b\"", "en")),
+                    Some(ArcTerm::new_bnode_unchecked("b1")),
+                ],
+            ],
+        };
+        assert_eq!(
+            serialize(&results, "").unwrap(),
+            (
+                "application/sparql-results+json",
+                concat!(
+                    r#"{"head":{"vars":["x","y"]},"results":{"bindings":["#,
+                    r#"{"x":{"type":"uri","value":"http://example.org/a"}},"#,
+                    r#"{"x":{"type":"literal","value":"a,\"b\"","xml:lang":"en"},"y":{"type":"bnode","value":"b1"}}"#,
+                    "]}}\n"
+                )
+                .to_string()
+            )
+        );
+        assert_eq!(
+            serialize(&results, "text/csv").unwrap().1,
+            "x,y\r\nhttp://example.org/a,\r\n\"a,\"\"b\"\"\",_:b1\r\n"
+        );
+        assert!(serialize(&results, "text/html,application/xml;q=0.9").unwrap().1.contains(
+            "<binding name=\"x\"><literal xml:lang=\"en\">a,&quot;b&quot;</literal></binding>"
+        ));
+        assert_eq!(serialize(&QueryResults::Boolean(true), "text/csv"), None);
+        assert_eq!(serialize(&results, "image/png"), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let solutions = |accept: &str| Format::negotiate(accept, &[Format::Json, Format::Xml, Format::Csv]);
+        let boolean = |accept: &str| Format::negotiate(accept, &[Format::Json, Format::Xml]);
+        let graph = |accept: &str| Format::negotiate(accept, &[Format::NTriples]);
+        assert_eq!(solutions(""), Some(Format::Json));
+        assert_eq!(solutions("text/csv;q=0.5, application/xml"), Some(Format::Xml));
+        assert_eq!(solutions("text/csv, application/xml;q=0.5"), Some(Format::Csv));
+        // the most specific range wins
+        assert_eq!(solutions("application/json;q=0, */*;q=0.1"), Some(Format::Xml));
+        assert_eq!(solutions("text/*;q=0.2, text/xml;q=0.1"), Some(Format::Csv));
+        assert_eq!(solutions("*/*;q=0"), None);
+        // unsupported formats are skipped
+        assert_eq!(boolean("text/csv, */*"), Some(Format::Json));
+        assert_eq!(boolean("text/csv"), None);
+        assert_eq!(graph("text/csv, */*;q=0.1"), Some(Format::NTriples));
+        assert_eq!(graph("application/n-triples;q=x"), Some(Format::NTriples));
+    }
+}
@@ -0,0 +1,384 @@
+//! A SPARQL endpoint on localhost, serving an `InfGraph` kept in memory
+//! (requires the `server` feature).
+//!
+//! ```text
+//! inferrust-server [--profile PROFILE] [--port PORT] [--threads N] [--timeout SECONDS] FILE...
+//! ```
+//!
+//! The files are parsed as Turtle (N-Triples files included),
+//! then reasoned on with the rule profile named `PROFILE`
+//! (`RDFS`, the default, `RHODF`, `RDFSPLUS` or `RDFSPLUS-CANONICAL`).
+//! As in an RDF merge, the blank nodes of different files are distinct,
+//! even if they have the same identifier.
+//!
+//! Requests are handled concurrently by `N` worker threads (one per CPU by default),
+//! and queries still running after `SECONDS` seconds (30 by default) are interrupted
+//! with a 503 response.
+//!
+//! Routes:
+//! - `/sparql`: the query operation of the SPARQL 1.1 Protocol
+//!   (GET with a `query` parameter, or POST, either URL-encoded or `application/sparql-query`);
+//!   results are serialized in JSON, XML or CSV (graphs in N-Triples),
+//!   according to the media types and quality values of the `Accept` header;
+//! - `/stats`: the size of the graph, and the number of triples of each predicate, in JSON.
+
+mod formats;
+
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::process::exit;
+use std::thread;
+use std::time::Duration;
+
+use inferrust::{InfGraph, ProcessOptions, Query, RuleProfile};
+use sophia_api::term::TTerm;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const USAGE: &str =
+    "usage: inferrust-server [--profile PROFILE] [--port PORT] [--threads N] [--timeout SECONDS] FILE...";
+
+fn main() {
+    let mut profile = RuleProfile::RDFS();
+    let mut port: u16 = 3030;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut timeout = Duration::from_secs(30);
+    let mut files = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => {
+                profile = args
+                    .next()
+                    .and_then(|name| RuleProfile::by_name(&name))
+                    .unwrap_or_else(|| usage())
+            }
+            "--port" => port = args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| usage()),
+            "--threads" => {
+                threads = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--timeout" => {
+                timeout = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .map(Duration::from_secs)
+                    .unwrap_or_else(|| usage())
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        usage();
+    }
+
+    let texts: Vec<String> = files
+        .iter()
+        .map(|file| fs::read_to_string(file).unwrap_or_else(|e| fail(format!("cannot read {}: {}", file, e))))
+        .collect();
+    let sources: Vec<_> = texts.iter().map(|text| sophia::parser::turtle::parse_str(text)).collect();
+    let mut graph =
+        InfGraph::new_unprocessed_parallel(sources).unwrap_or_else(|e| fail(format!("parse error: {}", e)));
+    graph.process(&profile);
+    eprintln!("{} triples with {}, from {} file(s)", graph.size(), profile, files.len());
+
+    let server = Server::http(("127.0.0.1", port)).unwrap_or_else(|e| fail(format!("cannot listen: {}", e)));
+    eprintln!("listening on http://127.0.0.1:{}/sparql with {} thread(s)", port, threads);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for mut request in server.incoming_requests() {
+                    let reply = match HttpRequest::read(&mut request) {
+                        Ok(parsed) => catch_panics(|| handle(&graph, &parsed, timeout)),
+                        Err(e) => Reply::new(400, "text/plain", format!("cannot read the request: {}", e)),
+                    };
+                    if let Err(e) = request.respond(reply.into_response()) {
+                        eprintln!("cannot respond: {}", e);
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2)
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    exit(1)
+}
+
+/// The parts of an HTTP request used by `handle`.
+#[derive(Clone, Debug)]
+struct HttpRequest {
+    method: Method,
+    url: String,
+    accept: String,
+    content_type: String,
+    body: String,
+}
+
+impl HttpRequest {
+    /// Read the headers and the body of `request`.
+    fn read(request: &mut Request) -> io::Result<Self> {
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body)?;
+        Ok(HttpRequest {
+            method: request.method().clone(),
+            url: request.url().to_string(),
+            accept: header(request, "Accept").unwrap_or_default(),
+            content_type: header(request, "Content-Type").unwrap_or_default(),
+            body,
+        })
+    }
+}
+
+/// A response, before it is converted into a `tiny_http::Response`.
+#[derive(Clone, Debug)]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        Reply {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let header = Header::from_bytes(&b"Content-Type"[..], self.content_type.as_bytes()).expect("valid header");
+        Response::from_string(self.body)
+            .with_status_code(self.status)
+            .with_header(header)
+    }
+}
+
+/// The response to `request`, interrupting queries after `timeout`.
+fn handle(graph: &InfGraph, request: &HttpRequest, timeout: Duration) -> Reply {
+    let (path, query_string) = match request.url.find('?') {
+        Some(i) => (&request.url[..i], &request.url[i + 1..]),
+        None => (&request.url[..], ""),
+    };
+    let accept = &request.accept;
+    match (&request.method, path) {
+        (Method::Get, "/sparql") => match param(query_string, "query") {
+            Some(query) => run(graph, &query, accept, timeout),
+            None => Reply::new(400, "text/plain", "missing query parameter".to_string()),
+        },
+        (Method::Post, "/sparql") => {
+            let content_type = &request.content_type;
+            if content_type.starts_with("application/sparql-query") {
+                run(graph, &request.body, accept, timeout)
+            } else if content_type.starts_with("application/x-www-form-urlencoded") {
+                match param(&request.body, "query") {
+                    Some(query) => run(graph, &query, accept, timeout),
+                    None => Reply::new(400, "text/plain", "missing query parameter".to_string()),
+                }
+            } else {
+                Reply::new(415, "text/plain", format!("unsupported content type {}", content_type))
+            }
+        }
+        (Method::Get, "/stats") => Reply::new(200, "application/json", stats(graph)),
+        (_, "/sparql") | (_, "/stats") => Reply::new(405, "text/plain", "method not allowed".to_string()),
+        _ => Reply::new(404, "text/plain", "not found".to_string()),
+    }
+}
+
+/// The reply built by `respond`, or a 500 response if it panics,
+/// so that the worker thread survives.
+fn catch_panics<F: FnOnce() -> Reply>(respond: F) -> Reply {
+    // handlers only read the graph, so a panic can not leave it inconsistent
+    match panic::catch_unwind(AssertUnwindSafe(respond)) {
+        Ok(reply) => reply,
+        Err(_) => Reply::new(500, "text/plain", "internal error while handling the request".to_string()),
+    }
+}
+
+/// Evaluate `query`, unless it runs longer than `timeout`,
+/// and serialize its results in a format of `accept`.
+fn run(graph: &InfGraph, query: &str, accept: &str, timeout: Duration) -> Reply {
+    let query = match Query::parse(query) {
+        Ok(query) => query,
+        Err(e) => return Reply::new(400, "text/plain", e.to_string()),
+    };
+    let results = match graph.execute_with(&query, &ProcessOptions::new().with_timeout(timeout)) {
+        Ok(results) => results,
+        Err(_) => {
+            let message = format!("the query did not finish in {} s", timeout.as_secs_f64());
+            return Reply::new(503, "text/plain", message);
+        }
+    };
+    match formats::serialize(&results, accept) {
+        Some((content_type, body)) => Reply::new(200, content_type, body),
+        None => {
+            let message = format!("no supported format for these results in {}", accept);
+            Reply::new(406, "text/plain", message)
+        }
+    }
+}
+
+/// The size of `graph`, and the number of triples of each predicate, in JSON.
+fn stats(graph: &InfGraph) -> String {
+    let mut predicates: Vec<(String, usize)> = graph
+        .predicate_sizes()
+        .into_iter()
+        .map(|(p, size)| (p.value().to_string(), size))
+        .collect();
+    predicates.sort();
+    let predicates: Vec<String> = predicates
+        .into_iter()
+        .map(|(p, size)| format!("{}:{}", formats::json_string(&p), size))
+        .collect();
+    format!("{{\"size\":{},\"predicates\":{{{}}}}}\n", graph.size(), predicates.join(","))
+}
+
+/// The value of the header `name` of `request`.
+fn header(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_string())
+}
+
+/// The decoded value of the parameter `name` in the URL-encoded `params`.
+fn param(params: &str, name: &str) -> Option<String> {
+    params.split('&').find_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        if url_decode(parts.next()?) == name {
+            Some(url_decode(parts.next().unwrap_or("")))
+        } else {
+            None
+        }
+    })
+}
+
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INPUT: &str = r#"
+        @prefix : <http://example.org/> .
+        @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+        :bart a :Boy .
+        :Boy rdfs:subClassOf :Person .
+    "#;
+
+    fn request(method: Method, url: &str, accept: &str, content_type: &str, body: &str) -> HttpRequest {
+        HttpRequest {
+            method,
+            url: url.to_string(),
+            accept: accept.to_string(),
+            content_type: content_type.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_handle() {
+        let graph = InfGraph::new_rdfs(sophia::parser::turtle::parse_str(INPUT)).unwrap();
+        let timeout = Duration::from_secs(3600);
+        let get = |url: &str, accept: &str| handle(&graph, &request(Method::Get, url, accept, "", ""), timeout);
+        let post = |query: &str, accept: &str| {
+            let post = request(Method::Post, "/sparql", accept, "application/sparql-query", query);
+            handle(&graph, &post, timeout)
+        };
+
+        let ask = "PREFIX : <http://example.org/> ASK { :bart a :Person }";
+        let reply = post(ask, "text/csv, */*;q=0.5");
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.content_type, "application/sparql-results+json");
+        assert_eq!(reply.body, "{\"head\":{},\"boolean\":true}\n");
+        assert_eq!(post(ask, "text/csv").status, 406);
+
+        let reply = post("SELECT ?c { ?x a ?c }", "text/csv;q=1, application/json;q=0.5");
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.content_type, "text/csv; charset=utf-8");
+        assert!(reply.body.starts_with("c\r\n"));
+
+        let reply = post("CONSTRUCT WHERE { ?x a ?c }", "text/csv, */*");
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.content_type, "application/n-triples");
+        let person = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <http://example.org/Person> .";
+        assert!(reply.body.contains(&format!("<http://example.org/bart> {}", person)));
+
+        assert_eq!(get("/sparql?query=ASK+%7B%7D", "").status, 200);
+        let form = request(Method::Post, "/sparql", "", "application/x-www-form-urlencoded", "query=ASK+%7B%7D");
+        assert_eq!(handle(&graph, &form, timeout).status, 200);
+        let text = request(Method::Post, "/sparql", "", "text/plain", "ASK {}");
+        assert_eq!(handle(&graph, &text, timeout).status, 415);
+        assert_eq!(get("/sparql", "").status, 400);
+        assert_eq!(get("/sparql?query=SELECT", "").status, 400);
+        let put = request(Method::Put, "/sparql", "", "", "");
+        assert_eq!(handle(&graph, &put, timeout).status, 405);
+        assert_eq!(get("/other", "").status, 404);
+
+        // the deadline has passed before the query starts
+        let reply = handle(&graph, &request(Method::Get, "/sparql?query=ASK+%7B%7D", "", "", ""), Duration::ZERO);
+        assert_eq!(reply.status, 503);
+    }
+
+    #[test]
+    fn test_stats() {
+        let graph = InfGraph::new_rdfs(sophia::parser::turtle::parse_str(INPUT)).unwrap();
+        let timeout = Duration::from_secs(3600);
+        let reply = handle(&graph, &request(Method::Get, "/stats", "", "", ""), timeout);
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.content_type, "application/json");
+        let size = format!("{{\"size\":{},\"predicates\":{{", graph.size());
+        assert!(reply.body.starts_with(&size));
+        assert!(reply.body.contains("\"http://www.w3.org/2000/01/rdf-schema#subClassOf\":"));
+        let post = request(Method::Post, "/stats", "", "", "");
+        assert_eq!(handle(&graph, &post, timeout).status, 405);
+        assert_eq!(catch_panics(|| handle(&graph, &post, timeout)).status, 405);
+        assert_eq!(catch_panics(|| panic!("bug in a handler")).status, 500);
+    }
+
+    #[test]
+    fn test_param() {
+        let params = "default-graph-uri=&query=SELECT+*+%7B+%3Fs+%3Fp+%3Fo+%7D&x=%";
+        assert_eq!(param(params, "query").unwrap(), "SELECT * { ?s ?p ?o }");
+        assert_eq!(param(params, "default-graph-uri").unwrap(), "");
+        assert_eq!(param(params, "x").unwrap(), "%");
+        assert_eq!(param(params, "y"), None);
+    }
+}
@@ -1,6 +1,5 @@
-//! Structures to compute transitive closure (using uutila's algorithm)
+//! Structures to compute transitive closure
+//! (using an iterative version of Tarjan's algorithm for strongly connected components)
 
 mod cl_graph;
 pub use self::cl_graph::*;
-mod utils;
-pub use self::utils::*;
@@ -1,188 +1,411 @@
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-use std::rc::Rc;
-
-use crate::closure::utils;
-
-#[derive(Clone)]
-pub struct Node {
-    pub id: u64,
-    dfs_num: RefCell<usize>,
-    in_comp: RefCell<bool>,
-    root: RefCell<Option<u64>>,
-    tc: RefCell<HashSet<u64>>,
+use std::cmp::min;
+
+use crate::utils::pair_groups;
+
+/// A directed graph, built from a sorted list of pairs (edges),
+/// whose transitive closure can be computed.
+///
+/// Nodes are renumbered with dense local identifiers (u32),
+/// and edges are stored in compressed sparse row format.
+pub struct ClosureGraph {
+    /// the original identifier of each node, sorted
+    ids: Vec<u64>,
+    /// the edges of node `i` are `targets[offsets[i]..offsets[i+1]]`
+    offsets: Vec<usize>,
+    targets: Vec<u32>,
+}
+
+/// The transitive closure of a `ClosureGraph`.
+///
+/// Nodes in the same strongly connected component share the same closure,
+/// so it is stored once per component, as a sorted list of local identifiers.
+pub struct Closure {
+    /// the original identifier of each node, sorted
+    ids: Vec<u64>,
+    /// the component of each node
+    comp: Vec<u32>,
+    /// the nodes reachable from each component
+    reach: Vec<Vec<u32>>,
+    /// the members of every component with more than one node
+    cycles: Vec<Vec<u32>>,
 }
 
-impl Node {
-    pub fn new(id: u64) -> Self {
+const UNVISITED: u32 = u32::MAX;
+
+/// The number of components (or groups of new pairs) processed
+/// between two calls to the `interrupted` callback of `close_until` and `extend_closure`.
+const CHECK_STEP: usize = 1024;
+
+impl ClosureGraph {
+    /// Build a graph from the given edges.
+    ///
+    /// # Pre-condition
+    /// `pairs` is sorted using the lexicographic order on pairs.
+    pub fn from(pairs: &[[u64; 2]]) -> Self {
+        let mut ids: Vec<u64> = pairs.iter().flat_map(|p| p.iter().cloned()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let local = |id: u64| ids.binary_search(&id).unwrap() as u32;
+        let mut offsets = vec![0; ids.len() + 1];
+        let mut targets = Vec::with_capacity(pairs.len());
+        for [s, o] in pairs {
+            offsets[local(*s) as usize + 1] += 1;
+            targets.push(local(*o));
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
         Self {
-            id,
-            dfs_num: RefCell::new(usize::max_value()),
-            in_comp: RefCell::new(false),
-            root: RefCell::new(None),
-            tc: RefCell::new(HashSet::new()),
+            ids,
+            offsets,
+            targets,
         }
     }
 
-    #[inline]
-
-    pub fn set_num(&self, num: usize) {
-        *self.dfs_num.borrow_mut() = num;
+    /// The number of nodes in this graph.
+    pub fn len(&self) -> usize {
+        self.ids.len()
     }
 
-    #[inline]
-
-    pub fn num(&self) -> usize {
-        *self.dfs_num.borrow()
+    /// Whether this graph has no node.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
     }
 
     #[inline]
-
-    pub fn in_comp(&self) -> bool {
-        *self.in_comp.borrow()
+    fn edges(&self, v: u32) -> &[u32] {
+        &self.targets[self.offsets[v as usize]..self.offsets[v as usize + 1]]
     }
 
-    #[inline]
-
-    pub fn set_in_comp(&self, in_c: bool) {
-        *self.in_comp.borrow_mut() = in_c;
+    /// Compute the strongly connected components of this graph,
+    /// using an iterative version of Tarjan's algorithm.
+    ///
+    /// Return the component of each node, and the members of each component.
+    /// Components are numbered in reverse topological order,
+    /// i.e. the successors of a component have smaller numbers.
+    pub fn components(&self) -> (Vec<u32>, Vec<Vec<u32>>) {
+        let n = self.len();
+        let mut index = vec![UNVISITED; n];
+        let mut lowlink = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = vec![];
+        let mut comp = vec![UNVISITED; n];
+        let mut members = vec![];
+        let mut counter = 0;
+        // simulated call stack: (node, position of the next edge to visit)
+        let mut calls: Vec<(u32, usize)> = vec![];
+
+        for root in 0..n as u32 {
+            if index[root as usize] != UNVISITED {
+                continue;
+            }
+            index[root as usize] = counter;
+            lowlink[root as usize] = counter;
+            counter += 1;
+            stack.push(root);
+            on_stack[root as usize] = true;
+            calls.push((root, self.offsets[root as usize]));
+
+            while let Some(&(v, e)) = calls.last() {
+                let vi = v as usize;
+                if e < self.offsets[vi + 1] {
+                    calls.last_mut().unwrap().1 += 1;
+                    let w = self.targets[e];
+                    let wi = w as usize;
+                    if index[wi] == UNVISITED {
+                        index[wi] = counter;
+                        lowlink[wi] = counter;
+                        counter += 1;
+                        stack.push(w);
+                        on_stack[wi] = true;
+                        calls.push((w, self.offsets[wi]));
+                    } else if on_stack[wi] {
+                        lowlink[vi] = min(lowlink[vi], index[wi]);
+                    }
+                } else {
+                    calls.pop();
+                    if let Some(&(u, _)) = calls.last() {
+                        lowlink[u as usize] = min(lowlink[u as usize], lowlink[vi]);
+                    }
+                    if lowlink[vi] == index[vi] {
+                        let c = members.len() as u32;
+                        let mut comp_members = vec![];
+                        loop {
+                            let w = stack.pop().unwrap();
+                            on_stack[w as usize] = false;
+                            comp[w as usize] = c;
+                            comp_members.push(w);
+                            if w == v {
+                                break;
+                            }
+                        }
+                        members.push(comp_members);
+                    }
+                }
+            }
+        }
+        (comp, members)
     }
 
-    #[inline]
-
-    pub fn root(&self) -> u64 {
-        self.root.borrow().unwrap()
+    /// The strongly connected components of this graph with more than one node,
+    /// as sorted lists of the original identifiers.
+    pub fn cycles(&self) -> Vec<Vec<u64>> {
+        let (_, members) = self.components();
+        let cycles: Vec<_> = members.into_iter().filter(|m| m.len() > 1).collect();
+        to_ids(&self.ids, &cycles)
     }
 
-    #[inline]
-
-    pub fn set_root(&self, r: u64) {
-        *self.root.borrow_mut() = Some(r);
+    /// Compute the transitive closure of this graph.
+    pub fn close(&self) -> Closure {
+        self.close_until(|| false)
+            .expect("closing can not be interrupted without a callback")
     }
 
-    #[inline]
-
-    pub fn tc_contains(&self, val: u64) -> bool {
-        self.tc.borrow().contains(&val)
+    /// Compute the transitive closure of this graph,
+    /// unless `interrupted` (which is called regularly) returns true,
+    /// in which case `None` is returned.
+    pub fn close_until<I>(&self, interrupted: I) -> Option<Closure>
+    where
+        I: Fn() -> bool,
+    {
+        if interrupted() {
+            return None;
+        }
+        let (comp, members) = self.components();
+        let mut reach: Vec<Vec<u32>> = Vec::with_capacity(members.len());
+        // marks[v] == c iff v has already been added to reach[c]
+        let mut marks = vec![UNVISITED; self.len()];
+        for (c, comp_members) in members.iter().enumerate() {
+            if c % CHECK_STEP == CHECK_STEP - 1 && interrupted() {
+                return None;
+            }
+            let c = c as u32;
+            let mut r = vec![];
+            let mut cyclic = comp_members.len() > 1;
+            for v in comp_members {
+                for w in self.edges(*v) {
+                    let cw = comp[*w as usize];
+                    if cw == c {
+                        // NB: this also catches self-loops
+                        cyclic = true;
+                        continue;
+                    }
+                    if marks[*w as usize] != c {
+                        marks[*w as usize] = c;
+                        r.push(*w);
+                    }
+                    // cw < c, so reach[cw] is already computed
+                    for x in &reach[cw as usize] {
+                        if marks[*x as usize] != c {
+                            marks[*x as usize] = c;
+                            r.push(*x);
+                        }
+                    }
+                }
+            }
+            if cyclic {
+                r.extend(comp_members.iter().cloned());
+            }
+            r.sort_unstable();
+            r.shrink_to_fit();
+            reach.push(r);
+        }
+        Some(Closure {
+            ids: self.ids.clone(),
+            comp,
+            reach,
+            cycles: members.into_iter().filter(|m| m.len() > 1).collect(),
+        })
     }
+}
 
-    #[inline]
-
-    pub fn tc_insert(&self, val: u64) {
-        self.tc.borrow_mut().insert(val);
+impl Closure {
+    /// The number of pairs in this closure.
+    pub fn len(&self) -> usize {
+        self.comp.iter().map(|c| self.reach[*c as usize].len()).sum()
     }
 
-    #[inline]
-
-    pub fn tc_extend<I: IntoIterator<Item = u64>>(&self, vals: I) {
-        self.tc.borrow_mut().extend(vals);
+    /// Whether this closure has no pair.
+    pub fn is_empty(&self) -> bool {
+        self.reach.iter().all(|r| r.is_empty())
     }
 
-    #[inline]
-
-    pub fn tc_iter(&self) -> Vec<u64> {
-        self.tc.borrow().iter().cloned().collect::<Vec<u64>>()
+    /// The strongly connected components of the closed graph with more than one node,
+    /// as sorted lists of the original identifiers.
+    pub fn cycles(&self) -> Vec<Vec<u64>> {
+        to_ids(&self.ids, &self.cycles)
     }
 
-    #[inline]
-
-    pub fn tc_clear(&self) {
-        self.tc.borrow_mut().clear();
+    /// The original identifiers of the nodes reachable from the node `id`,
+    /// sorted (none if `id` is not a node of the closed graph).
+    pub fn reachable(&self, id: u64) -> impl Iterator<Item = u64> + '_ {
+        let reach: &[u32] = match self.ids.binary_search(&id) {
+            Ok(v) => &self.reach[self.comp[v] as usize],
+            Err(_) => &[],
+        };
+        reach.iter().map(move |w| self.ids[*w as usize])
     }
 
-    #[inline]
-
-    pub fn tc_is_empty(&self) -> bool {
-        self.tc.borrow().is_empty()
+    /// All the pairs of this closure,
+    /// sorted using the lexicographic order on pairs, and without duplicates.
+    pub fn pairs(&self) -> Vec<[u64; 2]> {
+        let mut pairs = Vec::with_capacity(self.len());
+        for (v, id) in self.ids.iter().enumerate() {
+            for w in &self.reach[self.comp[v] as usize] {
+                pairs.push([*id, self.ids[*w as usize]]);
+            }
+        }
+        pairs
     }
 }
 
-pub struct ClosureGraph {
-    pub edges: Vec<[u64; 2]>,
-    pub nodes: Vec<Option<Node>>,
-    pub offset: u64,
-}
-
-impl ClosureGraph {
-    pub fn from(pairs: Vec<[u64; 2]>) -> Self {
-        let mut offset = pairs[0][0];
-        let maxv = pairs[pairs.len() - 1][0];
-        let values: Vec<u64> = pairs.iter().flat_map(|p| p.iter().cloned()).collect();
-        // NB: this is just a heuristics, some nodes may be objects only,
-        // so their identifier may be outside those bounds
-        let mut nodes = vec![None; (maxv - offset + 1) as usize];
-        for value in values {
-            if value < offset {
-                let shift = offset - value;
-                offset -= shift;
-                for _ in 0..shift {
-                    nodes.insert(0, None);
-                }
-            }
-            let idx = (value - offset) as usize;
-            if nodes.len() <= idx {
-                nodes.resize_with(idx + 1, Default::default);
+/// The pairs to add to a transitively closed relation R, once the pairs `new` are added to it,
+/// for it to be closed again,
+/// i.e. the pairs (x, y) such that a path from x to y goes through a pair of `new`.
+///
+/// R is only read through `successors` and `predecessors`,
+/// which return the successors (resp. predecessors) of a node in R ∪ `new`,
+/// so only the neighbourhood of the new pairs is visited.
+/// The result is sorted, without duplicates, but may contain pairs of R ∪ `new`.
+/// Return `None` if `interrupted` (which is called regularly) returns true.
+///
+/// # Pre-condition
+/// `new` is sorted using the lexicographic order on pairs.
+pub fn extend_closure<S, IS, P, IP, I>(
+    new: &[[u64; 2]],
+    successors: S,
+    predecessors: P,
+    interrupted: I,
+) -> Option<Vec<[u64; 2]>>
+where
+    S: Fn(u64) -> IS,
+    IS: IntoIterator<Item = u64>,
+    P: Fn(u64) -> IP,
+    IP: IntoIterator<Item = u64>,
+    I: Fn() -> bool,
+{
+    // Such a path is x R? a, a new b, then a path from b to y.
+    // Since R is closed, the latter is a path between the ends of new pairs,
+    // where each step is a single pair of R ∪ new, followed by y R? z.
+    let mut ends: Vec<u64> = new.iter().flat_map(|p| p.iter().cloned()).collect();
+    ends.sort_unstable();
+    ends.dedup();
+    let mut edges = vec![];
+    for x in &ends {
+        edges.extend(
+            successors(*x)
+                .into_iter()
+                .filter(|y| ends.binary_search(y).is_ok())
+                .map(|y| [*x, y]),
+        );
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    let between = ClosureGraph::from(&edges).close_until(&interrupted)?;
+
+    let mut pairs = vec![];
+    for (i, group) in pair_groups(new).enumerate() {
+        if i % CHECK_STEP == CHECK_STEP - 1 && interrupted() {
+            return None;
+        }
+        let a = group[0][0];
+        let mut targets = vec![];
+        for [_, b] in group {
+            let via: Vec<u64> = std::iter::once(*b).chain(between.reachable(*b)).collect();
+            for k in via {
+                targets.push(k);
+                targets.extend(successors(k));
             }
-            nodes[idx] = Some(Node::new(value));
         }
-        Self {
-            edges: pairs,
-            nodes,
-            offset,
+        targets.sort_unstable();
+        targets.dedup();
+        for x in std::iter::once(a).chain(predecessors(a)) {
+            pairs.extend(targets.iter().map(|y| [x, *y]));
         }
     }
+    pairs.sort_unstable();
+    pairs.dedup();
+    Some(pairs)
+}
 
-    /*
-
-    fn create_nodes(pairs: &Vec<[u64; 2]>) -> HashMap<u64, Node> {
-        let values: HashSet<u64> = pairs.iter().flat_map(|p| p.iter().cloned()).collect();
-        let mut map = HashMap::new();
-        for value in values {
-            map.insert(value, Node::new(value));
-        }
-        map
-    }
-    */
+/// Convert components of local identifiers into sorted lists of original identifiers.
+fn to_ids(ids: &[u64], components: &[Vec<u32>]) -> Vec<Vec<u64>> {
+    components
+        .iter()
+        .map(|component| {
+            let mut c: Vec<u64> = component.iter().map(|v| ids[*v as usize]).collect();
+            c.sort_unstable();
+            c
+        })
+        .collect()
+}
 
-    pub fn node(&self, id: u64) -> &Node {
-        let idx = (id - self.offset) as usize;
-        /*
-            if idx >= self.nodes.borrow().len() {
-                self.nodes.borrow_mut().resize(idx + 1, None);
-            }
-            self.nodes.borrow().get(idx).unwrap().as_ref().unwrap()
-        */
-        self.nodes.get(idx).unwrap().as_ref().unwrap()
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_close() {
+        let pairs = [[1, 2], [2, 3], [3, 2], [3, 4], [5, 5], [6, 1]];
+        let closure = ClosureGraph::from(&pairs).close();
+        let expected: Vec<[u64; 2]> = vec![
+            [1, 2], [1, 3], [1, 4],
+            [2, 2], [2, 3], [2, 4],
+            [3, 2], [3, 3], [3, 4],
+            [5, 5],
+            [6, 1], [6, 2], [6, 3], [6, 4],
+        ];
+        assert_eq!(closure.len(), expected.len());
+        assert_eq!(closure.pairs(), expected);
+        let cycles: Vec<Vec<u64>> = vec![vec![2, 3]];
+        assert_eq!(closure.cycles(), cycles);
+        assert_eq!(ClosureGraph::from(&pairs).cycles(), cycles);
+        assert!(ClosureGraph::from(&pairs).close_until(|| true).is_none());
     }
 
-    pub fn iter_nodes(&self) -> impl Iterator<Item = &Node> {
-        self.nodes.iter().filter_map(|opt| opt.as_ref())
+    #[test]
+    fn test_close_diamond() {
+        let pairs = [[1, 2], [1, 3], [2, 4], [3, 4]];
+        let closure = ClosureGraph::from(&pairs).close();
+        let expected: Vec<[u64; 2]> = vec![[1, 2], [1, 3], [1, 4], [2, 4], [3, 4]];
+        assert_eq!(closure.pairs(), expected);
     }
 
-    pub fn edges(&self, id: u64) -> Vec<u64> {
-        let start_index = crate::utils::first_pair(&self.edges, id);
-        self.edges[start_index..]
-            .iter()
-            .take_while(|e| e[0] == id)
-            .map(|e| e[1])
-            .collect()
+    #[test]
+    fn test_extend_closure() {
+        let closed = ClosureGraph::from(&[[1, 2], [3, 4], [5, 6], [7, 8]]).close().pairs();
+        // the new pairs link the chains 1-2, 3-4, 5-6 and 7-8, with a cycle 4-5-4
+        let new = [[2, 3], [4, 5], [6, 4]];
+        let mut all = closed.clone();
+        all.extend_from_slice(&new);
+        all.sort_unstable();
+        let successors = |x: u64| all.iter().filter(move |p| p[0] == x).map(|p| p[1]).collect::<Vec<_>>();
+        let predecessors = |x: u64| all.iter().filter(move |p| p[1] == x).map(|p| p[0]).collect::<Vec<_>>();
+        let extension = extend_closure(&new, successors, predecessors, || false).unwrap();
+
+        let mut extended = closed.clone();
+        extended.extend(extension);
+        extended.sort_unstable();
+        extended.dedup();
+        assert_eq!(extended, ClosureGraph::from(&all).close().pairs());
+        assert_eq!(extend_closure(&[], |_| vec![], |_| vec![], || false), Some(vec![]));
+        assert_eq!(extend_closure(&new, |_| vec![], |_| vec![], || true), None);
     }
 
-    pub fn close(&mut self) -> HashMap<u64, Rc<Vec<u64>>> {
-        utils::graph_tc(self);
-
-        let mut tc = HashMap::new();
-        for v in self.iter_nodes() {
-            if v.root() == v.id {
-                tc.insert(v.id, Rc::new(v.tc_iter()));
-            }
-        }
-        for v in self.iter_nodes() {
-            if v.root() != v.id {
-                debug_assert!(v.tc_is_empty());
-                tc.insert(v.id, Rc::clone(&tc[&v.root()]));
-            }
-        }
-        tc
+    #[test]
+    fn test_deep_components() {
+        // a recursive implementation would overflow the stack here
+        let n = 1_000_000;
+        let mut pairs: Vec<[u64; 2]> = (0..n).map(|i| [i, i + 1]).collect();
+        pairs.push([n, 0]);
+        pairs.sort();
+        let graph = ClosureGraph::from(&pairs);
+        let (comp, members) = graph.components();
+        assert_eq!(members.len(), 1);
+        assert!(comp.iter().all(|c| *c == 0));
+
+        let pairs: Vec<[u64; 2]> = (0..n).map(|i| [i, i + 1]).collect();
+        let (_, members) = ClosureGraph::from(&pairs).components();
+        assert_eq!(members.len(), n as usize + 1);
     }
 }
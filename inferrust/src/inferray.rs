@@ -3,11 +3,23 @@
 mod chunk;
 pub(crate) use self::chunk::*;
 
+mod compressed;
+
 mod dictionary;
 pub(crate) use self::dictionary::*;
 
+mod same_as;
+
+mod snapshot;
+
+#[cfg(feature = "mmap")]
+mod mapped;
+
 mod graph;
 pub use self::graph::*;
 
+mod query;
+pub use self::query::*;
+
 mod store;
 pub(crate) use self::store::*;
@@ -7,18 +7,38 @@
 //! - `os` is ordered by object, then subject
 //!
 //! Note that `os` is generated lazily when required.
+//!
+//! Both lists are usually stored on the heap,
+//! but they can also be backed by memory-mapped files (feature `mmap`),
+//! or compressed (see `Chunk::compress`).
+//! Mapped and compressed lists are read-only:
+//! they are copied to the heap as soon as the chunk is modified.
+//!
+//! Compressed lists are never decompressed as a whole for good:
+//! they can be scanned (`iter_so`, `so_with_s`, `os_with_o`) and queried (`contains`) in place,
+//! and the join engine reads them through a `PairList`, decoding them part by part.
+//! Slice access (`so`, `os`) returns a temporary decompressed copy.
 //! 
 /// See [module documentation](./index.html).
 
-use crate::utils::{bucket_sort_pairs, merge_sort};
-use once_cell::sync::OnceCell;
+use crate::utils::{radix_sort_pairs, merge_sort};
+
+use super::compressed::CompressedPairs;
+
+#[cfg(feature = "mmap")]
+use super::mapped::MappedPairs;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::OnceLock;
+#[cfg(feature = "mmap")]
+use std::sync::Arc;
 
 #[derive(PartialEq, Debug, Clone)]
 pub(crate) struct Chunk {
     /// subject-object list
-    so: Vec<[u64; 2]>,
+    so: Pairs,
     /// object-subject list (built lazily)
-    os: OnceCell<Vec<[u64; 2]>>,
+    os: OnceLock<Pairs>,
 }
 
 impl Chunk {
@@ -31,8 +51,60 @@ impl Chunk {
     /// See also implementation of `From<&[[u64; 2]]>
     pub fn empty() -> Chunk {
         Chunk {
-            so: vec![],
-            os: OnceCell::new(),
+            so: Pairs::Heap(vec![]),
+            os: OnceLock::new(),
+        }
+    }
+
+    /// Create a `Chunk` from a list of (subject-object) pairs
+    /// that is already sorted and without duplicates.
+    ///
+    /// Return `None` if `so` does not satisfy this pre-condition.
+    pub fn from_sorted(so: Vec<[u64; 2]>) -> Option<Chunk> {
+        if so.windows(2).any(|w| w[0] >= w[1]) {
+            return None;
+        }
+        Some(Chunk {
+            so: Pairs::Heap(so),
+            os: OnceLock::new(),
+        })
+    }
+
+    /// Build a `Chunk` from a list of pairs, in any order and possibly with duplicates.
+    pub fn from_unsorted(mut so: Vec<[u64; 2]>) -> Chunk {
+        radix_sort_pairs(&mut so);
+        Chunk {
+            so: Pairs::Heap(so),
+            os: OnceLock::new(),
+        }
+    }
+
+    /// Create a `Chunk` backed by memory-mapped pair lists.
+    ///
+    /// `so` must be sorted and without duplicates,
+    /// and `os` must contain the same pairs reversed, and sorted.
+    /// Return `None` if either list is not sorted, or if their sizes differ
+    /// (that `os` contains exactly the pairs of `so` is not checked).
+    ///
+    /// NB: checking the order reads both lists once.
+    #[cfg(feature = "mmap")]
+    pub fn from_mapped(so: MappedPairs, os: MappedPairs) -> Option<Chunk> {
+        let sorted = |pairs: &[[u64; 2]]| pairs.windows(2).all(|w| w[0] < w[1]);
+        if so.pairs().len() != os.pairs().len() || !sorted(so.pairs()) || !sorted(os.pairs()) {
+            return None;
+        }
+        Some(Self::from_mapped_unchecked(so, os))
+    }
+
+    /// Like `from_mapped`, but the order of `so` and `os` is trusted,
+    /// so that the mapped files are not read.
+    ///
+    /// If either list is not sorted, this chunk may miss some of its triples.
+    #[cfg(feature = "mmap")]
+    pub fn from_mapped_unchecked(so: MappedPairs, os: MappedPairs) -> Chunk {
+        Chunk {
+            so: Pairs::Mapped(Arc::new(so)),
+            os: OnceLock::from(Pairs::Mapped(Arc::new(os))),
         }
     }
 
@@ -46,9 +118,61 @@ impl Chunk {
         self.so.is_empty()
     }
 
+    /// Iterate over the (subject-object) pairs.
+    pub fn iter_so(&self) -> Box<dyn Iterator<Item = [u64; 2]> + '_> {
+        self.so.iter_from(0)
+    }
+
+    /// Iterate over the (subject-object) pairs whose subject is `s`.
+    pub fn so_with_s(&self, s: u64) -> impl Iterator<Item = [u64; 2]> + '_ {
+        self.so.iter_from(s).take_while(move |[is, _]| *is == s)
+    }
+
+    /// Iterate over the (object-subject) pairs whose object is `o`.
+    ///
+    /// # Performance
+    /// See `os`.
+    pub fn os_with_o(&self, o: u64) -> impl Iterator<Item = [u64; 2]> + '_ {
+        self.os_pairs().iter_from(o).take_while(move |[io, _]| *io == o)
+    }
+
+    /// Whether this chunk contains the given (subject-object) pair.
+    pub fn contains(&self, pair: &[u64; 2]) -> bool {
+        self.so.contains(pair)
+    }
+
+    /// Compress the pairs of this chunk (see `CompressedPairs`).
+    ///
+    /// This has no effect on the content of the chunk,
+    /// only on its memory footprint.
+    pub fn compress(&mut self) {
+        if self.so.is_compressed() || self.so.is_empty() {
+            return;
+        }
+        let os = Pairs::Compressed(CompressedPairs::new(&self.os()));
+        let so = Pairs::Compressed(CompressedPairs::new(&self.so()));
+        self.so = so;
+        self.os = OnceLock::from(os);
+    }
+
+    /// Whether the pairs of this chunk are compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.so.is_compressed()
+    }
+
+    /// The number of bytes used by the pairs of this chunk on the heap
+    /// (memory-mapped pairs use none).
+    pub fn heap_size(&self) -> usize {
+        self.so.heap_size() + self.os.get().map_or(0, Pairs::heap_size)
+    }
+
     /// The list of (subject-object) pairs.
-    pub fn so(&self) -> &[[u64; 2]] {
-        &self.so
+    ///
+    /// # Performance
+    /// If this chunk is compressed, this decompresses a copy of the pairs;
+    /// use `so_list` to read them in place.
+    pub fn so(&self) -> Cow<[[u64; 2]]> {
+        self.so.list().decode()
     }
 
     /// The list of (object-subject) pairs.
@@ -57,12 +181,31 @@ impl Chunk {
     /// This list is generated lazily,
     /// so the first call to this method after building
     /// (or modifying) a `Chunk` can be costly.
-    pub fn os(&self) -> &[[u64; 2]] {
-        &self.os.get_or_init(|| {
-            let mut v = self.so.clone();
+    /// If this chunk is compressed, this decompresses a copy of the pairs;
+    /// use `os_list` to read them in place.
+    pub fn os(&self) -> Cow<[[u64; 2]]> {
+        self.os_pairs().list().decode()
+    }
+
+    /// The list of (subject-object) pairs, read in place.
+    pub fn so_list(&self) -> PairList {
+        self.so.list()
+    }
+
+    /// The list of (object-subject) pairs, read in place.
+    ///
+    /// # Performance
+    /// See `os`.
+    pub fn os_list(&self) -> PairList {
+        self.os_pairs().list()
+    }
+
+    fn os_pairs(&self) -> &Pairs {
+        self.os.get_or_init(|| {
+            let mut v = self.so().into_owned();
             reverse_pairs(&mut v);
-            bucket_sort_pairs(&mut v);
-            v
+            radix_sort_pairs(&mut v);
+            Pairs::Heap(v)
         })
     }
 
@@ -71,44 +214,63 @@ impl Chunk {
         if pairs.is_empty() {
             return;
         }
-        self.so.extend_from_slice(pairs);
-        bucket_sort_pairs(&mut self.so);
+        let so = self.so.to_mut();
+        so.extend_from_slice(pairs);
+        radix_sort_pairs(so);
         // invalidate outdated lazy object-subject list
-        self.os = OnceCell::new();
+        self.os = OnceLock::new();
     }
 
     /// Merge `other` into this `Chunk`,
     /// ensuring that it remains sorted after the operation.
+    ///
+    /// Mapped and compressed pairs are kept in place if `other` brings no new pair.
     pub fn merge(&mut self, other: Chunk) {
-        let old_so = std::mem::replace(&mut self.so, vec![]);
-        self.so = merge_sort(old_so, other.so);
+        if other.is_empty() {
+            return;
+        }
+        if !self.so.is_heap() && other.iter_so().all(|pair| self.contains(&pair)) {
+            return;
+        }
+        let old_so = std::mem::replace(&mut self.so, Pairs::Heap(vec![]));
+        self.so = Pairs::Heap(merge_sort(old_so.into_vec(), other.so.into_vec()));
         // invalidate outdated lazy object-subject list
-        self.os = OnceCell::new();
+        self.os = OnceLock::new();
     }
 
     #[cfg(debug_assertions)]
     /// For tests only; check that this chunk is sorted.
     pub fn is_sorted(&self) -> bool {
-        for i in 1..self.so.len() {
-            if self.so[i-1] > self.so[i] {
-                return false;
+        self.so().windows(2).all(|w| w[0] <= w[1])
+    }
+
+    /// Replace every value `v` in this chunk by `f(v)`,
+    /// and re-sort the underlying data if needed.
+    pub(super) fn map_values<F>(&mut self, f: F)
+    where
+        F: Fn(u64) -> u64,
+    {
+        if self.so.list().iter().all(|[s, o]| f(s) == s && f(o) == o) {
+            return;
+        }
+        let so = self.so.to_mut();
+        for pair in so.iter_mut() {
+            for val in pair.iter_mut() {
+                *val = f(*val);
             }
         }
-        true
+        radix_sort_pairs(so);
+        self.os = OnceLock::new();
     }
 
-    /// Update this chunk with the given translation map/
+    /// Update this chunk with the given translation map.
     ///
     /// This is used when resources (index > START_INDEX)
     /// have been requalified as properties (index < START_INDEX).
-    ///
-    /// IMPORTANT: this method is not used yet,
-    /// but will be required later when rules such as EQ-REP-P
-    /// are correctly implemented.
-    #[allow(dead_code)]
     pub(super) fn remap(&mut self, map: &[[u64; 2]]) {
         let mut dirty = false;
-        for pair in self.so.iter_mut() {
+        let so = self.so.to_mut();
+        for pair in so.iter_mut() {
             for val in pair.iter_mut() {
                 for [old, new] in map {
                     if *val == *old {
@@ -120,17 +282,15 @@ impl Chunk {
             }
         }
         if dirty {
-            bucket_sort_pairs(&mut self.so);
-            self.os = OnceCell::new();
+            radix_sort_pairs(so);
+            self.os = OnceLock::new();
         }
     }
 }
 
 impl From<&[[u64; 2]]> for Chunk {
     fn from(other: &[[u64; 2]]) -> Chunk {
-        let mut ret = Chunk::empty();
-        ret.add_pairs(other);
-        ret
+        Chunk::from_unsorted(other.to_vec())
     }
 }
 
@@ -140,3 +300,231 @@ fn reverse_pairs(pairs: &mut [[u64; 2]]) {
         pair.swap(0, 1);
     }
 }
+
+/// The storage of a sorted list of pairs.
+#[derive(Clone)]
+enum Pairs {
+    /// pairs stored on the heap
+    Heap(Vec<[u64; 2]>),
+    /// pairs stored in a memory-mapped file
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<MappedPairs>),
+    /// compressed pairs
+    Compressed(CompressedPairs),
+}
+
+impl Pairs {
+    /// A view of these pairs, read in place.
+    fn list(&self) -> PairList {
+        match self {
+            Pairs::Heap(v) => PairList::Slice(v),
+            #[cfg(feature = "mmap")]
+            Pairs::Mapped(mapped) => PairList::Slice(mapped.pairs()),
+            Pairs::Compressed(c) => PairList::Compressed {
+                pairs: c,
+                first: 0,
+                last: u64::MAX,
+            },
+        }
+    }
+
+    /// The number of pairs (without decompressing them).
+    fn len(&self) -> usize {
+        match self {
+            Pairs::Heap(v) => v.len(),
+            #[cfg(feature = "mmap")]
+            Pairs::Mapped(mapped) => mapped.pairs().len(),
+            Pairs::Compressed(c) => c.len(),
+        }
+    }
+
+    /// Whether there is no pair (without decompressing them).
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of bytes used by these pairs on the heap.
+    fn heap_size(&self) -> usize {
+        match self {
+            Pairs::Heap(v) => v.capacity() * std::mem::size_of::<[u64; 2]>(),
+            #[cfg(feature = "mmap")]
+            Pairs::Mapped(_) => 0,
+            Pairs::Compressed(c) => c.heap_size(),
+        }
+    }
+
+    fn is_heap(&self) -> bool {
+        match self {
+            Pairs::Heap(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        match self {
+            Pairs::Compressed(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Iterate over the pairs, starting from the first one
+    /// whose first element is greater than or equal to `x`.
+    fn iter_from(&self, x: u64) -> Box<dyn Iterator<Item = [u64; 2]> + '_> {
+        self.list().iter_from(x)
+    }
+
+    /// Whether `pair` is present.
+    fn contains(&self, pair: &[u64; 2]) -> bool {
+        match self.list() {
+            PairList::Slice(pairs) => pairs.binary_search(pair).is_ok(),
+            PairList::Compressed { pairs, .. } => pairs.contains(pair),
+        }
+    }
+
+    /// Borrow these pairs mutably, copying them to the heap if necessary.
+    fn to_mut(&mut self) -> &mut Vec<[u64; 2]> {
+        if !self.is_heap() {
+            let v = std::mem::replace(self, Pairs::Heap(vec![])).into_vec();
+            *self = Pairs::Heap(v);
+        }
+        match self {
+            Pairs::Heap(v) => v,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Convert these pairs into a vector, copying them if necessary.
+    fn into_vec(self) -> Vec<[u64; 2]> {
+        match self {
+            Pairs::Heap(v) => v,
+            #[cfg(feature = "mmap")]
+            Pairs::Mapped(mapped) => mapped.pairs().to_vec(),
+            Pairs::Compressed(c) => c.to_vec(),
+        }
+    }
+}
+
+impl PartialEq for Pairs {
+    fn eq(&self, other: &Pairs) -> bool {
+        self.len() == other.len() && self.list().iter().eq(other.list().iter())
+    }
+}
+
+impl fmt::Debug for Pairs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.list().iter()).finish()
+    }
+}
+
+/// A sorted list of pairs (of a chunk), read in place by the join engine:
+/// either a slice, or (part of) compressed pairs, which are decoded as they are read.
+///
+/// Compressed lists are never decoded as a whole:
+/// they are split (see `split`) into parts that are decoded one at a time.
+#[derive(Clone, Copy)]
+pub(crate) enum PairList<'a> {
+    Slice(&'a [[u64; 2]]),
+    /// the pairs of `pairs` whose first element is between `first` and `last` (inclusive)
+    Compressed {
+        pairs: &'a CompressedPairs,
+        first: u64,
+        last: u64,
+    },
+}
+
+impl<'a> PairList<'a> {
+    /// Iterate over the pairs.
+    pub fn iter(self) -> Box<dyn Iterator<Item = [u64; 2]> + 'a> {
+        match self {
+            PairList::Slice(pairs) => Box::new(pairs.iter().cloned()),
+            PairList::Compressed { first, .. } => self.iter_from(first),
+        }
+    }
+
+    /// Iterate over the pairs, starting from the first one
+    /// whose first element is greater than or equal to `x`.
+    pub fn iter_from(self, x: u64) -> Box<dyn Iterator<Item = [u64; 2]> + 'a> {
+        match self {
+            PairList::Slice(pairs) => {
+                let start = pairs.partition_point(|p| p[0] < x);
+                Box::new(pairs[start..].iter().cloned())
+            }
+            PairList::Compressed { pairs, first, last } => {
+                Box::new(pairs.iter_from(x.max(first)).take_while(move |p| p[0] <= last))
+            }
+        }
+    }
+
+    /// Whether there is no pair.
+    pub fn is_empty(self) -> bool {
+        match self {
+            PairList::Slice(pairs) => pairs.is_empty(),
+            PairList::Compressed { .. } => self.iter().next().is_none(),
+        }
+    }
+
+    /// The (possibly empty) group of pairs whose first element is `x`;
+    /// only the blocks containing them are decoded.
+    pub fn with(self, x: u64) -> Cow<'a, [[u64; 2]]> {
+        match self {
+            PairList::Slice(pairs) => Cow::Borrowed(crate::rules::pairs_with(pairs, x)),
+            PairList::Compressed { .. } => {
+                Cow::Owned(self.iter_from(x).take_while(|p| p[0] == x).collect())
+            }
+        }
+    }
+
+    /// The pairs, as a slice (decoding them if they are compressed).
+    pub fn decode(self) -> Cow<'a, [[u64; 2]]> {
+        match self {
+            PairList::Slice(pairs) => Cow::Borrowed(pairs),
+            PairList::Compressed { .. } => Cow::Owned(self.iter().collect()),
+        }
+    }
+
+    /// Split this list into parts of (roughly) at most `size` pairs,
+    /// without splitting any group of pairs sharing the same first element.
+    ///
+    /// Compressed lists are split on block boundaries, without decoding them.
+    pub fn split(self, size: usize) -> Vec<PairList<'a>> {
+        match self {
+            PairList::Slice(pairs) => {
+                let mut parts = Vec::with_capacity(pairs.len() / size + 1);
+                let mut rest = pairs;
+                while rest.len() > size {
+                    let last = rest[size - 1][0];
+                    let end = rest.partition_point(|p| p[0] <= last);
+                    let (part, r) = rest.split_at(end);
+                    parts.push(PairList::Slice(part));
+                    rest = r;
+                }
+                if !rest.is_empty() {
+                    parts.push(PairList::Slice(rest));
+                }
+                parts
+            }
+            PairList::Compressed { pairs, first, last } => {
+                let mut parts = vec![];
+                let mut start = first;
+                for key in pairs.split_keys(size) {
+                    if key <= start {
+                        continue;
+                    }
+                    if key > last {
+                        break;
+                    }
+                    parts.push(PairList::Compressed { pairs, first: start, last: key - 1 });
+                    start = key;
+                }
+                parts.push(PairList::Compressed { pairs, first: start, last });
+                parts
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a [[u64; 2]]> for PairList<'a> {
+    fn from(pairs: &'a [[u64; 2]]) -> Self {
+        PairList::Slice(pairs)
+    }
+}
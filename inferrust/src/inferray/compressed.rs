@@ -0,0 +1,275 @@
+//! A compressed representation of a sorted list of pairs.
+//!
+//! Pairs are grouped in blocks of (at most) `BLOCK_SIZE` pairs.
+//! Within a block, pairs are grouped in runs sharing the same first element:
+//! - the first element of each run is delta-encoded
+//!   w.r.t. the previous run of the block (the first run is stored as is),
+//!   followed by the length of the run;
+//! - the second elements of the run are delta-encoded
+//!   w.r.t. the previous pair of the run (the first one is stored as is).
+//!
+//! All numbers are encoded as LEB128 varints.
+//! A skip index stores the first pair and the byte offset of each block,
+//! so that a lookup only decodes a single block.
+
+/// The maximum number of pairs per block.
+const BLOCK_SIZE: usize = 128;
+
+/// See [module documentation](./index.html).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CompressedPairs {
+    /// the encoded blocks
+    data: Vec<u8>,
+    /// the first pair and byte offset of each block
+    skips: Vec<([u64; 2], usize)>,
+    /// the total number of pairs
+    len: usize,
+}
+
+impl CompressedPairs {
+    /// Compress the given list of pairs.
+    ///
+    /// # Pre-condition
+    /// `pairs` is sorted and without duplicates.
+    pub fn new(pairs: &[[u64; 2]]) -> Self {
+        let mut data = vec![];
+        let mut skips = Vec::with_capacity((pairs.len() + BLOCK_SIZE - 1) / BLOCK_SIZE);
+        for block in pairs.chunks(BLOCK_SIZE) {
+            skips.push((block[0], data.len()));
+            let mut prev_s = 0;
+            let mut i = 0;
+            while i < block.len() {
+                let s = block[i][0];
+                let run_len = block[i..].iter().take_while(|p| p[0] == s).count();
+                write_varint(&mut data, s - prev_s);
+                write_varint(&mut data, run_len as u64);
+                let mut prev_o = 0;
+                for [_, o] in &block[i..i + run_len] {
+                    write_varint(&mut data, o - prev_o);
+                    prev_o = *o;
+                }
+                prev_s = s;
+                i += run_len;
+            }
+        }
+        data.shrink_to_fit();
+        Self {
+            data,
+            skips,
+            len: pairs.len(),
+        }
+    }
+
+    /// The number of pairs.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there is no pair.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of bytes used on the heap.
+    pub fn heap_size(&self) -> usize {
+        self.data.capacity() + self.skips.capacity() * std::mem::size_of::<([u64; 2], usize)>()
+    }
+
+    /// The first elements at which these pairs can be split
+    /// into parts of (roughly) `size` pairs, in increasing order;
+    /// the parts start on block boundaries, so they are found without decoding any block.
+    pub fn split_keys(&self, size: usize) -> Vec<u64> {
+        let step = (size / BLOCK_SIZE).max(1);
+        let mut keys: Vec<u64> = self.skips.iter().step_by(step).skip(1).map(|(first, _)| first[0]).collect();
+        keys.dedup();
+        keys
+    }
+
+    /// Decompress all the pairs.
+    pub fn to_vec(&self) -> Vec<[u64; 2]> {
+        let mut v = Vec::with_capacity(self.len);
+        for b in 0..self.skips.len() {
+            self.decode_block(b, &mut v);
+        }
+        v
+    }
+
+    /// Iterate over all the pairs.
+    pub fn iter(&self) -> CompressedIter {
+        CompressedIter {
+            pairs: self,
+            next_block: 0,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            pos: 0,
+        }
+    }
+
+    /// Iterate over the pairs, starting from the first one
+    /// whose first element is greater than or equal to `x`
+    /// (see `crate::rules::gallop`).
+    pub fn iter_from(&self, x: u64) -> CompressedIter {
+        let b = self.skips.partition_point(|(first, _)| first[0] < x);
+        // block b-1 may contain pairs greater than x after its first one
+        let b = b.saturating_sub(1);
+        let mut it = CompressedIter {
+            pairs: self,
+            next_block: b,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            pos: 0,
+        };
+        if it.fill() {
+            it.pos = it.buf.partition_point(|p| p[0] < x);
+        }
+        it
+    }
+
+    /// Whether `pair` is present.
+    pub fn contains(&self, pair: &[u64; 2]) -> bool {
+        let b = self.skips.partition_point(|(first, _)| first <= pair);
+        if b == 0 {
+            return false;
+        }
+        let mut buf = Vec::with_capacity(BLOCK_SIZE);
+        self.decode_block(b - 1, &mut buf);
+        buf.binary_search(pair).is_ok()
+    }
+
+    /// Append the pairs of block `b` to `out`.
+    fn decode_block(&self, b: usize, out: &mut Vec<[u64; 2]>) {
+        let mut pos = self.skips[b].1;
+        let block_len = if b + 1 < self.skips.len() {
+            BLOCK_SIZE
+        } else {
+            self.len - b * BLOCK_SIZE
+        };
+        let end = out.len() + block_len;
+        let mut s = 0;
+        while out.len() < end {
+            s += read_varint(&self.data, &mut pos);
+            let run_len = read_varint(&self.data, &mut pos);
+            let mut o = 0;
+            for _ in 0..run_len {
+                o += read_varint(&self.data, &mut pos);
+                out.push([s, o]);
+            }
+        }
+    }
+}
+
+/// An iterator over (part of) a `CompressedPairs`, decoding one block at a time.
+pub(crate) struct CompressedIter<'a> {
+    pairs: &'a CompressedPairs,
+    next_block: usize,
+    buf: Vec<[u64; 2]>,
+    pos: usize,
+}
+
+impl<'a> CompressedIter<'a> {
+    /// Decode the next block into `buf`; return false if there is none.
+    fn fill(&mut self) -> bool {
+        if self.next_block >= self.pairs.skips.len() {
+            return false;
+        }
+        self.buf.clear();
+        self.pairs.decode_block(self.next_block, &mut self.buf);
+        self.next_block += 1;
+        self.pos = 0;
+        true
+    }
+}
+
+impl<'a> Iterator for CompressedIter<'a> {
+    type Item = [u64; 2];
+
+    fn next(&mut self) -> Option<[u64; 2]> {
+        while self.pos >= self.buf.len() {
+            if !self.fill() {
+                return None;
+            }
+        }
+        self.pos += 1;
+        Some(self.buf[self.pos - 1])
+    }
+}
+
+fn write_varint(data: &mut Vec<u8>, mut val: u64) {
+    while val >= 0x80 {
+        data.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    data.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> u64 {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Vec<[u64; 2]> {
+        let mut pairs = vec![];
+        for s in 0..50 {
+            for o in 0..(s % 7) {
+                pairs.push([(1 << 32) + s * 3, o * 1000 + s]);
+            }
+        }
+        pairs.push([u64::MAX, u64::MAX]);
+        pairs
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let pairs = sample();
+        let c = CompressedPairs::new(&pairs);
+        assert_eq!(c.len(), pairs.len());
+        assert_eq!(c.to_vec(), pairs);
+        assert_eq!(c.iter().collect::<Vec<_>>(), pairs);
+        assert!(CompressedPairs::new(&[]).iter().next().is_none());
+    }
+
+    #[test]
+    fn test_iter_from() {
+        let pairs = sample();
+        let c = CompressedPairs::new(&pairs);
+        for x in &[0, (1 << 32) + 3, (1 << 32) + 4, (1 << 32) + 147, u64::MAX] {
+            let start = pairs.partition_point(|p| p[0] < *x);
+            assert_eq!(c.iter_from(*x).collect::<Vec<_>>(), &pairs[start..]);
+        }
+    }
+
+    #[test]
+    fn test_split_keys() {
+        let pairs = sample();
+        let c = CompressedPairs::new(&pairs);
+        assert!(c.split_keys(1 << 20).is_empty());
+        let keys = c.split_keys(1);
+        assert_eq!(keys.len(), c.skips.len() - 1);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_contains() {
+        let pairs = sample();
+        let c = CompressedPairs::new(&pairs);
+        for pair in &pairs {
+            assert!(c.contains(pair));
+        }
+        assert!(!c.contains(&[0, 0]));
+        assert!(!c.contains(&[(1 << 32) + 1, 1]));
+        assert!(!c.contains(&[(1 << 32) + 6, 5000]));
+    }
+}
@@ -14,13 +14,23 @@
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 use sophia_api::ns::*;
-use sophia_api::term::TTerm;
+use sophia_api::term::{TTerm, TermKind};
 use sophia_api::triple::Triple;
 use sophia_term::{ArcTerm, RefTerm, StaticTerm, Term, TermData};
 use sophia_term::factory::{ArcTermFactory, TermFactory};
 
 use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+
+use super::snapshot::*;
+use crate::par::*;
+
+/// The number of buckets in which resources are deduplicated in parallel
+/// (see `NodeDictionary::merge_shards`).
+const NB_BUCKETS: usize = 64;
 
 /// See [module documentation](./index.html)
 pub(crate) struct NodeDictionary {
@@ -195,11 +205,37 @@ impl NodeDictionary {
             });
     }
 
+    /// The list of `[resource_index, property_index]` remappings
+    /// performed so far, in chronological order.
+    pub fn remapped(&self) -> &[[u64; 2]] {
+        &self.remapped
+    }
+
     /// Indicates whether a resource index was remapped to a property index.
     pub fn was_remapped(&self, res: u64) -> bool {
         self.remapped.iter().any(|[o, _]| *o == res)
     }
 
+    /// The number of resources in this dictionary.
+    pub fn resource_count(&self) -> usize {
+        self.resources.len()
+    }
+
+    /// Indicates whether `idx` is the index of a property or of a resource of this dictionary.
+    pub fn contains_index(&self, idx: u64) -> bool {
+        let start = Self::START_INDEX as u64;
+        if idx < start {
+            start - idx <= self.properties.len() as u64
+        } else {
+            idx > start && idx <= self.get_res_ctr()
+        }
+    }
+
+    /// The number of properties in this dictionary (including the predefined ones).
+    pub fn property_count(&self) -> usize {
+        self.properties.len()
+    }
+
     /// Return the first available resource index
     pub fn get_res_ctr(&self) -> u64 {
         self.resources.len() as u64 + Self::START_INDEX as u64
@@ -216,6 +252,151 @@ impl NodeDictionary {
         Self::START_INDEX as u64 - idx as u64 - 1
     }
 
+    /// Merge the dictionaries `shards` into a new dictionary,
+    /// and return it with the translation of the indexes of each shard.
+    ///
+    /// If `scope_bnodes`, the blank nodes of each shard are renamed
+    /// (their label is prefixed with the number of the shard and `_`),
+    /// so that blank nodes of different shards are distinct.
+    ///
+    /// Terms that are properties in any shard are properties in the merged dictionary
+    /// (as they are merged first), even where they are resources in other shards.
+    ///
+    /// Properties are merged sequentially, as they are usually few;
+    /// resources are split in buckets according to their hash,
+    /// and the buckets are deduplicated in parallel.
+    pub(super) fn merge_shards(shards: &[NodeDictionary], scope_bnodes: bool) -> (Self, Vec<IndexTranslation>) {
+        let scoped = |shard: usize, term: &ArcTerm| {
+            if scope_bnodes && term.kind() == TermKind::BlankNode {
+                ArcTerm::new_bnode_unchecked(format!("{}_{}", shard, term.value()))
+            } else {
+                term.clone()
+            }
+        };
+        let mut dict = Self::new();
+        let properties: Vec<Vec<u64>> = shards
+            .iter()
+            .map(|shard| shard.properties.iter().map(|t| dict.add_property(t) as u64).collect())
+            .collect();
+
+        // the resources of each shard, and their positions in each bucket
+        let resources: Vec<(Vec<ArcTerm>, Vec<Vec<usize>>)> = (0..shards.len())
+            .into_par_iter()
+            .map(|i| {
+                let terms: Vec<ArcTerm> = shards[i].resources.iter().map(|t| scoped(i, t)).collect();
+                let mut buckets = vec![vec![]; NB_BUCKETS];
+                for (pos, term) in terms.iter().enumerate() {
+                    buckets[bucket_of(term)].push(pos);
+                }
+                (terms, buckets)
+            })
+            .collect();
+        // the new resources of each bucket, numbered from 0 in their bucket
+        let new_resources: Vec<(Vec<&ArcTerm>, HashMap<&ArcTerm, u64>)> = (0..NB_BUCKETS)
+            .into_par_iter()
+            .map(|b| {
+                let mut terms = vec![];
+                let mut local = HashMap::new();
+                for (shard_terms, buckets) in &resources {
+                    for pos in &buckets[b] {
+                        let term = &shard_terms[*pos];
+                        if dict.get_index(term).is_none() && !local.contains_key(term) {
+                            local.insert(term, terms.len() as u64);
+                            terms.push(term);
+                        }
+                    }
+                }
+                (terms, local)
+            })
+            .collect();
+        let mut bucket_starts = Vec::with_capacity(NB_BUCKETS);
+        let mut next = Self::START_INDEX as u64 + 1 + dict.resources.len() as u64;
+        for (terms, _) in &new_resources {
+            bucket_starts.push(next);
+            next += terms.len() as u64;
+        }
+        let translations: Vec<IndexTranslation> = resources
+            .par_iter()
+            .zip(properties)
+            .map(|((terms, _), properties)| {
+                let resources = terms
+                    .iter()
+                    .map(|term| match dict.get_index(term) {
+                        Some(idx) => idx,
+                        None => {
+                            let b = bucket_of(term);
+                            bucket_starts[b] + new_resources[b].1[term]
+                        }
+                    })
+                    .collect();
+                IndexTranslation {
+                    resources,
+                    properties,
+                }
+            })
+            .collect();
+
+        dict.resources.reserve((next - bucket_starts[0]) as usize);
+        dict.indexes.reserve((next - bucket_starts[0]) as usize);
+        for (terms, _) in &new_resources {
+            for term in terms {
+                let term = (*term).clone();
+                let refterm = unsafe { fake_static(&term) };
+                dict.resources.push(term);
+                dict.indexes.insert(refterm, dict.resources.len() as u64 + Self::START_INDEX as u64);
+            }
+        }
+        debug_assert_eq!(dict.resources.len() as u64 + Self::START_INDEX as u64 + 1, next);
+        (dict, translations)
+    }
+
+    /// Write this dictionary in the snapshot format (see `InfGraph::save`).
+    pub(super) fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u64(w, self.properties.len() as u64)?;
+        for term in &self.properties {
+            write_term(w, term)?;
+        }
+        write_u64(w, self.resources.len() as u64)?;
+        for term in &self.resources {
+            write_term(w, term)?;
+        }
+        write_pairs(w, &self.remapped)
+    }
+
+    /// Read a dictionary written by `save`.
+    pub(super) fn load<R: Read>(r: &mut R) -> io::Result<Self> {
+        let properties = (0..read_u64(r)?)
+            .map(|_| read_term(r))
+            .collect::<io::Result<Vec<_>>>()?;
+        let resources = (0..read_u64(r)?)
+            .map(|_| read_term(r))
+            .collect::<io::Result<Vec<_>>>()?;
+        let remapped = read_pairs(r)?;
+        if properties.len() < (Self::START_INDEX - Self::prop_start) as usize
+            || resources.len() < (Self::res_start - Self::START_INDEX as u64) as usize
+        {
+            return Err(invalid_data("truncated dictionary"));
+        }
+        let mut indexes = HashMap::with_capacity(properties.len() + resources.len());
+        // NB: resources that were remapped also appear in properties;
+        // inserting properties last ensures that they get their property index
+        for (i, term) in resources.iter().enumerate() {
+            let idx = Self::START_INDEX as u64 + 1 + i as u64;
+            indexes.insert(unsafe { fake_static(term) }, idx);
+        }
+        for (i, term) in properties.iter().enumerate() {
+            let idx = Self::START_INDEX as u64 - 1 - i as u64;
+            indexes.insert(unsafe { fake_static(term) }, idx);
+        }
+        Ok(Self {
+            factory: ArcTermFactory::new(),
+            resources,
+            properties,
+            indexes,
+            remapped,
+        })
+    }
+
     fn add<T>(&mut self, term: &T) -> u64
     where
         T: TTerm + ?Sized,
@@ -382,6 +563,53 @@ impl NodeDictionary {
     }
 }
 
+/// The bucket of `term` (see `NodeDictionary::merge_shards`).
+fn bucket_of(term: &ArcTerm) -> usize {
+    let mut hasher = DefaultHasher::new();
+    term.hash(&mut hasher);
+    (hasher.finish() % NB_BUCKETS as u64) as usize
+}
+
+/// A translation from the indexes of a `NodeDictionary` to those of another one
+/// (see `NodeDictionary::merge_shards`).
+pub(crate) struct IndexTranslation {
+    resources: Vec<u64>,
+    properties: Vec<u64>,
+}
+
+impl IndexTranslation {
+    /// Translate a single index.
+    #[inline]
+    pub fn translate(&self, idx: u64) -> u64 {
+        if idx < NodeDictionary::START_INDEX as u64 {
+            self.properties[NodeDictionary::prop_idx_to_offset(idx)]
+        } else {
+            self.resources[(idx - NodeDictionary::START_INDEX as u64 - 1) as usize]
+        }
+    }
+
+    /// Translate an index-triple.
+    #[inline]
+    pub fn translate_triple(&self, [s, p, o]: [u64; 3]) -> [u64; 3] {
+        [self.translate(s), self.translate(p), self.translate(o)]
+    }
+}
+
+impl Clone for NodeDictionary {
+    fn clone(&self) -> Self {
+        // NB: the keys of `indexes` borrow their text from the terms
+        // in `resources` and `properties`; the cloned terms share the same
+        // underlying `Arc<str>`, so those keys remain valid in the clone.
+        Self {
+            factory: ArcTermFactory::new(),
+            resources: self.resources.clone(),
+            properties: self.properties.clone(),
+            indexes: self.indexes.clone(),
+            remapped: self.remapped.clone(),
+        }
+    }
+}
+
 /// Unsafely converts a term into a StaticTerm.
 /// This is to be used *only* when we can guarantee that the produced StaticTerm
 /// will not outlive the source term.
@@ -395,6 +623,55 @@ where
     t.borrow().clone_map(|txt| &*(txt as *const str))
 }
 
+/// Write a term in the snapshot format.
+fn write_term<W: Write>(w: &mut W, term: &ArcTerm) -> io::Result<()> {
+    match term.kind() {
+        TermKind::Iri => {
+            write_u8(w, 0)?;
+            write_str(w, &term.value())
+        }
+        TermKind::BlankNode => {
+            write_u8(w, 1)?;
+            write_str(w, &term.value())
+        }
+        TermKind::Literal => match term.language() {
+            Some(lang) => {
+                write_u8(w, 2)?;
+                write_str(w, &term.value())?;
+                write_str(w, lang)
+            }
+            None => {
+                write_u8(w, 3)?;
+                write_str(w, &term.value())?;
+                write_str(w, &term.datatype().unwrap().value())
+            }
+        },
+        TermKind::Variable => {
+            write_u8(w, 4)?;
+            write_str(w, &term.value())
+        }
+    }
+}
+
+/// Read a term written by `write_term`.
+fn read_term<R: Read>(r: &mut R) -> io::Result<ArcTerm> {
+    // NB: terms in a snapshot were valid when saved, hence the unchecked constructors
+    Ok(match read_u8(r)? {
+        0 => ArcTerm::new_iri_unchecked(read_str(r)?),
+        1 => ArcTerm::new_bnode_unchecked(read_str(r)?),
+        2 => {
+            let txt = read_str(r)?;
+            ArcTerm::new_literal_lang_unchecked(txt, read_str(r)?)
+        }
+        3 => {
+            let txt = read_str(r)?;
+            ArcTerm::new_literal_dt_unchecked(txt, ArcTerm::new_iri_unchecked(read_str(r)?))
+        }
+        4 => ArcTerm::new_variable_unchecked(read_str(r)?),
+        kind => return Err(invalid_data(format!("unknown term kind {}", kind))),
+    })
+}
+
 fn contains_prop_in_s_or_o(property_index: u32) -> PropertyPosition {
     let prop_in_s = vec![NodeDictionary::rdfsdomain, NodeDictionary::rdfsrange];
     let prop_in_s_and_o = vec![
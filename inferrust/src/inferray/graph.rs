@@ -7,17 +7,34 @@ use sophia_api::triple::stream::TripleSource;
 use sophia_api::triple::streaming_mode::{ByTermRefs, StreamedTriple};
 use sophia_term::ArcTerm;
 
+use itertools::Either;
+#[cfg(feature = "parallel")]
+use rayon::ThreadPool;
+
 use std::convert::Infallible;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "mmap")]
+use std::{fs::File, io::BufReader, io::BufWriter, path::Path};
 
+use crate::closure::ClosureGraph;
+use crate::inferray::same_as::SameAsIndex;
+use crate::inferray::snapshot::*;
 use crate::inferray::NodeDictionary;
 use crate::inferray::TripleStore;
+use crate::interrupt::{Interrupt, ProcessError, ProcessOptions, Stop};
+use crate::observer::{Observer, Silent};
+use crate::par::*;
 use crate::rules::*;
-use crate::utils::*;
+use crate::utils::pair_groups;
 
 /// Implementation of `sophia_api::graph::Graph` that supports inferences.
+#[derive(Clone)]
 pub struct InfGraph {
     dictionary: NodeDictionary,
     store: TripleStore,
+    /// the `owl:sameAs` cliques, if they are canonicalized
+    same_as: Option<SameAsIndex>,
 }
 
 impl Graph for InfGraph {
@@ -30,17 +47,21 @@ impl Graph for InfGraph {
                 .chunks()
                 .iter()
                 .enumerate()
-                .filter(|(_, chunk)| !chunk.so().is_empty())
+                .filter(|(_, chunk)| !chunk.is_empty())
                 .map(move |(pi, chunk)| {
                     let p = self
                         .dictionary
                         .get_term(NodeDictionary::offset_to_prop_idx(pi));
-                    chunk.so().iter().map(move |[si, oi]| {
-                        Ok(StreamedTriple::by_term_refs(
-                            self.dictionary.get_term(*si),
-                            p,
-                            self.dictionary.get_term(*oi),
-                        ))
+                    chunk.iter_so().flat_map(move |[si, oi]| {
+                        self.expand(si).flat_map(move |si| {
+                            self.expand(oi).map(move |oi| {
+                                Ok(StreamedTriple::by_term_refs(
+                                    self.dictionary.get_term(si),
+                                    p,
+                                    self.dictionary.get_term(oi),
+                                ))
+                            })
+                        })
                     })
                 })
                 .flatten(),
@@ -58,22 +79,20 @@ impl Graph for InfGraph {
                     .chunks()
                     .iter()
                     .enumerate()
-                    .filter(|(_, chunk)| !chunk.so().is_empty())
+                    .filter(|(_, chunk)| !chunk.is_empty())
                     .map(move |(pi, chunk)| {
                         let p = self
                             .dictionary
                             .get_term(NodeDictionary::offset_to_prop_idx(pi));
-                        let start_index = first_pair(&chunk.so(), si);
-                        chunk.so()[start_index..]
-                            .iter()
-                            .take_while(move |[is, _]| si == *is)
-                            .map(move |[_, oi]| {
+                        chunk.so_with_s(self.canonical(si)).flat_map(move |[_, oi]| {
+                            self.expand(oi).map(move |oi| {
                                 Ok(StreamedTriple::by_term_refs(
                                     s,
                                     p,
-                                    self.dictionary.get_term(*oi),
+                                    self.dictionary.get_term(oi),
                                 ))
                             })
+                        })
                     })
                     .flatten(),
             )
@@ -89,14 +108,18 @@ impl Graph for InfGraph {
         if let Some(ip) = self.dictionary.get_index(p) {
             let idx = NodeDictionary::prop_idx_to_offset(ip);
             let chunk = &self.store.chunks()[idx];
-            if !chunk.so().is_empty() {
+            if !chunk.is_empty() {
                 let p = self.dictionary.get_term(ip);
-                Box::from(chunk.so().iter().map(move |[si, oi]| {
-                    Ok(StreamedTriple::by_term_refs(
-                        self.dictionary.get_term(*si),
-                        p,
-                        self.dictionary.get_term(*oi),
-                    ))
+                Box::from(chunk.iter_so().flat_map(move |[si, oi]| {
+                    self.expand(si).flat_map(move |si| {
+                        self.expand(oi).map(move |oi| {
+                            Ok(StreamedTriple::by_term_refs(
+                                self.dictionary.get_term(si),
+                                p,
+                                self.dictionary.get_term(oi),
+                            ))
+                        })
+                    })
                 }))
             } else {
                 Box::from(std::iter::empty())
@@ -117,22 +140,20 @@ impl Graph for InfGraph {
                     .chunks()
                     .iter()
                     .enumerate()
-                    .filter(|(_, chunk)| !chunk.os().is_empty())
+                    .filter(|(_, chunk)| !chunk.is_empty())
                     .map(move |(pi, chunk)| {
                         let p = self
                             .dictionary
                             .get_term(NodeDictionary::offset_to_prop_idx(pi));
-                        let start_index = first_pair(&chunk.os(), oi);
-                        chunk.os()[start_index..]
-                            .iter()
-                            .take_while(move |[io, _]| oi == *io)
-                            .map(move |[_, si]| {
+                        chunk.os_with_o(self.canonical(oi)).flat_map(move |[_, si]| {
+                            self.expand(si).map(move |si| {
                                 Ok(StreamedTriple::by_term_refs(
-                                    self.dictionary.get_term(*si),
+                                    self.dictionary.get_term(si),
                                     p,
                                     o,
                                 ))
                             })
+                        })
                     })
                     .flatten(),
             )
@@ -149,22 +170,18 @@ impl Graph for InfGraph {
         if let (Some(si), Some(pi)) = (self.dictionary.get_index(s), self.dictionary.get_index(p)) {
             let idx = NodeDictionary::prop_idx_to_offset(pi);
             let chunk = &self.store.chunks()[idx];
-            if !chunk.so().is_empty() {
+            if !chunk.is_empty() {
                 let s = self.dictionary.get_term(si);
                 let p = self.dictionary.get_term(pi);
-                let start_index = first_pair(&chunk.so(), si);
-                Box::from(
-                    chunk.so()[start_index..]
-                        .iter()
-                        .take_while(move |[is, _]| *is == si)
-                        .map(move |[_, oi]| {
-                            Ok(StreamedTriple::by_term_refs(
-                                s,
-                                p,
-                                self.dictionary.get_term(*oi),
-                            ))
-                        }),
-                )
+                Box::from(chunk.so_with_s(self.canonical(si)).flat_map(move |[_, oi]| {
+                    self.expand(oi).map(move |oi| {
+                        Ok(StreamedTriple::by_term_refs(
+                            s,
+                            p,
+                            self.dictionary.get_term(oi),
+                        ))
+                    })
+                }))
             } else {
                 Box::from(std::iter::empty())
             }
@@ -181,12 +198,13 @@ impl Graph for InfGraph {
         if let (Some(si), Some(oi)) = (self.dictionary.get_index(s), self.dictionary.get_index(o)) {
             let s = self.dictionary.get_term(si);
             let o = self.dictionary.get_term(oi);
+            let pair = [self.canonical(si), self.canonical(oi)];
             Box::from(self.store.chunks().iter().enumerate().filter_map(
                 move |(pi, chunk)| {
-                    if chunk.so().is_empty() {
+                    if chunk.is_empty() {
                         None
                     } else {
-                        if chunk.so().binary_search(&[si, oi]).is_ok() {
+                        if chunk.contains(&pair) {
                             Some(Ok(StreamedTriple::by_term_refs(
                                 s,
                                 self.dictionary
@@ -212,22 +230,18 @@ impl Graph for InfGraph {
         if let (Some(pi), Some(oi)) = (self.dictionary.get_index(p), self.dictionary.get_index(o)) {
             let idx = NodeDictionary::prop_idx_to_offset(pi);
             let chunk = &self.store.chunks()[idx];
-            if !chunk.os().is_empty() {
+            if !chunk.is_empty() {
                 let p = self.dictionary.get_term(pi);
                 let o = self.dictionary.get_term(oi);
-                let start_index = first_pair(&chunk.os(), oi);
-                Box::from(
-                    chunk.os()[start_index..]
-                        .iter()
-                        .take_while(move |[io, _]| *io == oi)
-                        .map(move |[_, si]| {
-                            Ok(StreamedTriple::by_term_refs(
-                                self.dictionary.get_term(*si),
-                                p,
-                                o,
-                            ))
-                        }),
-                )
+                Box::from(chunk.os_with_o(self.canonical(oi)).flat_map(move |[_, si]| {
+                    self.expand(si).map(move |si| {
+                        Ok(StreamedTriple::by_term_refs(
+                            self.dictionary.get_term(si),
+                            p,
+                            o,
+                        ))
+                    })
+                }))
             } else {
                 Box::from(std::iter::empty())
             }
@@ -254,10 +268,10 @@ impl Graph for InfGraph {
         ) {
             let idx = NodeDictionary::prop_idx_to_offset(pi);
             let chunk = &self.store.chunks()[idx];
-            if chunk.so().is_empty() {
+            if chunk.is_empty() {
                 Box::from(std::iter::empty())
             } else {
-                if chunk.so().binary_search(&[si, oi]).is_ok() {
+                if chunk.contains(&[self.canonical(si), self.canonical(oi)]) {
                     let s = self.dictionary.get_term(si);
                     let o = self.dictionary.get_term(oi);
                     let p = self.dictionary.get_term(pi);
@@ -272,6 +286,9 @@ impl Graph for InfGraph {
     }
 }
 
+/// The number of triples loaded between two reports to the observer.
+const LOAD_STEP: usize = 100_000;
+
 impl InfGraph {
     /// Create a new `InfGraph` from the given triple source,
     /// to which the given inference regime (`profile`) is applied.
@@ -284,6 +301,18 @@ impl InfGraph {
         Ok(this)
     }
 
+    /// Like `new`, but loading and reasoning run in `pool`,
+    /// rather than in rayon's global thread pool
+    /// (requires the `parallel` feature).
+    #[cfg(feature = "parallel")]
+    pub fn new_in_pool<TS>(ts: TS, profile: &RuleProfile, pool: &ThreadPool) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource + Send,
+        TS::Error: Send,
+    {
+        pool.install(|| Self::new(ts, profile))
+    }
+
     /// Create a new `InfGraph` from the given triple source,
     /// with the RDFS rule profile.
     pub fn new_rdfs<TS>(ts: TS) -> Result<Self, TS::Error>
@@ -313,11 +342,195 @@ impl InfGraph {
 
     /// The total number of triples (explicit + inferred)
     /// in this graph.
+    ///
+    /// If `owl:sameAs` cliques are canonicalized (see `RuleProfile::RDFSPlusCanonical`),
+    /// this is the number of triples about the representatives of the cliques,
+    /// which is smaller than the number of triples yielded by `Graph::triples`.
     #[inline]
     pub fn size(&self) -> usize {
         self.store.size()
     }
 
+    /// The number of triples (explicit + inferred) of each predicate of this graph,
+    /// counted like `size`.
+    pub fn predicate_sizes(&self) -> Vec<(&ArcTerm, usize)> {
+        self.store
+            .chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| !chunk.is_empty())
+            .map(|(offset, chunk)| {
+                let p = NodeDictionary::offset_to_prop_idx(offset);
+                (self.dictionary.get_term(p), chunk.len())
+            })
+            .collect()
+    }
+
+    /// The members of the `owl:sameAs` clique of `term`, including `term` itself.
+    ///
+    /// Return an empty vector if `term` is not in this graph.
+    pub fn same_as_clique<T>(&self, term: &T) -> Vec<&ArcTerm>
+    where
+        T: TTerm + ?Sized,
+    {
+        let idx = match self.dictionary.get_index(term) {
+            Some(idx) => idx,
+            None => return vec![],
+        };
+        let members: Vec<u64> = match &self.same_as {
+            Some(same_as) => self.expand(same_as.rep(idx)).collect(),
+            None => {
+                let mut members = vec![idx];
+                if let Some(chunk) = self.store.chunks().get(NodeDictionary::prop_idx_to_offset(
+                    NodeDictionary::owlsameAs as u64,
+                )) {
+                    members.extend(chunk.so_with_s(idx).map(|[_, o]| o));
+                }
+                members.sort_unstable();
+                members.dedup();
+                members
+            }
+        };
+        members
+            .into_iter()
+            .map(|i| self.dictionary.get_term(i))
+            .collect()
+    }
+
+    /// The groups of nodes that `property` makes equivalent,
+    /// i.e. the strongly connected components (with more than one node)
+    /// of the graph formed by the `property` arcs.
+    ///
+    /// For example, with `rdfs:subClassOf`, this returns the groups of classes
+    /// that collapse into one another (once the graph is processed).
+    pub fn equivalence_classes<T>(&self, property: &T) -> Vec<Vec<&ArcTerm>>
+    where
+        T: TTerm + ?Sized,
+    {
+        let chunk = match self.dictionary.get_index(property) {
+            Some(ip) if ip < NodeDictionary::START_INDEX as u64 => {
+                match self.store.chunks().get(NodeDictionary::prop_idx_to_offset(ip)) {
+                    Some(chunk) => chunk,
+                    None => return vec![],
+                }
+            }
+            _ => return vec![],
+        };
+        ClosureGraph::from(&chunk.so())
+            .cycles()
+            .into_iter()
+            .map(|cycle| {
+                let mut members: Vec<u64> = cycle.into_iter().flat_map(|i| self.expand(i)).collect();
+                members.sort_unstable();
+                members.into_iter().map(|i| self.dictionary.get_term(i)).collect()
+            })
+            .collect()
+    }
+
+    /// Save this graph in a versioned binary format,
+    /// which can be read back with `load`.
+    ///
+    /// The snapshot contains all the triples (explicit + inferred),
+    /// so it should be saved once the graph is processed.
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_header(&mut writer)?;
+        self.dictionary.save(&mut writer)?;
+        self.store.save(&mut writer)?;
+        self.save_same_as(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Load a graph saved with `save`.
+    ///
+    /// No reasoning is involved: the graph is restored exactly as it was saved.
+    ///
+    /// # Performance
+    /// `reader` is read with many small reads,
+    /// so it should be buffered (e.g. with `std::io::BufReader`).
+    pub fn load<R: Read>(mut reader: R) -> io::Result<Self> {
+        read_header(&mut reader)?;
+        let dictionary = NodeDictionary::load(&mut reader)?;
+        let store = TripleStore::load(&mut reader, &dictionary)?;
+        let same_as = Self::load_same_as(&mut reader)?;
+        Ok(Self { dictionary, store, same_as })
+    }
+
+    /// Save this graph in directory `dir` (created if needed),
+    /// in a format that can be memory-mapped by `open_mapped`.
+    ///
+    /// Unlike `save`, the produced files are not portable
+    /// across architectures with different byte orders.
+    #[cfg(feature = "mmap")]
+    pub fn save_mapped<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let mut dict = BufWriter::new(File::create(dir.join("dictionary"))?);
+        write_header(&mut dict)?;
+        self.dictionary.save(&mut dict)?;
+        self.save_same_as(&mut dict)?;
+        dict.flush()?;
+        self.store.save_mapped(dir)
+    }
+
+    /// Open a graph saved with `save_mapped`.
+    ///
+    /// The triples are memory-mapped from the files in `dir`,
+    /// so they can be much larger than the available RAM.
+    /// The files must not be modified while the graph is open.
+    ///
+    /// The dictionary, on the other hand, is loaded in memory:
+    /// it holds the text of every IRI, blank node and literal of the graph,
+    /// plus an entry in a hash map for each of them,
+    /// so it takes roughly the size of these terms (plus some tens of bytes per term).
+    ///
+    /// The triple files are trusted to be as written by `save_mapped`:
+    /// only their size is checked, so opening a graph does not read them.
+    /// Use `open_mapped_verified` if they may be corrupt.
+    ///
+    /// Note that reasoning on (or otherwise modifying) this graph
+    /// copies the affected chunks in memory.
+    #[cfg(feature = "mmap")]
+    pub fn open_mapped<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Self::open_mapped_with(dir.as_ref(), false)
+    }
+
+    /// Like `open_mapped`, but the triple files are also checked to be sorted,
+    /// and to only contain terms of the dictionary,
+    /// so opening a graph reads them once;
+    /// an `io::ErrorKind::InvalidData` error is returned otherwise.
+    #[cfg(feature = "mmap")]
+    pub fn open_mapped_verified<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Self::open_mapped_with(dir.as_ref(), true)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn open_mapped_with(dir: &Path, verify: bool) -> io::Result<Self> {
+        let mut dict = BufReader::new(File::open(dir.join("dictionary"))?);
+        read_header(&mut dict)?;
+        let dictionary = NodeDictionary::load(&mut dict)?;
+        let same_as = Self::load_same_as(&mut dict)?;
+        let store = TripleStore::open_mapped(dir, &dictionary, verify)?;
+        Ok(Self { dictionary, store, same_as })
+    }
+
+    fn save_same_as<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match &self.same_as {
+            Some(same_as) => {
+                write_u8(w, 1)?;
+                same_as.save(w)
+            }
+            None => write_u8(w, 0),
+        }
+    }
+
+    fn load_same_as<R: Read>(r: &mut R) -> io::Result<Option<SameAsIndex>> {
+        match read_u8(r)? {
+            0 => Ok(None),
+            1 => Ok(Some(SameAsIndex::load(r)?)),
+            _ => Err(invalid_data("invalid sameAs flag")),
+        }
+    }
+
     /// **for benchmarking purposes only**
     ///
     /// Create a new `InfGraph` from the given triple source,
@@ -326,7 +539,15 @@ impl InfGraph {
     /// until the `process` method is called.
     ///
     /// This is useful for benchmarking the the loading time (without inferences).
-    pub fn new_unprocessed<TS>(mut ts: TS) -> Result<Self, TS::Error>
+    pub fn new_unprocessed<TS>(ts: TS) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource,
+    {
+        Self::new_unprocessed_observed(ts, &Silent)
+    }
+
+    /// Like `new_unprocessed`, but report the progress of loading to `observer`.
+    pub fn new_unprocessed_observed<TS>(mut ts: TS, observer: &dyn Observer) -> Result<Self, TS::Error>
     where
         TS: TripleSource,
     {
@@ -335,10 +556,140 @@ impl InfGraph {
         ts.for_each_triple(|t| {
             let rep = dictionary.encode_triple(&t);
             encoded.push(rep);
+            if encoded.len() % LOAD_STEP == 0 {
+                observer.triples_loaded(encoded.len());
+            }
         })?;
+        observer.triples_loaded(encoded.len());
+        dictionary.remap_triples(&mut encoded);
+        observer.dictionary_built(dictionary.resource_count(), dictionary.property_count());
+        let store = TripleStore::new(encoded);
+        Ok(Self { dictionary, store, same_as: None })
+    }
+
+    /// Create a new `InfGraph` from the given triple sources, loaded in parallel.
+    /// Like `new_unprocessed`, this graph is **unusable**
+    /// until the `process` method is called.
+    ///
+    /// Each source is parsed and encoded in its own dictionary, on its own thread;
+    /// those dictionaries are then merged (in parallel) into the final one.
+    /// See `new_unprocessed_pieces` for loading several pieces of a single document.
+    ///
+    /// # Blank nodes
+    /// As required when merging RDF graphs,
+    /// the blank nodes of different sources are distinct nodes,
+    /// even if they have the same identifier.
+    /// They are renamed accordingly (`_:b` in the 2nd source becomes `_:1_b`).
+    pub fn new_unprocessed_parallel<TS>(sources: Vec<TS>) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource + Send,
+        TS::Error: Send,
+    {
+        Self::load_shards(sources, true, &Silent)
+    }
+
+    /// Like `new_unprocessed_parallel`, but report the progress of loading to `observer`
+    /// (the number of loaded triples is the total over all the sources).
+    pub fn new_unprocessed_parallel_observed<TS>(sources: Vec<TS>, observer: &dyn Observer) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource + Send,
+        TS::Error: Send,
+    {
+        Self::load_shards(sources, true, observer)
+    }
+
+    /// Like `new_unprocessed_parallel`, but the sources are pieces of a single document
+    /// (see `split_ntriples` and `split_turtle`),
+    /// so blank nodes with the same identifier in different pieces
+    /// are the same node.
+    pub fn new_unprocessed_pieces<TS>(pieces: Vec<TS>) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource + Send,
+        TS::Error: Send,
+    {
+        Self::load_shards(pieces, false, &Silent)
+    }
+
+    /// Load `sources` in parallel (see `new_unprocessed_parallel`),
+    /// renaming their blank nodes if `scope_bnodes`,
+    /// and reporting the progress of loading to `observer`.
+    fn load_shards<TS>(sources: Vec<TS>, scope_bnodes: bool, observer: &dyn Observer) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource + Send,
+        TS::Error: Send,
+    {
+        // the number of triples loaded from all the sources
+        let loaded = AtomicUsize::new(0);
+        let shards = sources
+            .into_par_iter()
+            .map(|mut ts| {
+                let mut dictionary = NodeDictionary::new();
+                let mut encoded = vec![];
+                ts.for_each_triple(|t| {
+                    let rep = dictionary.encode_triple(&t);
+                    encoded.push(rep);
+                    if encoded.len() % LOAD_STEP == 0 {
+                        observer.triples_loaded(loaded.fetch_add(LOAD_STEP, Ordering::Relaxed) + LOAD_STEP);
+                    }
+                })?;
+                loaded.fetch_add(encoded.len() % LOAD_STEP, Ordering::Relaxed);
+                dictionary.remap_triples(&mut encoded);
+                Ok((dictionary, encoded))
+            })
+            .collect::<Result<Vec<_>, TS::Error>>()?;
+        observer.triples_loaded(loaded.load(Ordering::Relaxed));
+        let (dictionaries, triples): (Vec<_>, Vec<_>) = shards.into_iter().unzip();
+        let (mut dictionary, translations) = NodeDictionary::merge_shards(&dictionaries, scope_bnodes);
+        drop(dictionaries);
+        observer.dictionary_built(dictionary.resource_count(), dictionary.property_count());
+        let mut encoded: Vec<[u64; 3]> = triples
+            .into_par_iter()
+            .zip(translations)
+            .flat_map_iter(|(triples, translation)| {
+                triples
+                    .into_iter()
+                    .map(move |t| translation.translate_triple(t))
+            })
+            .collect();
         dictionary.remap_triples(&mut encoded);
         let store = TripleStore::new(encoded);
-        Ok(Self { dictionary, store })
+        Ok(Self { dictionary, store, same_as: None })
+    }
+
+    /// Like `new_unprocessed_parallel`, but the sources are loaded in `pool`,
+    /// rather than in rayon's global thread pool
+    /// (requires the `parallel` feature).
+    #[cfg(feature = "parallel")]
+    pub fn new_unprocessed_parallel_in_pool<TS>(sources: Vec<TS>, pool: &ThreadPool) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource + Send,
+        TS::Error: Send,
+    {
+        pool.install(|| Self::new_unprocessed_parallel(sources))
+    }
+
+    /// Encode the triples of `ts` and add them to this graph,
+    /// *without* any reasoning.
+    ///
+    /// Resources of the graph that are used as predicates in `ts`
+    /// are remapped to property indexes in the existing triples.
+    pub(crate) fn insert_triples<TS>(&mut self, mut ts: TS) -> Result<(), TS::Error>
+    where
+        TS: TripleSource,
+    {
+        let old_remapped = self.dictionary.remapped().len();
+        let dictionary = &mut self.dictionary;
+        let mut encoded = vec![];
+        ts.for_each_triple(|t| {
+            let rep = dictionary.encode_triple(&t);
+            encoded.push(rep);
+        })?;
+        if self.dictionary.remapped().len() > old_remapped {
+            self.store.remap(&self.dictionary.remapped()[old_remapped..]);
+        }
+        self.dictionary.remap_triples(&mut encoded);
+        self.merge_store(TripleStore::new(encoded));
+        Ok(())
     }
 
     /// **for benchlarking purposes only**
@@ -347,20 +698,264 @@ impl InfGraph {
     ///
     /// This is useful for benchmatking the processing time of inferences (without loading).
     pub fn process(&mut self, profile: &RuleProfile) {
-        self.compute_transitive_closures(&profile.cl_profile);
-        profile.before_rules.process(self);
+        self.process_observed(profile, &Silent)
+    }
+
+    /// Like `process`, but report the progress of reasoning to `observer`.
+    pub fn process_observed(&mut self, profile: &RuleProfile, observer: &dyn Observer) {
+        self.process_interruptible(profile, Interrupt::never(), observer)
+            .expect("processing can not be interrupted without cancel token, deadline nor limit")
+    }
+
+    /// Finalizes the processing of a graph created with `new_unprocessed`,
+    /// unless it is interrupted according to `options`
+    /// (cancellation, deadline, or one of the limits being exceeded).
+    ///
+    /// Interruptions are checked between the iterations of the fixpoint,
+    /// and regularly inside the rules and the computation of transitive closures.
+    /// Progress is reported to the observer of `options`, if any,
+    /// and rules run in the thread pool of `options`, if any.
+    ///
+    /// # Interruption
+    /// If processing is interrupted, the reason is returned as an error;
+    /// for limits, it names the predicate or the `owl:sameAs` clique responsible.
+    /// The graph then contains all its original triples,
+    /// and the triples inferred by the iterations completed before the interruption
+    /// (plus, if it was interrupted while re-closing transitive properties,
+    /// those of the interrupted iteration), which are all valid inferences,
+    /// but it is not saturated.
+    /// Processing it again with the same profile completes the inference.
+    pub fn process_with(
+        &mut self,
+        profile: &RuleProfile,
+        options: &ProcessOptions,
+    ) -> Result<(), ProcessError> {
+        let interrupt = Interrupt::new(options);
+        let observer = options.observer();
+        #[cfg(feature = "parallel")]
+        let result = match options.thread_pool() {
+            Some(pool) => pool.install(|| self.process_interruptible(profile, &interrupt, observer)),
+            None => self.process_interruptible(profile, &interrupt, observer),
+        };
+        #[cfg(not(feature = "parallel"))]
+        let result = self.process_interruptible(profile, &interrupt, observer);
+        result.map_err(|stop| self.process_error(stop))
+    }
+
+    fn process_interruptible(
+        &mut self,
+        profile: &RuleProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        interrupt.start(self.size());
+        self.init_same_as(profile);
+        self.check_limits(interrupt)?;
+        self.compute_transitive_closures(&profile.cl_profile, interrupt, observer)?;
+        self.check_limits(interrupt)?;
+        profile.before_rules.process(self, interrupt)?;
         if profile.axiomatic_triples {
             self.init_axiomatic_triples();
         }
-        profile.rules.process(self);
-        match &profile.after_rules {
-            Some(func) => {
-                self.merge_store(TripleStore::new(func(self)));
-            }
-            None => (),
-        }
+        profile.rules.process_closing(self, &profile.cl_profile, interrupt, observer)?;
+        self.apply_after_rules(profile, interrupt, observer)?;
+        #[cfg(debug_assertions)]
+        debug_assert!(self.store.is_sorted());
+        Ok(())
+    }
+
+    /// Apply the instance-level part of `profile` to this graph.
+    ///
+    /// This assumes that the schema part of this graph has already been
+    /// processed with the same profile (see `CompiledSchema`),
+    /// so the closures of `rdfs:subClassOf` and `rdfs:subPropertyOf`
+    /// are not recomputed, and schema rules are not applied.
+    ///
+    /// See `process_with` for the state of the graph if `interrupt` is triggered.
+    pub(crate) fn process_instances(
+        &mut self,
+        profile: &RuleProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        interrupt.start(self.size());
+        self.init_same_as(profile);
+        self.check_limits(interrupt)?;
+        let cl_profile = ClosureProfile {
+            on_sco: false,
+            on_spo: false,
+            ..profile.cl_profile
+        };
+        self.compute_transitive_closures(&cl_profile, interrupt, observer)?;
+        self.check_limits(interrupt)?;
+        profile.before_rules.process(self, interrupt)?;
+        profile.instance_rules.process_closing(self, &cl_profile, interrupt, observer)?;
+        self.apply_after_rules(profile, interrupt, observer)?;
         #[cfg(debug_assertions)]
         debug_assert!(self.store.is_sorted());
+        Ok(())
+    }
+
+    /// Apply the rules that `profile` runs once the fixpoint is reached.
+    fn apply_after_rules(
+        &mut self,
+        profile: &RuleProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        interrupt.status()?;
+        let size = self.size();
+        if let Some(func) = &profile.after_rules {
+            let store = func(self).into_store();
+            self.check_new_triples(&store, interrupt)?;
+            self.merge_store(store);
+        }
+        self.rewrite_same_as();
+        observer.after_rules_finished(self.size().saturating_sub(size));
+        Ok(())
+    }
+
+    /// Check that adding the triples of `new` to this graph does not exceed
+    /// the inference limit of `interrupt`, blaming the most frequent predicate in `new` otherwise.
+    pub(crate) fn check_new_triples(&self, new: &TripleStore, interrupt: &Interrupt) -> Result<(), Stop> {
+        interrupt.check_size(self.size() + new.size(), || match new.size() {
+            0 => self.store.largest_predicate(),
+            _ => new.largest_predicate(),
+        })
+    }
+
+    /// Check that this graph does not exceed the limits of `interrupt`,
+    /// and that the process has not been interrupted otherwise.
+    pub(crate) fn check_limits(&self, interrupt: &Interrupt) -> Result<(), Stop> {
+        interrupt.check_size(self.size(), || self.store.largest_predicate())?;
+        if !interrupt.limits_cliques() {
+            return Ok(());
+        }
+        let largest = match &self.same_as {
+            Some(same_as) => same_as.largest_clique(),
+            None => self
+                .store
+                .chunks()
+                .get(NodeDictionary::prop_idx_to_offset(NodeDictionary::owlsameAs as u64))
+                .and_then(|chunk| {
+                    let so = chunk.so();
+                    pair_groups(&so).max_by_key(|group| group.len()).map(|group| {
+                        // the owl:sameAs triples of x are (x, y) for every other y of its clique
+                        let x = group[0][0];
+                        let reflexive = group.binary_search(&[x, x]).is_ok();
+                        (x, group.len() + !reflexive as usize)
+                    })
+                }),
+        };
+        if let Some((member, size)) = largest {
+            interrupt.check_clique(member, size);
+        }
+        interrupt.status()
+    }
+
+    /// Convert the reason why processing was stopped into a `ProcessError`.
+    pub(crate) fn process_error(&self, stop: Stop) -> ProcessError {
+        let term = |idx| self.dictionary.get_term(idx).to_string();
+        match stop {
+            Stop::Cancelled => ProcessError::Cancelled,
+            Stop::TimedOut => ProcessError::TimedOut,
+            Stop::Inferences { limit, predicate } => ProcessError::TooManyInferences {
+                limit,
+                predicate: term(predicate),
+            },
+            Stop::Clique { limit, member, size } => ProcessError::CliqueTooLarge {
+                limit,
+                member: term(member),
+                size,
+            },
+            Stop::Iterations { limit } => ProcessError::TooManyIterations { limit },
+        }
+    }
+
+    /// Enable the canonicalization of `owl:sameAs` cliques if `profile` requires it,
+    /// and canonicalize the triples currently in the store.
+    fn init_same_as(&mut self, profile: &RuleProfile) {
+        if profile.canonical_same_as && self.same_as.is_none() {
+            self.same_as = Some(SameAsIndex::default());
+        }
+        // triples may have been added since the last canonicalization
+        self.rewrite_same_as();
+        self.canonicalize_same_as();
+    }
+
+    /// Merge the `owl:sameAs` cliques with the new `owl:sameAs` triples of the store,
+    /// and replace every resource in the store by the representative of its clique.
+    ///
+    /// Return true if any clique has changed.
+    /// This does nothing if `owl:sameAs` cliques are not canonicalized.
+    pub(crate) fn canonicalize_same_as(&mut self) -> bool {
+        let same_as = match &mut self.same_as {
+            Some(same_as) => same_as,
+            None => return false,
+        };
+        let changed = match self.store.chunks().get(NodeDictionary::prop_idx_to_offset(
+            NodeDictionary::owlsameAs as u64,
+        )) {
+            Some(chunk) => same_as.union(&chunk.so()),
+            None => false,
+        };
+        if changed {
+            self.rewrite_same_as();
+        }
+        changed
+    }
+
+    /// Replace every resource in the store by the representative of its clique.
+    fn rewrite_same_as(&mut self) {
+        if let Some(same_as) = &self.same_as {
+            if !same_as.is_empty() {
+                self.store.map_values(|v| same_as.rep(v));
+            }
+        }
+    }
+
+    /// The index used in the store for `idx`.
+    #[inline]
+    pub(crate) fn canonical(&self, idx: u64) -> u64 {
+        match &self.same_as {
+            Some(same_as) => same_as.rep(idx),
+            None => idx,
+        }
+    }
+
+    /// All the indexes represented by `idx` in the store.
+    #[inline]
+    pub(crate) fn expand(&self, idx: u64) -> impl Iterator<Item = u64> + '_ {
+        match self.same_as.as_ref().and_then(|same_as| same_as.clique(idx)) {
+            Some(members) => Either::Left(members.iter().cloned()),
+            None => Either::Right(std::iter::once(idx)),
+        }
+    }
+
+    /// The index used in the store for `term`, if it is in this graph.
+    #[inline]
+    pub(crate) fn index_of<T>(&self, term: &T) -> Option<u64>
+    where
+        T: TTerm + ?Sized,
+    {
+        self.dictionary.get_index(term).map(|idx| self.canonical(idx))
+    }
+
+    /// The term with index `idx`.
+    #[inline]
+    pub(crate) fn term(&self, idx: u64) -> &ArcTerm {
+        self.dictionary.get_term(idx)
+    }
+
+    /// Compress the triples of this graph in memory.
+    ///
+    /// This does not change the content of the graph,
+    /// but significantly reduces its memory footprint.
+    /// The `Graph` methods work directly on the compressed data,
+    /// and so does reasoning, which decodes the chunks read by the rules part by part;
+    /// only the chunks receiving new triples are decompressed (for good).
+    pub fn compress(&mut self) {
+        self.store.compress();
     }
 
     /// Borrow the NodeDictionary of this graph.
@@ -385,24 +980,164 @@ impl InfGraph {
         self.store.merge(other);
     }
 
-    fn compute_transitive_closures(&mut self, profile: &ClosureProfile) {
+    fn compute_transitive_closures(
+        &mut self,
+        profile: &ClosureProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        let ips = self.closed_properties(profile);
+        self.close_properties(&ips, profile, interrupt, observer)
+    }
+
+    /// Compute the transitive closures of the given properties,
+    /// and derive equivalences from their cycles if `profile.on_eq`.
+    ///
+    /// If `interrupt` is triggered while closing, this graph is left unchanged.
+    fn close_properties(
+        &mut self,
+        ips: &[u32],
+        profile: &ClosureProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        let dictionary = &self.dictionary;
+        let cycles = self.store.transitive_closures(
+            ips,
+            interrupt,
+            |ip, len| observer.closure_started(dictionary.get_term(ip as u64), len),
+            |ip, len| observer.closure_finished(dictionary.get_term(ip as u64), len),
+        )?;
+        self.derive_equivalences(cycles, profile);
+        Ok(())
+    }
+
+    /// Extend the transitive closures of some properties with the pairs `added` to them
+    /// (see `TripleStore::extend_closures`),
+    /// and derive equivalences from their new cycles if `profile.on_eq`.
+    ///
+    /// If `interrupt` is triggered while closing, this graph is left unchanged.
+    fn extend_closures(
+        &mut self,
+        added: &[(u32, Vec<[u64; 2]>)],
+        profile: &ClosureProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        let dictionary = &self.dictionary;
+        let cycles = self.store.extend_closures(
+            added,
+            interrupt,
+            |ip, len| observer.closure_started(dictionary.get_term(ip as u64), len),
+            |ip, len| observer.closure_finished(dictionary.get_term(ip as u64), len),
+        )?;
+        self.derive_equivalences(cycles, profile);
+        Ok(())
+    }
+
+    /// Derive equivalences from the cycles of `rdfs:subClassOf` and `rdfs:subPropertyOf`,
+    /// if `profile.on_eq`.
+    fn derive_equivalences(&mut self, cycles: Vec<(u32, Vec<Vec<u64>>)>, profile: &ClosureProfile) {
+        if !profile.on_eq {
+            return;
+        }
+        let mut equivalences = vec![];
+        for (ip, prop_cycles) in cycles {
+            let eq = match ip {
+                NodeDictionary::rdfssubClassOf => NodeDictionary::owlequivalentClass,
+                NodeDictionary::rdfssubPropertyOf => NodeDictionary::owlequivalentProperty,
+                _ => continue,
+            } as u64;
+            for cycle in prop_cycles {
+                for a in &cycle {
+                    for b in &cycle {
+                        if a != b {
+                            equivalences.push([*a, eq, *b]);
+                        }
+                    }
+                }
+            }
+        }
+        if !equivalences.is_empty() {
+            self.merge_store(TripleStore::new(equivalences));
+        }
+    }
+
+    /// The properties whose transitive closure is maintained under `profile`.
+    pub(crate) fn closed_properties(&self, profile: &ClosureProfile) -> Vec<u32> {
+        let mut ips = vec![];
         if profile.on_sco {
-            self.store.transitive_closure(NodeDictionary::rdfssubClassOf);
+            ips.push(NodeDictionary::rdfssubClassOf);
         }
         if profile.on_spo {
-            self.store.transitive_closure(NodeDictionary::rdfssubPropertyOf);
+            ips.push(NodeDictionary::rdfssubPropertyOf);
         }
         if profile.on_sa {
-            self.store.transitive_closure(NodeDictionary::owlsameAs);
+            ips.push(NodeDictionary::owlsameAs);
         }
         if profile.on_trp {
-            for tr_idx in self.get_tr_idx() {
-                self.store.transitive_closure(tr_idx);
-            }
+            ips.extend(self.get_tr_idx());
         }
+        ips
+    }
+
+    /// The pairs of `new` that are not in this graph yet, for each of the properties `ips`
+    /// (to be passed to `reclose` once `new` is merged into this graph).
+    pub(crate) fn added_pairs(&self, ips: &[u32], new: &TripleStore) -> Vec<(u32, Vec<[u64; 2]>)> {
+        let chunks = self.store.chunks();
+        ips.iter()
+            .filter_map(|ip| {
+                let offset = NodeDictionary::prop_idx_to_offset(*ip as u64);
+                let pairs: Vec<[u64; 2]> = match (new.chunks().get(offset), chunks.get(offset)) {
+                    (Some(new_chunk), Some(chunk)) => {
+                        new_chunk.iter_so().filter(|pair| !chunk.contains(pair)).collect()
+                    }
+                    (Some(new_chunk), None) => new_chunk.iter_so().collect(),
+                    (None, _) => vec![],
+                };
+                if pairs.is_empty() {
+                    None
+                } else {
+                    Some((*ip, pairs))
+                }
+            })
+            .collect()
     }
 
-    fn get_tr_idx(&mut self) -> Vec<u32> {
+    /// Restore the transitive closures that may have been broken by new triples.
+    ///
+    /// `closed` is the result of `closed_properties` before those triples were added,
+    /// and `added` the result of `added_pairs` for them.
+    /// The closure of a property that was not closed before
+    /// (typically, because it has just been inferred to be an `owl:TransitiveProperty`)
+    /// is computed from scratch;
+    /// the closure of a property that was closed is only extended
+    /// with the paths going through its new pairs.
+    ///
+    /// If `closed` is empty (e.g. after `owl:sameAs` cliques were canonicalized,
+    /// which may change every chunk), all the closures are computed from scratch.
+    ///
+    /// If `interrupt` is triggered, some closures may be left incomplete.
+    pub(crate) fn reclose(
+        &mut self,
+        profile: &ClosureProfile,
+        closed: &[u32],
+        added: &[(u32, Vec<[u64; 2]>)],
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
+        let (extended, unclosed): (Vec<u32>, Vec<u32>) = self.closed_properties(profile)
+            .into_iter()
+            .partition(|ip| closed.contains(ip));
+        let added: Vec<(u32, Vec<[u64; 2]>)> = added.iter()
+            .filter(|(ip, _)| extended.contains(ip))
+            .cloned()
+            .collect();
+        self.close_properties(&unclosed, profile, interrupt, observer)?;
+        self.extend_closures(&added, profile, interrupt, observer)
+    }
+
+    fn get_tr_idx(&self) -> Vec<u32> {
         if let Some(pairs) = self
             .store
             .chunks()
@@ -411,8 +1146,7 @@ impl InfGraph {
             ))
         {
             pairs
-                .so()
-                .iter()
+                .iter_so()
                 .filter(|pair| pair[1] == NodeDictionary::owltransitiveProperty as u64)
                 .map(|pair| pair[0] as u32)
                 .collect()
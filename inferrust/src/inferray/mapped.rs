@@ -0,0 +1,65 @@
+//! Memory-mapped storage of sorted pairs (requires feature `mmap`).
+//!
+//! A pair file is the raw content of a `[[u64; 2]]` slice,
+//! in the native byte order of the machine that wrote it.
+//! It is therefore *not* portable across architectures;
+//! use `InfGraph::save` for a portable snapshot.
+
+use memmap2::Mmap;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem::{align_of, size_of};
+use std::path::Path;
+
+use super::snapshot::invalid_data;
+
+const PAIR_SIZE: usize = size_of::<[u64; 2]>();
+
+/// A read-only list of pairs, backed by a memory-mapped file.
+pub(crate) struct MappedPairs {
+    mmap: Mmap,
+}
+
+impl MappedPairs {
+    /// Map the given pair file in memory.
+    ///
+    /// # Safety note
+    /// The file must not be modified while it is mapped;
+    /// this is not enforced by the OS, so it is up to the caller.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() % PAIR_SIZE != 0 {
+            return Err(invalid_data(format!(
+                "{}: size is not a multiple of {}",
+                path.display(),
+                PAIR_SIZE
+            )));
+        }
+        debug_assert_eq!(mmap.as_ptr() as usize % align_of::<[u64; 2]>(), 0);
+        Ok(Self { mmap })
+    }
+
+    /// The pairs stored in the underlying file.
+    #[inline]
+    pub fn pairs(&self) -> &[[u64; 2]] {
+        // mmap'ed memory is page-aligned, and its size was checked in `open`
+        unsafe {
+            std::slice::from_raw_parts(
+                self.mmap.as_ptr() as *const [u64; 2],
+                self.mmap.len() / PAIR_SIZE,
+            )
+        }
+    }
+}
+
+/// Write `pairs` into a file that can later be opened with `MappedPairs::open`.
+pub(crate) fn write_pairs_file(path: &Path, pairs: &[[u64; 2]]) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    for [a, b] in pairs {
+        w.write_all(&a.to_ne_bytes())?;
+        w.write_all(&b.to_ne_bytes())?;
+    }
+    w.flush()
+}
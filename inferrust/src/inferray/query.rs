@@ -0,0 +1,406 @@
+//! Basic graph patterns (conjunctions of triple patterns), evaluated on indexes.
+//!
+//! Patterns are encoded with the `NodeDictionary`, and evaluated one at a time,
+//! in an order chosen from the sizes of the chunks they read:
+//! each pattern is joined with the solutions of the previous ones
+//! by a sort-merge join over the `so` or `os` list of its chunk.
+//! Only the final solutions are decoded into terms.
+
+use sophia_api::term::TTerm;
+use sophia_term::ArcTerm;
+
+use super::*;
+use crate::rules::{gallop, pairs_with};
+
+/// A term of a triple pattern: either a named variable, or a constant term.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatternTerm<T> {
+    Var(String),
+    Term(T),
+}
+
+impl<T> PatternTerm<T> {
+    /// The variable named `name`.
+    pub fn var(name: &str) -> Self {
+        PatternTerm::Var(name.to_string())
+    }
+}
+
+/// The solutions of a basic graph pattern (see `InfGraph::query_bgp`).
+///
+/// Each solution binds every variable of the pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solutions<'g> {
+    variables: Vec<String>,
+    rows: Vec<Vec<&'g ArcTerm>>,
+}
+
+impl<'g> Solutions<'g> {
+    /// The variables of the pattern, in order of first appearance.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// The number of solutions.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Whether the pattern has no solution.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Iterate over the solutions,
+    /// each of them giving the value of every variable (in the order of `variables`).
+    pub fn iter(&self) -> impl Iterator<Item = &[&'g ArcTerm]> + '_ {
+        self.rows.iter().map(|row| &row[..])
+    }
+
+    /// The values of `variable` in every solution (empty if there is no such variable).
+    pub fn values(&self, variable: &str) -> Vec<&'g ArcTerm> {
+        match self.variables.iter().position(|v| v == variable) {
+            Some(i) => self.rows.iter().map(|row| row[i]).collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// A position of an encoded triple pattern.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Slot {
+    /// the variable with the given number
+    Var(usize),
+    /// the index of a term
+    Const(u64),
+}
+
+/// The solutions of an encoded basic graph pattern,
+/// as the indexes bound to each variable (by variable number).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct IdSolutions {
+    pub rows: Vec<Vec<u64>>,
+}
+
+impl InfGraph {
+    /// Find all the solutions of the basic graph pattern `patterns`,
+    /// i.e. the bindings of its variables such that every triple pattern is in this graph.
+    ///
+    /// The patterns are evaluated on indexes, starting with the most selective ones
+    /// (according to the number of triples of each predicate),
+    /// and only the final solutions are decoded into terms.
+    ///
+    /// # Example
+    /// ```
+    /// # use inferrust::*;
+    /// # use sophia_term::BoxTerm;
+    /// # let graph = InfGraph::new_rdfs(sophia::parser::turtle::parse_str("")).unwrap();
+    /// let ex = |name: &str| PatternTerm::Term(BoxTerm::new_iri(format!("http://example.org/{}", name)).unwrap());
+    /// let solutions = graph.query_bgp(&[
+    ///     [PatternTerm::var("x"), ex("father"), PatternTerm::var("y")],
+    ///     [PatternTerm::var("y"), ex("mother"), PatternTerm::var("z")],
+    /// ]);
+    /// for row in solutions.iter() {
+    ///     println!("{} {} {}", row[0], row[1], row[2]);
+    /// }
+    /// ```
+    pub fn query_bgp<T>(&self, patterns: &[[PatternTerm<T>; 3]]) -> Solutions
+    where
+        T: TTerm,
+    {
+        let mut variables: Vec<String> = vec![];
+        let mut known = true;
+        let encoded: Vec<[Slot; 3]> = patterns
+            .iter()
+            .map(|pattern| {
+                let mut slots = [Slot::Const(0); 3];
+                for (slot, term) in slots.iter_mut().zip(pattern.iter()) {
+                    *slot = match term {
+                        PatternTerm::Var(name) => match variables.iter().position(|v| v == name) {
+                            Some(i) => Slot::Var(i),
+                            None => {
+                                variables.push(name.clone());
+                                Slot::Var(variables.len() - 1)
+                            }
+                        },
+                        PatternTerm::Term(t) => match self.index_of(t) {
+                            Some(idx) => Slot::Const(idx),
+                            None => {
+                                known = false;
+                                Slot::Const(0)
+                            }
+                        },
+                    };
+                }
+                slots
+            })
+            .collect();
+        let ids = if known {
+            self.query_ids(&encoded, variables.len())
+        } else {
+            // a term absent from the dictionary matches nothing
+            IdSolutions::default()
+        };
+        self.decode(variables, ids)
+    }
+
+    /// Find all the solutions of the encoded basic graph pattern `patterns`,
+    /// with `nb_vars` variables.
+    pub(crate) fn query_ids(&self, patterns: &[[Slot; 3]], nb_vars: usize) -> IdSolutions {
+        let mut rows = vec![vec![0; nb_vars]];
+        let mut bound = vec![false; nb_vars];
+        let mut remaining: Vec<&[Slot; 3]> = patterns.iter().collect();
+        while !remaining.is_empty() && !rows.is_empty() {
+            let next = self.next_pattern(&remaining, &bound);
+            let pattern = remaining.swap_remove(next);
+            rows = self.join_pattern(rows, &bound, pattern);
+            for slot in pattern {
+                if let Slot::Var(v) = slot {
+                    bound[*v] = true;
+                }
+            }
+        }
+        IdSolutions { rows }
+    }
+
+    /// Choose the next pattern to evaluate among `remaining`, knowing the `bound` variables:
+    /// the one with the fewest candidate triples, preferring patterns
+    /// sharing a variable with the previous ones (to avoid cartesian products).
+    fn next_pattern(&self, remaining: &[&[Slot; 3]], bound: &[bool]) -> usize {
+        let connected = |pattern: &[Slot; 3]| {
+            pattern.iter().any(|slot| match slot {
+                Slot::Var(v) => bound[*v],
+                Slot::Const(_) => false,
+            })
+        };
+        let any_connected = remaining.iter().any(|p| connected(p));
+        remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| !any_connected || connected(p))
+            .min_by_key(|(_, p)| self.estimate(p))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// The number of triples matching the constants of `pattern`.
+    fn estimate(&self, pattern: &[Slot; 3]) -> usize {
+        let chunks = self.store().chunks();
+        let count = |chunk: &Chunk| match pattern {
+            [Slot::Const(s), _, Slot::Const(o)] => chunk.contains(&[*s, *o]) as usize,
+            [Slot::Const(s), _, _] => chunk.so_with_s(*s).count(),
+            [_, _, Slot::Const(o)] => chunk.os_with_o(*o).count(),
+            _ => chunk.len(),
+        };
+        match pattern[1] {
+            Slot::Const(p) => match chunk_of(chunks, p) {
+                Some(chunk) => count(chunk),
+                None => 0,
+            },
+            Slot::Var(_) => chunks.iter().map(count).sum(),
+        }
+    }
+
+    /// Join `rows` (whose `bound` variables are bound) with the triples matching `pattern`.
+    fn join_pattern(&self, mut rows: Vec<Vec<u64>>, bound: &[bool], pattern: &[Slot; 3]) -> Vec<Vec<u64>> {
+        let chunks = self.store().chunks();
+        match pattern[1] {
+            Slot::Const(p) => match chunk_of(chunks, p) {
+                Some(chunk) => join_chunk(rows, bound, pattern, p, chunk),
+                None => vec![],
+            },
+            Slot::Var(v) if bound[v] => {
+                // join separately the rows of each predicate
+                rows.sort_unstable_by_key(|row| row[v]);
+                let mut result = vec![];
+                let mut rest = &rows[..];
+                while let Some(first) = rest.first() {
+                    let p = first[v];
+                    let len = rest.partition_point(|row| row[v] == p);
+                    if let Some(chunk) = chunk_of(chunks, p) {
+                        result.extend(join_chunk(rest[..len].to_vec(), bound, pattern, p, chunk));
+                    }
+                    rest = &rest[len..];
+                }
+                result
+            }
+            Slot::Var(_) => chunks
+                .iter()
+                .enumerate()
+                .filter(|(_, chunk)| !chunk.is_empty())
+                .flat_map(|(offset, chunk)| {
+                    let p = NodeDictionary::offset_to_prop_idx(offset);
+                    join_chunk(rows.clone(), bound, pattern, p, chunk)
+                })
+                .collect(),
+        }
+    }
+
+    /// Decode the solutions `ids` of a pattern with the given `variables`,
+    /// expanding `owl:sameAs` cliques if they are canonicalized.
+    fn decode(&self, variables: Vec<String>, ids: IdSolutions) -> Solutions {
+        let mut rows = vec![];
+        for id_row in ids.rows {
+            let mut expanded: Vec<Vec<&ArcTerm>> = vec![vec![]];
+            for idx in id_row {
+                let members: Vec<&ArcTerm> = self.expand(idx).map(|i| self.dict().get_term(i)).collect();
+                expanded = expanded
+                    .into_iter()
+                    .flat_map(|row| {
+                        members.iter().map(move |term| {
+                            let mut row = row.clone();
+                            row.push(*term);
+                            row
+                        })
+                    })
+                    .collect();
+            }
+            rows.extend(expanded);
+        }
+        Solutions { variables, rows }
+    }
+}
+
+/// The chunk of predicate `p`, if `p` is a property having triples.
+fn chunk_of(chunks: &[Chunk], p: u64) -> Option<&Chunk> {
+    if p >= NodeDictionary::START_INDEX as u64 {
+        return None;
+    }
+    chunks
+        .get(NodeDictionary::prop_idx_to_offset(p))
+        .filter(|chunk| !chunk.is_empty())
+}
+
+/// The index bound to `slot` in `row`, if any.
+#[inline]
+fn key(slot: Slot, row: &[u64], bound: &[bool]) -> Option<u64> {
+    match slot {
+        Slot::Const(c) => Some(c),
+        Slot::Var(v) if bound[v] => Some(row[v]),
+        Slot::Var(_) => None,
+    }
+}
+
+/// Join `rows` with the triples of `chunk` (whose predicate is `p`) matching `pattern`.
+///
+/// The rows are sorted on the subject of the pattern (if bound) and merged with `so`,
+/// or else on its object (if bound) and merged with `os`;
+/// otherwise every row is combined with every pair of `chunk`.
+///
+/// The pairs are read in place, like in the join engine:
+/// a slice is searched by galloping, and compressed pairs are decoded as they are merged.
+fn join_chunk(
+    mut rows: Vec<Vec<u64>>,
+    bound: &[bool],
+    pattern: &[Slot; 3],
+    p: u64,
+    chunk: &Chunk,
+) -> Vec<Vec<u64>> {
+    let mut result = vec![];
+    let mut emit = |row: &[u64], s: u64, o: u64| {
+        if let Some(new_row) = extend(row, bound, pattern, [s, p, o]) {
+            result.push(new_row);
+        }
+    };
+    let first = match rows.first() {
+        Some(row) => row,
+        None => return vec![],
+    };
+    let (pos, list) = if key(pattern[0], first, bound).is_some() {
+        (0, chunk.so_list())
+    } else if key(pattern[2], first, bound).is_some() {
+        (2, chunk.os_list())
+    } else {
+        for row in &rows {
+            for [s, o] in chunk.iter_so() {
+                emit(row, s, o);
+            }
+        }
+        return result;
+    };
+    // whether the pairs of `list` are [s, o] or [o, s]
+    let pair_to_so = |pair: &[u64; 2]| if pos == 0 { (pair[0], pair[1]) } else { (pair[1], pair[0]) };
+    rows.sort_unstable_by_key(|row| key(pattern[pos], row, bound));
+    let (mut rest_pairs, mut decoded) = match list {
+        PairList::Slice(pairs) => (pairs, None),
+        PairList::Compressed { .. } => {
+            let x = key(pattern[pos], &rows[0], bound).unwrap();
+            (&[][..], Some(list.iter_from(x).peekable()))
+        }
+    };
+    let mut compressed_group = vec![];
+    let mut rest_rows = &rows[..];
+    while let Some(row) = rest_rows.first() {
+        let x = key(pattern[pos], row, bound).unwrap();
+        let len = rest_rows.partition_point(|r| key(pattern[pos], r, bound) == Some(x));
+        let group = match &mut decoded {
+            None => {
+                rest_pairs = &rest_pairs[gallop(rest_pairs, x)..];
+                let group = pairs_with(rest_pairs, x);
+                rest_pairs = &rest_pairs[group.len()..];
+                group
+            }
+            Some(decoded) => {
+                while decoded.next_if(|p| p[0] < x).is_some() {}
+                compressed_group.clear();
+                compressed_group.extend(std::iter::from_fn(|| decoded.next_if(|p| p[0] == x)));
+                &compressed_group[..]
+            }
+        };
+        for row in &rest_rows[..len] {
+            for pair in group {
+                let (s, o) = pair_to_so(pair);
+                emit(row, s, o);
+            }
+        }
+        rest_rows = &rest_rows[len..];
+    }
+    result
+}
+
+/// Extend `row` with the bindings of `pattern` matching `triple`,
+/// unless they conflict with the constants of `pattern` or the `bound` variables of `row`.
+fn extend(row: &[u64], bound: &[bool], pattern: &[Slot; 3], triple: [u64; 3]) -> Option<Vec<u64>> {
+    let mut new_row = row.to_vec();
+    // the variables bound by a previous position of this pattern
+    let mut assigned = [usize::MAX; 3];
+    for (i, (slot, value)) in pattern.iter().zip(triple.iter()).enumerate() {
+        match slot {
+            Slot::Const(c) => {
+                if c != value {
+                    return None;
+                }
+            }
+            Slot::Var(v) => {
+                if bound[*v] || assigned[..i].contains(v) {
+                    if new_row[*v] != *value {
+                        return None;
+                    }
+                } else {
+                    new_row[*v] = *value;
+                    assigned[i] = *v;
+                }
+            }
+        }
+    }
+    Some(new_row)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extend() {
+        let x = Slot::Var(0);
+        let y = Slot::Var(1);
+        let p = Slot::Const(7);
+        assert_eq!(extend(&[0, 0], &[false, false], &[x, p, y], [1, 7, 2]), Some(vec![1, 2]));
+        assert_eq!(extend(&[1, 0], &[true, false], &[x, p, y], [1, 7, 2]), Some(vec![1, 2]));
+        assert_eq!(extend(&[3, 0], &[true, false], &[x, p, y], [1, 7, 2]), None);
+        assert_eq!(extend(&[0, 0], &[false, false], &[x, p, y], [1, 8, 2]), None);
+        // a variable repeated in a pattern must match the same value
+        assert_eq!(extend(&[0, 0], &[false, false], &[x, p, x], [1, 7, 2]), None);
+        assert_eq!(extend(&[0, 0], &[false, false], &[x, p, x], [1, 7, 1]), Some(vec![1, 0]));
+    }
+}
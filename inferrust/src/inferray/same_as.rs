@@ -0,0 +1,153 @@
+//! A union-find structure over resource indexes,
+//! used to canonicalize `owl:sameAs` cliques
+//! (see `RuleProfile::RDFSPlusCanonical`).
+//!
+//! Each clique (set of resources that are `owl:sameAs` each other)
+//! has a representative, which is the only member of the clique
+//! that appears in the `TripleStore`.
+//! Resources that are in no clique are their own representative.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use super::snapshot::*;
+use super::NodeDictionary;
+
+/// See [module documentation](./index.html).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct SameAsIndex {
+    /// the representative of every resource belonging to a clique
+    rep: HashMap<u64, u64>,
+    /// the sorted members of every clique, indexed by their representative
+    cliques: HashMap<u64, Vec<u64>>,
+}
+
+impl SameAsIndex {
+    /// Whether `idx` is the index of a resource (as opposed to a property).
+    ///
+    /// Only resources are canonicalized.
+    #[inline]
+    pub fn is_resource(idx: u64) -> bool {
+        idx > NodeDictionary::START_INDEX as u64
+    }
+
+    /// Whether this index contains no clique.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cliques.is_empty()
+    }
+
+    /// The representative of the clique of `idx`.
+    #[inline]
+    pub fn rep(&self, idx: u64) -> u64 {
+        self.rep.get(&idx).cloned().unwrap_or(idx)
+    }
+
+    /// The members of the clique of `idx`, if any.
+    #[inline]
+    pub fn clique(&self, idx: u64) -> Option<&[u64]> {
+        self.cliques.get(&self.rep(idx)).map(|c| &c[..])
+    }
+
+    /// The representative and size of the largest clique, if any.
+    pub fn largest_clique(&self) -> Option<(u64, usize)> {
+        self.cliques
+            .iter()
+            .map(|(rep, members)| (*rep, members.len()))
+            .max_by_key(|(_, size)| *size)
+    }
+
+    /// Merge the cliques of every pair of resources in `pairs`
+    /// (pairs involving a property are ignored).
+    ///
+    /// Return true if any clique has changed.
+    pub fn union(&mut self, pairs: &[[u64; 2]]) -> bool {
+        let mut changed = false;
+        for [a, b] in pairs {
+            if !Self::is_resource(*a) || !Self::is_resource(*b) {
+                continue;
+            }
+            let (ra, rb) = (self.rep(*a), self.rep(*b));
+            if ra == rb {
+                continue;
+            }
+            let ca = self.cliques.remove(&ra).unwrap_or_else(|| vec![ra]);
+            let cb = self.cliques.remove(&rb).unwrap_or_else(|| vec![rb]);
+            // the representative of the largest clique is kept,
+            // so that only the members of the smallest one are updated
+            let (new_rep, mut big, small) = if (ca.len(), rb) > (cb.len(), ra) {
+                (ra, ca, cb)
+            } else {
+                (rb, cb, ca)
+            };
+            for member in &small {
+                self.rep.insert(*member, new_rep);
+            }
+            self.rep.insert(new_rep, new_rep);
+            big.extend(small);
+            big.sort_unstable();
+            self.cliques.insert(new_rep, big);
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut pairs: Vec<[u64; 2]> = self.rep.iter().map(|(m, r)| [*m, *r]).collect();
+        pairs.sort_unstable();
+        write_pairs(w, &pairs)
+    }
+
+    pub fn load<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut this = Self::default();
+        for [member, rep] in read_pairs(r)? {
+            if !Self::is_resource(member) || !Self::is_resource(rep) {
+                return Err(invalid_data("non-resource index in sameAs clique"));
+            }
+            this.rep.insert(member, rep);
+            this.cliques.entry(rep).or_insert_with(Vec::new).push(member);
+        }
+        if this.cliques.iter().any(|(rep, members)| this.rep(*rep) != *rep || members.len() < 2) {
+            return Err(invalid_data("inconsistent sameAs cliques"));
+        }
+        Ok(this)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const R: u64 = NodeDictionary::START_INDEX as u64 + 1;
+
+    #[test]
+    fn test_union() {
+        let mut idx = SameAsIndex::default();
+        assert!(idx.is_empty());
+        assert!(idx.union(&[[R, R + 1], [R + 2, R + 3]]));
+        assert_eq!(idx.clique(R), Some(&[R, R + 1][..]));
+        assert_eq!(idx.clique(R + 4), None);
+        assert_eq!(idx.rep(R + 4), R + 4);
+        assert!(!idx.union(&[[R + 1, R], [R, R]]));
+        // properties are ignored
+        assert!(!idx.union(&[[R, R - 2]]));
+
+        assert!(idx.union(&[[R + 1, R + 3], [R + 5, R + 3]]));
+        let rep = idx.rep(R);
+        for i in &[R, R + 1, R + 2, R + 3, R + 5] {
+            assert_eq!(idx.rep(*i), rep);
+        }
+        assert_eq!(idx.clique(R + 5), Some(&[R, R + 1, R + 2, R + 3, R + 5][..]));
+        assert_eq!(idx.cliques.len(), 1);
+    }
+
+    #[test]
+    fn test_save_load() -> io::Result<()> {
+        let mut idx = SameAsIndex::default();
+        idx.union(&[[R, R + 1], [R + 2, R + 3], [R + 4, R + 3]]);
+        let mut buf = vec![];
+        idx.save(&mut buf)?;
+        assert_eq!(SameAsIndex::load(&mut &buf[..])?, idx);
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Low-level helpers for the binary snapshot format of `InfGraph`.
+//!
+//! A snapshot is made of:
+//! - a header: the `MAGIC` bytes, followed by the format `VERSION` (u32);
+//! - the `NodeDictionary` (properties, resources and remapped indexes);
+//! - the `TripleStore` (the sorted subject-object pairs of every chunk);
+//! - the `owl:sameAs` cliques, if they are canonicalized
+//!   (a flag byte, followed by the member-representative pairs).
+//!
+//! All integers are encoded in little-endian;
+//! strings are encoded as their length (u64) followed by their UTF-8 bytes.
+//!
+//! Lengths read from a snapshot are not trusted:
+//! they never preallocate more than `MAX_PREALLOC` items,
+//! and reaching the end of the snapshot before a length is exhausted
+//! is reported as `io::ErrorKind::InvalidData`.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+/// The first bytes of every snapshot.
+pub(crate) const MAGIC: &[u8; 8] = b"INFRRST\0";
+/// The version of the snapshot format; bump it on every incompatible change.
+pub(crate) const VERSION: u32 = 2;
+/// The maximal number of items preallocated from a length read in a snapshot;
+/// longer sequences grow as they are actually read.
+const MAX_PREALLOC: usize = 1 << 16;
+
+pub(crate) fn write_header<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())
+}
+
+pub(crate) fn read_header<R: Read>(r: &mut R) -> io::Result<()> {
+    let mut magic = [0; 8];
+    read_exact(r, &mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not an InfGraph snapshot"));
+    }
+    let mut version = [0; 4];
+    read_exact(r, &mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported snapshot version {} (expected {})",
+            version, VERSION
+        )));
+    }
+    Ok(())
+}
+
+#[inline]
+pub(crate) fn write_u8<W: Write>(w: &mut W, val: u8) -> io::Result<()> {
+    w.write_all(&[val])
+}
+
+#[inline]
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    read_exact(r, &mut buf)?;
+    Ok(buf[0])
+}
+
+#[inline]
+pub(crate) fn write_u64<W: Write>(w: &mut W, val: u64) -> io::Result<()> {
+    w.write_all(&val.to_le_bytes())
+}
+
+#[inline]
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    read_exact(r, &mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn write_str<W: Write>(w: &mut W, txt: &str) -> io::Result<()> {
+    write_u64(w, txt.len() as u64)?;
+    w.write_all(txt.as_bytes())
+}
+
+/// Read a length, i.e. a u64 that must fit in a `usize`.
+pub(crate) fn read_len<R: Read>(r: &mut R) -> io::Result<usize> {
+    let len = read_u64(r)?;
+    usize::try_from(len).map_err(|_| invalid_data(format!("invalid length {}", len)))
+}
+
+/// An empty vector, with a capacity suited to `len` items read from a snapshot.
+pub(crate) fn with_capacity<T>(len: usize) -> Vec<T> {
+    Vec::with_capacity(len.min(MAX_PREALLOC))
+}
+
+pub(crate) fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_len(r)?;
+    let mut buf = with_capacity(len);
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(invalid_data(format!(
+            "truncated string ({} bytes instead of {})",
+            buf.len(),
+            len
+        )));
+    }
+    String::from_utf8(buf).map_err(invalid_data)
+}
+
+pub(crate) fn write_pairs<W: Write>(w: &mut W, pairs: &[[u64; 2]]) -> io::Result<()> {
+    write_u64(w, pairs.len() as u64)?;
+    for [a, b] in pairs {
+        write_u64(w, *a)?;
+        write_u64(w, *b)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_pairs<R: Read>(r: &mut R) -> io::Result<Vec<[u64; 2]>> {
+    let len = read_len(r)?;
+    let mut pairs = with_capacity(len);
+    for _ in 0..len {
+        pairs.push([read_u64(r)?, read_u64(r)?]);
+    }
+    Ok(pairs)
+}
+
+/// Like `Read::read_exact`, but the end of the snapshot is reported as invalid data,
+/// since it means that the snapshot is truncated or that a length is corrupt.
+fn read_exact<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    r.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data("unexpected end of snapshot"),
+        _ => e,
+    })
+}
+
+pub(crate) fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() -> io::Result<()> {
+        let mut buf = vec![];
+        write_header(&mut buf)?;
+        write_u8(&mut buf, 42)?;
+        write_str(&mut buf, "hello wörld")?;
+        write_pairs(&mut buf, &[[1, 2], [3, u64::MAX]])?;
+
+        let mut r = &buf[..];
+        read_header(&mut r)?;
+        assert_eq!(read_u8(&mut r)?, 42);
+        assert_eq!(read_str(&mut r)?, "hello wörld");
+        assert_eq!(read_pairs(&mut r)?, vec![[1, 2], [3, u64::MAX]]);
+        assert!(r.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut r = &b"NOTASNAPSHOT"[..];
+        assert!(read_header(&mut r).is_err());
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(read_header(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_length() -> io::Result<()> {
+        let mut buf = vec![];
+        write_str(&mut buf, "hello")?;
+        buf[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = read_str(&mut &buf[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut buf = vec![];
+        write_pairs(&mut buf, &[[1, 2]])?;
+        buf[..8].copy_from_slice(&(u64::MAX >> 8).to_le_bytes());
+        let err = read_pairs(&mut &buf[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+}
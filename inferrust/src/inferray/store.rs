@@ -14,9 +14,18 @@
 /// [`TripleStore`]: ./struct.TripleStore.html
 /// [`Chunk`]: ../chunk/index.html
 
+use super::snapshot::*;
 use super::Chunk;
 use super::NodeDictionary;
 use crate::closure::*;
+use crate::interrupt::{Interrupt, Stop};
+use crate::par::*;
+
+use std::io::{self, Read, Write};
+#[cfg(feature = "mmap")]
+use std::{fs::File, io::BufReader, io::BufWriter, path::Path};
+#[cfg(feature = "mmap")]
+use super::mapped::*;
 
 /// See [module documentation](./index.html).
 #[derive(Default, PartialEq, Debug, Clone)]
@@ -42,9 +51,13 @@ impl TripleStore {
             }
             proto_chunks[op].push([is, io]);
         }
-        let chunks: Vec<Chunk> = proto_chunks.into_iter()
-            .map(|v| v[..].into())
-            .collect();
+        Self::from_chunks(proto_chunks.into_par_iter()
+            .map(Chunk::from_unsorted)
+            .collect())
+    }
+
+    /// Build a store from its chunks (indexed by predicate offset).
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
         let size = chunks.iter().map(|c| c.len()).sum();
         #[cfg(debug_assertions)]
         debug_assert!(chunks.iter().map(Chunk::is_sorted).all(|b| b));
@@ -63,6 +76,18 @@ impl TripleStore {
         &self.chunks
     }
 
+    /// The index of the predicate having the most triples in this store
+    /// (`0` if this store is empty).
+    pub fn largest_predicate(&self) -> u64 {
+        self.chunks
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, chunk)| chunk.len())
+            .filter(|(_, chunk)| !chunk.is_empty())
+            .map(|(offset, _)| NodeDictionary::offset_to_prop_idx(offset))
+            .unwrap_or(0)
+    }
+
     #[cfg(debug_assertions)]
     /// For tests only. Checks that this store is sorted.
     pub fn is_sorted(&self) -> bool {
@@ -71,22 +96,143 @@ impl TripleStore {
         self.chunks.iter().map(Chunk::len).sum::<usize>() == self.size
     }
 
-    /// Computes the transitive closure of the given property
-    pub(super) fn transitive_closure(&mut self, ip: u32) {
-        let offset = NodeDictionary::prop_idx_to_offset(ip as u64);
-        if offset >= self.chunks.len() || self.chunks[offset].is_empty() {
-            return
+    /// Computes the transitive closures of the given properties.
+    ///
+    /// Closures are computed concurrently (one per property),
+    /// and then written back into this store at once.
+    /// `started` and `finished` are called (possibly concurrently) with each closed property
+    /// and its number of triples, before and after its closure.
+    ///
+    /// `interrupt` is checked regularly while closing;
+    /// if it is triggered, this store is left unchanged.
+    ///
+    /// # Return value
+    /// Return the cycles (strongly connected components with more than one node)
+    /// found in each closed property, for the properties that have some.
+    pub(super) fn transitive_closures<S, F>(
+        &mut self,
+        ips: &[u32],
+        interrupt: &Interrupt,
+        started: S,
+        finished: F,
+    ) -> Result<Vec<(u32, Vec<Vec<u64>>)>, Stop>
+    where
+        S: Fn(u32, usize) + Sync,
+        F: Fn(u32, usize) + Sync,
+    {
+        let mut offsets: Vec<usize> = ips.iter()
+            .map(|ip| NodeDictionary::prop_idx_to_offset(*ip as u64))
+            .filter(|offset| *offset < self.chunks.len() && !self.chunks[*offset].is_empty())
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        let chunks = &self.chunks;
+        let closed: Option<Vec<(usize, Chunk, Vec<Vec<u64>>)>> = offsets.into_par_iter()
+            .map(|offset| {
+                if interrupt.check() {
+                    return None;
+                }
+                let ip = NodeDictionary::offset_to_prop_idx(offset) as u32;
+                started(ip, chunks[offset].len());
+                let closure = ClosureGraph::from(&chunks[offset].so())
+                    .close_until(|| interrupt.check())?;
+                let new_chunk = Chunk::from_sorted(closure.pairs())
+                    .expect("closure pairs should be sorted");
+                finished(ip, new_chunk.len());
+                Some((offset, new_chunk, closure.cycles()))
+            })
+            .collect();
+        interrupt.status()?;
+        let closed = closed.expect("closures are only given up when interrupted");
+        let mut cycles = vec![];
+        for (offset, new_chunk, chunk_cycles) in closed {
+            self.size += new_chunk.len();
+            self.size -= self.chunks[offset].len();
+            self.chunks[offset] = new_chunk;
+            if !chunk_cycles.is_empty() {
+                cycles.push((NodeDictionary::offset_to_prop_idx(offset) as u32, chunk_cycles));
+            }
+        }
+        Ok(cycles)
+    }
+
+    /// Extends the transitive closures of some properties with new pairs.
+    ///
+    /// `added` lists, for some properties whose chunk was transitively closed,
+    /// the (sorted) pairs that have been added to that chunk since then.
+    /// Rather than re-computing the closure from scratch,
+    /// only the paths going through those pairs are added (see `extend_closure`).
+    /// `interrupt`, `started` and `finished` are used as in `transitive_closures`.
+    ///
+    /// # Return value
+    /// Return, for the properties that have some,
+    /// the pairs of distinct nodes `[x, y]` that became related both ways
+    /// (i.e. that are now in the same cycle).
+    pub(super) fn extend_closures<S, F>(
+        &mut self,
+        added: &[(u32, Vec<[u64; 2]>)],
+        interrupt: &Interrupt,
+        started: S,
+        finished: F,
+    ) -> Result<Vec<(u32, Vec<Vec<u64>>)>, Stop>
+    where
+        S: Fn(u32, usize) + Sync,
+        F: Fn(u32, usize) + Sync,
+    {
+        let chunks = &self.chunks;
+        let added: Vec<&(u32, Vec<[u64; 2]>)> = added.iter()
+            .filter(|(ip, new)| {
+                !new.is_empty() && NodeDictionary::prop_idx_to_offset(*ip as u64) < chunks.len()
+            })
+            .collect();
+        let extended: Option<Vec<(usize, Chunk, Vec<Vec<u64>>)>> = added.into_par_iter()
+            .map(|(ip, new)| {
+                if interrupt.check() {
+                    return None;
+                }
+                let offset = NodeDictionary::prop_idx_to_offset(*ip as u64);
+                let chunk = &chunks[offset];
+                started(*ip, chunk.len());
+                let mut pairs = extend_closure(
+                    new,
+                    |x| chunk.so_with_s(x).map(|[_, y]| y),
+                    |x| chunk.os_with_o(x).map(|[_, y]| y),
+                    || interrupt.check(),
+                )?;
+                pairs.retain(|pair| !chunk.contains(pair));
+                let cycles = pairs.iter()
+                    .filter(|[x, y]| {
+                        x < y && (chunk.contains(&[*y, *x]) || pairs.binary_search(&[*y, *x]).is_ok())
+                    })
+                    .map(|[x, y]| vec![*x, *y])
+                    .collect();
+                finished(*ip, chunk.len() + pairs.len());
+                let new_chunk = Chunk::from_sorted(pairs)
+                    .expect("extended closure pairs should be sorted");
+                Some((offset, new_chunk, cycles))
+            })
+            .collect();
+        interrupt.status()?;
+        let extended = extended.expect("closures are only given up when interrupted");
+        let mut cycles = vec![];
+        for (offset, new_chunk, chunk_cycles) in extended {
+            self.size -= self.chunks[offset].len();
+            self.chunks[offset].merge(new_chunk);
+            self.size += self.chunks[offset].len();
+            if !chunk_cycles.is_empty() {
+                cycles.push((NodeDictionary::offset_to_prop_idx(offset) as u32, chunk_cycles));
+            }
         }
-        let old_chunk = &self.chunks[offset];
-        let old_len = old_chunk.len();
-        let closure = ClosureGraph::from(old_chunk.so().to_vec()).close();
-        let new_chunk: Chunk = closure.iter()
-            .flat_map(|(s, os)| os.iter().map(move |o| [*s, *o]))
-            .collect::<Vec<_>>()[..]
-            .into();
-        let new_len = new_chunk.len();
-        self.chunks[offset] = new_chunk;
-        self.size += new_len - old_len;
+        Ok(cycles)
+    }
+
+    /// Replace every subject and object `v` in this store by `f(v)`.
+    pub(super) fn map_values<F>(&mut self, f: F)
+    where
+        F: Fn(u64) -> u64 + Sync,
+    {
+        self.chunks.par_iter_mut().for_each(|chunk| chunk.map_values(&f));
+        self.size = self.chunks.iter().map(Chunk::len).sum();
     }
 
     /// Merge triples from another store into this one.
@@ -106,7 +252,7 @@ impl TripleStore {
         }
         if s_len > o_len {
             for chunk in &self.chunks[o_len..] {
-                self.size += chunk.so().len();
+                self.size += chunk.len();
             }
         } else if s_len < o_len {
             self.chunks.reserve(o_len-s_len);
@@ -117,18 +263,113 @@ impl TripleStore {
         }
     }
 
-    /// Update this chunk with the given translation map/
+    /// Compress all the chunks of this store (see `Chunk::compress`).
+    pub(super) fn compress(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.compress();
+        }
+    }
+
+    /// Write this store in the snapshot format (see `InfGraph::save`).
+    pub(super) fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u64(w, self.chunks.len() as u64)?;
+        for chunk in &self.chunks {
+            write_pairs(w, &chunk.so())?;
+        }
+        Ok(())
+    }
+
+    /// Read a store written by `save`,
+    /// whose indexes must all belong to `dictionary`.
+    pub(super) fn load<R: Read>(r: &mut R, dictionary: &NodeDictionary) -> io::Result<Self> {
+        let nb_chunks = read_len(r)?;
+        if nb_chunks > dictionary.property_count() {
+            return Err(invalid_data(format!("{} chunks for fewer properties", nb_chunks)));
+        }
+        let mut chunks = with_capacity(nb_chunks);
+        for _ in 0..nb_chunks {
+            let pairs = read_pairs(r)?;
+            if let Some(idx) = pairs.iter().flatten().find(|idx| !dictionary.contains_index(**idx)) {
+                return Err(invalid_data(format!("unknown index {}", idx)));
+            }
+            let chunk = Chunk::from_sorted(pairs)
+                .ok_or_else(|| invalid_data("unsorted chunk"))?;
+            chunks.push(chunk);
+        }
+        let size = chunks.iter().map(Chunk::len).sum();
+        Ok(Self { chunks, size })
+    }
+
+    /// Write this store as a set of pair files in `dir`,
+    /// that can be memory-mapped with `open_mapped`.
     ///
-    /// This is used when resources (index > START_INDEX)
-    /// have been requalified as properties (index < START_INDEX).
+    /// `dir` contains an index file (`chunks`) listing the size of each chunk,
+    /// and two files (`so-N` and `os-N`) for each non-empty chunk `N`.
+    #[cfg(feature = "mmap")]
+    pub(super) fn save_mapped(&self, dir: &Path) -> io::Result<()> {
+        let mut index = BufWriter::new(File::create(dir.join("chunks"))?);
+        write_u64(&mut index, self.chunks.len() as u64)?;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            write_u64(&mut index, chunk.len() as u64)?;
+            if !chunk.is_empty() {
+                write_pairs_file(&dir.join(format!("so-{}", i)), &chunk.so())?;
+                write_pairs_file(&dir.join(format!("os-{}", i)), &chunk.os())?;
+            }
+        }
+        index.flush()
+    }
+
+    /// Open a store written by `save_mapped`, whose indexes belong to `dictionary`.
+    ///
+    /// The pairs are not loaded in memory,
+    /// but memory-mapped from the files in `dir`.
+    /// Only the size of the files is checked, unless `verify` is true:
+    /// then the pairs are also checked to be sorted (since the joins rely on their order)
+    /// and to belong to `dictionary`, which reads every file once.
+    #[cfg(feature = "mmap")]
+    pub(super) fn open_mapped(dir: &Path, dictionary: &NodeDictionary, verify: bool) -> io::Result<Self> {
+        let mut index = BufReader::new(File::open(dir.join("chunks"))?);
+        let nb_chunks = read_len(&mut index)?;
+        if nb_chunks > dictionary.property_count() {
+            return Err(invalid_data(format!("{} chunks for fewer properties", nb_chunks)));
+        }
+        let mut chunks = with_capacity(nb_chunks);
+        for i in 0..nb_chunks {
+            let len = read_len(&mut index)?;
+            if len == 0 {
+                chunks.push(Chunk::empty());
+                continue;
+            }
+            let so = MappedPairs::open(&dir.join(format!("so-{}", i)))?;
+            let os = MappedPairs::open(&dir.join(format!("os-{}", i)))?;
+            if so.pairs().len() != len || os.pairs().len() != len {
+                return Err(invalid_data(format!("chunk {} has an unexpected size", i)));
+            }
+            if !verify {
+                chunks.push(Chunk::from_mapped_unchecked(so, os));
+                continue;
+            }
+            if let Some(idx) = so.pairs().iter().flatten().find(|idx| !dictionary.contains_index(**idx)) {
+                return Err(invalid_data(format!("unknown index {} in chunk {}", idx, i)));
+            }
+            let chunk = Chunk::from_mapped(so, os)
+                .ok_or_else(|| invalid_data(format!("chunk {} is not sorted", i)))?;
+            chunks.push(chunk);
+        }
+        let size = chunks.iter().map(Chunk::len).sum();
+        Ok(Self { chunks, size })
+    }
+
+    /// Update this store with the given translation map.
     ///
-    /// IMPORTANT: this method is not used yet,
-    /// but will be required later when rules such as EQ-REP-P
-    /// are correctly implemented.
-    #[allow(dead_code)]
+    /// This is used when resources (index > START_INDEX)
+    /// have been requalified as properties (index < START_INDEX),
+    /// e.g. when new triples are added to an already populated graph.
     pub(super) fn remap(&mut self, map: &[[u64; 2]]) {
         for chunk in &mut self.chunks {
             chunk.remap(map);
         }
+        // remapping may have created duplicates, which have been removed
+        self.size = self.chunks.iter().map(Chunk::len).sum();
     }
 }
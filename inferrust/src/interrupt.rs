@@ -0,0 +1,462 @@
+//! Cancellation, deadlines and limits for the reasoner (see `InfGraph::process_with`).
+//!
+//! Interruptions are checked between the rounds of the fixpoint,
+//! and regularly inside the scans performed by the rules
+//! and inside the computation of transitive closures.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "parallel")]
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use crate::observer::{Observer, Silent};
+
+/// A token used to cancel a reasoning process, possibly from another thread.
+///
+/// Clones of a token share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a new token, not yet cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every process using this token (or any of its clones).
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether this token has been cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Options controlling how `InfGraph::process_with` may be interrupted,
+/// how its progress is reported, and on which threads it runs.
+///
+/// The cancel token and the deadline also apply to queries (see `InfGraph::execute_with`).
+///
+/// Besides cancellation and deadlines,
+/// limits guard against ontologies that make the reasoner derive
+/// an unreasonable number of triples
+/// (e.g. a property wrongly declared `owl:FunctionalProperty`,
+/// making huge `owl:sameAs` cliques).
+///
+/// # Example
+/// ```
+/// use inferrust::*;
+/// use std::time::Duration;
+///
+/// let token = CancelToken::new();
+/// let options = ProcessOptions::new()
+///     .with_cancel_token(token.clone())
+///     .with_timeout(Duration::from_secs(10))
+///     .with_max_inferred_triples(10_000_000)
+///     .with_max_clique_size(1000);
+/// ```
+#[derive(Clone, Default)]
+pub struct ProcessOptions {
+    cancel: Option<CancelToken>,
+    deadline: Option<Instant>,
+    max_inferred: Option<usize>,
+    max_clique: Option<usize>,
+    max_iterations: Option<usize>,
+    observer: Option<Arc<dyn Observer>>,
+    #[cfg(feature = "parallel")]
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl ProcessOptions {
+    /// Options with neither cancel token, deadline nor limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interrupt processing when `token` is cancelled.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Interrupt processing if it is not finished at `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Interrupt processing if it is not finished after `timeout` (starting now).
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Stop processing if it infers more than `max` triples.
+    pub fn with_max_inferred_triples(mut self, max: usize) -> Self {
+        self.max_inferred = Some(max);
+        self
+    }
+
+    /// Stop processing if an `owl:sameAs` clique gets more than `max` members.
+    pub fn with_max_clique_size(mut self, max: usize) -> Self {
+        self.max_clique = Some(max);
+        self
+    }
+
+    /// Stop processing if the fixpoint is not reached after `max` rounds of rules.
+    ///
+    /// A round runs the rules of one stratum whose inputs changed,
+    /// so an iteration over all the strata counts as several rounds.
+    pub fn with_max_iterations(mut self, max: usize) -> Self {
+        self.max_iterations = Some(max);
+        self
+    }
+
+    /// Report the progress of processing to `observer`.
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Run processing in `pool`, rather than in rayon's global thread pool.
+    ///
+    /// This allows to isolate concurrent reasoning jobs from each other,
+    /// and to size them independently.
+    #[cfg(feature = "parallel")]
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Run processing in a dedicated pool of `num_threads` threads,
+    /// rather than in rayon's global thread pool.
+    ///
+    /// Fail if the threads of the pool can not be created.
+    #[cfg(feature = "parallel")]
+    pub fn with_num_threads(self, num_threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new().num_threads(num_threads).build()?;
+        Ok(self.with_thread_pool(Arc::new(pool)))
+    }
+
+    /// The thread pool in which processing runs, if not rayon's global one.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn thread_pool(&self) -> Option<&ThreadPool> {
+        self.pool.as_deref()
+    }
+
+    /// The observer to which progress is reported.
+    pub(crate) fn observer(&self) -> &dyn Observer {
+        match &self.observer {
+            Some(observer) => observer.as_ref(),
+            None => &Silent,
+        }
+    }
+}
+
+impl fmt::Debug for ProcessOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("ProcessOptions");
+        debug
+            .field("cancel", &self.cancel)
+            .field("deadline", &self.deadline)
+            .field("max_inferred", &self.max_inferred)
+            .field("max_clique", &self.max_clique)
+            .field("max_iterations", &self.max_iterations)
+            .field("observer", &self.observer.is_some());
+        #[cfg(feature = "parallel")]
+        debug.field("pool", &self.pool);
+        debug.finish()
+    }
+}
+
+/// The reason why `InfGraph::process_with` (or `InfGraph::execute_with`) was interrupted.
+///
+/// Terms are given in N-Triples syntax.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProcessError {
+    /// the cancel token was cancelled
+    Cancelled,
+    /// the deadline has passed
+    TimedOut,
+    /// more than `limit` triples were inferred,
+    /// `predicate` being the predicate of most of the last ones
+    TooManyInferences { limit: usize, predicate: String },
+    /// the `owl:sameAs` clique of `member` has more than `limit` members
+    CliqueTooLarge { limit: usize, member: String, size: usize },
+    /// the fixpoint was not reached after `limit` rounds of rules
+    /// (see `ProcessOptions::with_max_iterations`)
+    TooManyIterations { limit: usize },
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessError::Cancelled => write!(f, "processing was cancelled"),
+            ProcessError::TimedOut => write!(f, "processing timed out"),
+            ProcessError::TooManyInferences { limit, predicate } => write!(
+                f,
+                "reasoning inferred more than {} triples (mostly with predicate {})",
+                limit, predicate
+            ),
+            ProcessError::CliqueTooLarge { limit, member, size } => write!(
+                f,
+                "the owl:sameAs clique of {} has {} members (more than {})",
+                member, size, limit
+            ),
+            ProcessError::TooManyIterations { limit } => {
+                write!(f, "reasoning did not reach its fixpoint after {} rounds of rules", limit)
+            }
+        }
+    }
+}
+
+impl Error for ProcessError {}
+
+/// Why a reasoning process was stopped.
+///
+/// This is the internal counterpart of `ProcessError`,
+/// where terms are given by their index (see `InfGraph::process_error`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stop {
+    Cancelled,
+    TimedOut,
+    Inferences { limit: usize, predicate: u64 },
+    Clique { limit: usize, member: u64, size: usize },
+    Iterations { limit: usize },
+}
+
+/// The interruption state of a reasoning process, shared by all the threads running its rules.
+///
+/// Once an interruption is detected, it is remembered,
+/// so that the other threads stop as soon as they check it.
+#[derive(Debug)]
+pub(crate) struct Interrupt {
+    cancel: Option<CancelToken>,
+    deadline: Option<Instant>,
+    max_inferred: Option<usize>,
+    max_clique: Option<usize>,
+    max_iterations: Option<usize>,
+    /// the number of triples in the store when processing started
+    base_size: AtomicUsize,
+    /// the number of triples inferred before the current round
+    inferred: AtomicUsize,
+    /// the number of new triples buffered by the rules of the current round
+    pending: AtomicUsize,
+    /// the reason of the interruption, with its culprit, once it is detected
+    /// (set at once, so that no thread sees a partial diagnostic)
+    stop: OnceLock<Stop>,
+}
+
+static NEVER: Interrupt = Interrupt {
+    cancel: None,
+    deadline: None,
+    max_inferred: None,
+    max_clique: None,
+    max_iterations: None,
+    base_size: AtomicUsize::new(0),
+    inferred: AtomicUsize::new(0),
+    pending: AtomicUsize::new(0),
+    stop: OnceLock::new(),
+};
+
+impl Interrupt {
+    pub fn new(options: &ProcessOptions) -> Self {
+        Interrupt {
+            cancel: options.cancel.clone(),
+            deadline: options.deadline,
+            max_inferred: options.max_inferred,
+            max_clique: options.max_clique,
+            max_iterations: options.max_iterations,
+            base_size: AtomicUsize::new(0),
+            inferred: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            stop: OnceLock::new(),
+        }
+    }
+
+    /// An interruption state that is never interrupted.
+    #[inline]
+    pub fn never() -> &'static Self {
+        &NEVER
+    }
+
+    /// Whether the process has been interrupted.
+    pub fn check(&self) -> bool {
+        if self.stop.get().is_some() {
+            return true;
+        }
+        match (&self.cancel, self.deadline) {
+            (Some(token), _) if token.is_cancelled() => self.trip(Stop::Cancelled),
+            (_, Some(deadline)) if Instant::now() >= deadline => self.trip(Stop::TimedOut),
+            _ => false,
+        }
+    }
+
+    /// Record an interruption, unless one was already recorded, and return true.
+    fn trip(&self, stop: Stop) -> bool {
+        // the first interruption detected wins
+        let _ = self.stop.set(stop);
+        true
+    }
+
+    /// Fail with the reason of the interruption, if the process has been interrupted.
+    pub fn status(&self) -> Result<(), Stop> {
+        self.check();
+        match self.stop.get() {
+            Some(stop) => Err(*stop),
+            None => Ok(()),
+        }
+    }
+
+    /// Start counting inferred triples from a store of `size` triples.
+    pub fn start(&self, size: usize) {
+        if self.max_inferred.is_some() {
+            self.base_size.store(size, Ordering::SeqCst);
+            self.inferred.store(0, Ordering::SeqCst);
+            self.pending.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Start a round of rules (numbered from 1) on a store of `size` triples.
+    pub fn start_round(&self, round: usize, size: usize) -> Result<(), Stop> {
+        if let Some(max) = self.max_iterations {
+            if round > max {
+                self.trip(Stop::Iterations { limit: max });
+            }
+        }
+        if self.max_inferred.is_some() {
+            let base_size = self.base_size.load(Ordering::SeqCst);
+            self.inferred.store(size.saturating_sub(base_size), Ordering::SeqCst);
+            self.pending.store(0, Ordering::SeqCst);
+        }
+        self.status()
+    }
+
+    /// Account for `n` new triples buffered by a rule,
+    /// mostly with the given `predicate`.
+    ///
+    /// Return true if the process is (now) interrupted.
+    pub fn reserve(&self, n: usize, predicate: u64) -> bool {
+        if let Some(max) = self.max_inferred {
+            let pending = self.pending.fetch_add(n, Ordering::Relaxed) + n;
+            if self.inferred.load(Ordering::Relaxed) + pending > max {
+                return self.trip(Stop::Inferences { limit: max, predicate });
+            }
+        }
+        self.check()
+    }
+
+    /// Check that a store of `size` triples does not exceed the inference limit,
+    /// blaming `predicate` (computed lazily) otherwise.
+    pub fn check_size<F>(&self, size: usize, predicate: F) -> Result<(), Stop>
+    where
+        F: FnOnce() -> u64,
+    {
+        if let Some(max) = self.max_inferred {
+            if size.saturating_sub(self.base_size.load(Ordering::SeqCst)) > max {
+                self.trip(Stop::Inferences {
+                    limit: max,
+                    predicate: predicate(),
+                });
+            }
+        }
+        self.status()
+    }
+
+    /// Check that the `owl:sameAs` clique of `member`, with `size` members,
+    /// does not exceed the clique limit.
+    ///
+    /// Return true if the process is (now) interrupted.
+    pub fn check_clique(&self, member: u64, size: usize) -> bool {
+        match self.max_clique {
+            Some(max) if size > max => self.trip(Stop::Clique { limit: max, member, size }),
+            _ => self.stop.get().is_some(),
+        }
+    }
+
+    /// Whether a limit on the size of `owl:sameAs` cliques is set.
+    #[inline]
+    pub fn limits_cliques(&self) -> bool {
+        self.max_clique.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interrupt() {
+        assert_eq!(Interrupt::never().status(), Ok(()));
+
+        let token = CancelToken::new();
+        let interrupt = Interrupt::new(&ProcessOptions::new().with_cancel_token(token.clone()));
+        assert!(!interrupt.check());
+        token.clone().cancel();
+        assert!(interrupt.check());
+        assert_eq!(interrupt.status(), Err(Stop::Cancelled));
+
+        let interrupt = Interrupt::new(&ProcessOptions::new().with_deadline(Instant::now()));
+        assert_eq!(interrupt.status(), Err(Stop::TimedOut));
+        let interrupt = Interrupt::new(&ProcessOptions::new().with_timeout(Duration::from_secs(3600)));
+        assert_eq!(interrupt.status(), Ok(()));
+    }
+
+    #[test]
+    fn test_limits() {
+        let options = ProcessOptions::new()
+            .with_max_inferred_triples(100)
+            .with_max_clique_size(10)
+            .with_max_iterations(3);
+
+        let interrupt = Interrupt::new(&options);
+        interrupt.start(1000);
+        assert_eq!(interrupt.start_round(1, 1050), Ok(()));
+        assert!(!interrupt.reserve(40, 7));
+        assert!(!interrupt.check_clique(8, 10));
+        assert!(interrupt.reserve(20, 9));
+        // the first limit exceeded is reported
+        assert!(interrupt.check_clique(8, 11));
+        assert_eq!(interrupt.status(), Err(Stop::Inferences { limit: 100, predicate: 9 }));
+
+        let interrupt = Interrupt::new(&options);
+        interrupt.start(1000);
+        assert_eq!(interrupt.check_size(1100, || unreachable!()), Ok(()));
+        assert_eq!(
+            interrupt.check_size(1101, || 5),
+            Err(Stop::Inferences { limit: 100, predicate: 5 })
+        );
+
+        let interrupt = Interrupt::new(&options);
+        assert!(interrupt.check_clique(8, 11));
+        assert_eq!(interrupt.status(), Err(Stop::Clique { limit: 10, member: 8, size: 11 }));
+
+        let interrupt = Interrupt::new(&options);
+        assert_eq!(interrupt.start_round(3, 0), Ok(()));
+        assert_eq!(interrupt.start_round(4, 0), Err(Stop::Iterations { limit: 3 }));
+    }
+
+    #[test]
+    fn test_concurrent_trips() {
+        let interrupt = Interrupt::new(&ProcessOptions::new().with_max_clique_size(10));
+        std::thread::scope(|scope| {
+            for member in 0..8 {
+                let interrupt = &interrupt;
+                scope.spawn(move || {
+                    assert!(interrupt.check_clique(member, 11 + member as usize));
+                    // the diagnostic is always complete, whichever thread won
+                    match interrupt.status() {
+                        Err(Stop::Clique { limit: 10, member, size }) => assert_eq!(size, 11 + member as usize),
+                        status => panic!("unexpected status {:?}", status),
+                    }
+                });
+            }
+        });
+    }
+}
@@ -32,6 +32,19 @@
 //!
 //! [Sophia parser]:https://docs.rs/sophia/0.6.1/sophia/parser/index.html
 //!
+//! # Features
+//!
+//! - `parallel` (default): rules run on several threads, using [rayon];
+//!   without it, they run sequentially
+//!   (e.g. for `wasm32-unknown-unknown`, see the `inferrust-wasm` crate).
+//! - `mmap` (default): memory-mapped snapshots (see `InfGraph::save_mapped`).
+//! - `tracing`: `TracingObserver`, reporting progress with [tracing].
+//! - `server`: the `inferrust-server` binary, a SPARQL endpoint on localhost, e.g.
+//!   `cargo run --release --features server --bin inferrust-server -- --profile rdfs data.ttl`.
+//!
+//! [rayon]:https://docs.rs/rayon
+//! [tracing]:https://docs.rs/tracing
+//!
 //! ## References
 //! \[1] Julien Subercaze, Christophe Gravier, Jules Chevalier, Frédérique Laforest:
 //! Inferray: fast in-memory RDF inference. PVLDB 9(6): 468-479 (2016)
@@ -41,11 +54,24 @@
 
 mod closure;
 mod inferray;
+mod interrupt;
+mod loader;
+mod observer;
+mod par;
 mod rules;
+mod schema;
+mod sparql;
 mod utils;
 
-pub use inferray::InfGraph;
+pub use inferray::{InfGraph, PatternTerm, Solutions};
+pub use interrupt::{CancelToken, ProcessError, ProcessOptions};
+pub use loader::{split_ntriples, split_turtle};
+pub use observer::Observer;
+#[cfg(feature = "tracing")]
+pub use observer::TracingObserver;
 pub use rules::RuleProfile;
+pub use schema::CompiledSchema;
+pub use sparql::{Query, QueryResults, SparqlError};
 
 #[cfg(test)]
 mod test;
\ No newline at end of file
@@ -0,0 +1,242 @@
+//! Utility functions to split RDF documents,
+//! in order to load them in parallel with `InfGraph::new_unprocessed_pieces`.
+//!
+//! # Example
+//! ```
+//! use inferrust::*;
+//!
+//! let nt = r#"
+//! <http://example.org/bart> <http://example.org/mother> <http://example.org/marge> .
+//! <http://example.org/lisa> <http://example.org/mother> <http://example.org/marge> .
+//! "#;
+//! let sources = split_ntriples(nt, 2)
+//!     .into_iter()
+//!     .map(sophia::parser::nt::parse_str)
+//!     .collect();
+//! let graph = InfGraph::new_unprocessed_pieces(sources).unwrap();
+//! assert_eq!(graph.size(), 2);
+//! ```
+
+use std::collections::HashMap;
+
+/// Split an N-Triples document into (at most) `nb_pieces` pieces
+/// of similar sizes, each of which is a valid N-Triples document.
+pub fn split_ntriples(data: &str, nb_pieces: usize) -> Vec<&str> {
+    let nb_pieces = nb_pieces.max(1);
+    let target = ((data.len() + nb_pieces - 1) / nb_pieces).max(1);
+    let mut pieces = Vec::with_capacity(nb_pieces);
+    let mut start = 0;
+    while start < data.len() {
+        let from = (start + target - 1).min(data.len());
+        // NB: searching bytes, as `from` may not be a char boundary
+        let end = match data.as_bytes()[from..].iter().position(|b| *b == b'\n') {
+            Some(i) => from + i + 1,
+            None => data.len(),
+        };
+        pieces.push(&data[start..end]);
+        start = end;
+    }
+    pieces
+}
+
+/// Split a Turtle document into (at most) `nb_pieces` pieces
+/// of similar sizes, each of which is a valid Turtle document.
+///
+/// Pieces are cut between statements,
+/// and all the `@prefix`/`@base` directives of the document
+/// are copied at the beginning of each piece.
+///
+/// The document is returned as a single piece
+/// whenever splitting it could change its meaning, i.e. when
+/// - a prefix (or the base IRI) is declared several times with different values, or
+/// - it contains anonymous blank nodes (`[...]`) or collections (`(...)`),
+///   as the identifiers generated for them by the parser
+///   may collide across pieces.
+pub fn split_turtle(data: &str, nb_pieces: usize) -> Vec<String> {
+    let whole = || vec![data.to_string()];
+    if nb_pieces <= 1 {
+        return whole();
+    }
+    let (header, boundaries) = match scan_turtle(data) {
+        Some(scan) => scan,
+        None => return whole(),
+    };
+    let target = data.len() / nb_pieces + 1;
+    let mut pieces = Vec::with_capacity(nb_pieces);
+    let mut start = 0;
+    for b in boundaries.into_iter().chain(std::iter::once(data.len())) {
+        if b - start >= target || (b == data.len() && b > start) {
+            let mut piece = String::with_capacity(header.len() + b - start);
+            piece.push_str(&header);
+            piece.push_str(&data[start..b]);
+            pieces.push(piece);
+            start = b;
+        }
+    }
+    pieces
+}
+
+/// Scan a Turtle document.
+///
+/// Return the concatenation of its directives,
+/// and the positions where a new statement can start,
+/// or `None` if the document can not be safely split (see `split_turtle`).
+fn scan_turtle(data: &str) -> Option<(String, Vec<usize>)> {
+    let bytes = data.as_bytes();
+    let len = bytes.len();
+    let mut boundaries = vec![];
+    let mut stmt_start = 0;
+    // the last significant byte of the current statement
+    let mut last = b'.';
+    let mut i = 0;
+    while i < len {
+        match bytes[i] {
+            b'#' => {
+                while i < len && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'<' => {
+                i += data[i..].find('>')?;
+                last = b'>';
+            }
+            q @ b'"' | q @ b'\'' => {
+                let long = bytes[i..].starts_with(&[q, q, q]);
+                i += if long { 3 } else { 1 };
+                loop {
+                    match bytes.get(i)? {
+                        b'\\' => i += 1,
+                        b'\n' if !long => return None,
+                        c if *c == q && (!long || bytes[i..].starts_with(&[q, q, q])) => break,
+                        _ => (),
+                    }
+                    i += 1;
+                }
+                if long {
+                    i += 2;
+                }
+                last = q;
+            }
+            b'[' | b'(' => return None,
+            b'\n' => {
+                if last == b'.' || (last == b'>' && is_sparql_directive(&data[stmt_start..i])) {
+                    boundaries.push(i + 1);
+                    stmt_start = i + 1;
+                    last = b'.';
+                }
+            }
+            c if !c.is_ascii_whitespace() => last = c,
+            _ => (),
+        }
+        i += 1;
+    }
+
+    let mut header = String::new();
+    let mut declared = HashMap::new();
+    let mut start = 0;
+    for end in boundaries.iter().cloned().chain(std::iter::once(len)) {
+        let stmt = strip_comments(&data[start..end]);
+        if let Some(name) = directive_name(stmt) {
+            match declared.insert(name, stmt) {
+                Some(old) if old != stmt => return None,
+                Some(_) => (),
+                None => {
+                    header.push_str(stmt);
+                    header.push('\n');
+                }
+            }
+        }
+        start = end;
+    }
+    Some((header, boundaries))
+}
+
+/// Strip leading comments and whitespaces, and trailing whitespaces.
+fn strip_comments(stmt: &str) -> &str {
+    let mut stmt = stmt.trim();
+    while stmt.starts_with('#') {
+        stmt = match stmt.find('\n') {
+            Some(i) => stmt[i..].trim(),
+            None => "",
+        };
+    }
+    stmt
+}
+
+/// If `stmt` is a directive, return the name it declares
+/// (the prefix including its colon, or "" for the base IRI).
+fn directive_name(stmt: &str) -> Option<&str> {
+    let keyword_end = stmt.find(char::is_whitespace)?;
+    let rest = stmt[keyword_end..].trim_start();
+    match &stmt[..keyword_end] {
+        "@base" => Some(""),
+        "@prefix" => rest.find(':').map(|i| &rest[..=i]),
+        kw if kw.eq_ignore_ascii_case("BASE") => Some(""),
+        kw if kw.eq_ignore_ascii_case("PREFIX") => rest.find(':').map(|i| &rest[..=i]),
+        _ => None,
+    }
+}
+
+/// Whether `stmt` is a SPARQL-style directive (which are not terminated by a '.').
+fn is_sparql_directive(stmt: &str) -> bool {
+    let stmt = strip_comments(stmt);
+    let starts_with_kw = |kw: &str| {
+        stmt.get(..kw.len())
+            .map_or(false, |s| s.eq_ignore_ascii_case(kw))
+            && stmt[kw.len()..].starts_with(char::is_whitespace)
+    };
+    (starts_with_kw("BASE") || starts_with_kw("PREFIX")) && stmt.ends_with('>')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_ntriples() {
+        let nt = "<a> <b> <c> .\n<d> <e> <f> .\n<g> <h> <i> .\n";
+        assert_eq!(split_ntriples(nt, 1), vec![nt]);
+        let pieces = split_ntriples(nt, 3);
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces.concat(), nt);
+        assert!(pieces.iter().all(|p| p.ends_with(".\n")));
+        assert_eq!(split_ntriples("", 3), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_split_turtle() {
+        let ttl = r#"@prefix : <http://example.org/> .
+PREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>
+:a :b :c ;
+   :d "a string with a . at the end." .
+:e :f """a long
+string.
+""" .
+# a comment.
+:g rdfs:label 'it\'s' , <http://example.org/#h> . # another comment
+"#;
+        let header = "@prefix : <http://example.org/> .\nPREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>\n";
+        let pieces = split_turtle(ttl, 4);
+        assert!(pieces.len() > 1);
+        let mut bodies = String::new();
+        for piece in &pieces {
+            assert!(piece.starts_with(header));
+            let body = &piece[header.len()..];
+            assert!(body.ends_with(".\n") || body.ends_with(">\n") || body.ends_with("comment\n"));
+            assert_eq!(body.matches("\"\"\"").count() % 2, 0);
+            assert!(!body.contains(":c ;\n") || body.contains("at the end."));
+            bodies.push_str(body);
+        }
+        assert_eq!(bodies, ttl);
+        assert_eq!(split_turtle(ttl, 1), vec![ttl.to_string()]);
+    }
+
+    #[test]
+    fn test_split_turtle_unsafe() {
+        let ttl = "@prefix : <http://example.org/> .\n:a :b [ :c :d ] .\n:e :f :g .\n";
+        assert_eq!(split_turtle(ttl, 2), vec![ttl.to_string()]);
+        let ttl = "@prefix : <http://example.org/> .\n:a :b :c .\n@prefix : <http://example.com/> .\n:e :f :g .\n";
+        assert_eq!(split_turtle(ttl, 2), vec![ttl.to_string()]);
+    }
+}
@@ -0,0 +1,126 @@
+//! Progress reporting for loading and reasoning
+//! (see `InfGraph::new_unprocessed_observed` and `ProcessOptions::with_observer`).
+
+use sophia_term::ArcTerm;
+
+#[cfg(feature = "tracing")]
+use std::collections::HashMap;
+#[cfg(feature = "tracing")]
+use std::sync::Mutex;
+
+/// Receives the events of loading and reasoning, e.g. to display progress bars or logs.
+///
+/// Every method does nothing by default,
+/// so that implementations only override the events they are interested in.
+///
+/// Closure events may be sent concurrently from several threads
+/// (the closures of different properties are computed in parallel).
+pub trait Observer: Send + Sync {
+    /// `count` triples have been loaded so far.
+    ///
+    /// This is sent regularly during loading, and once loading is complete.
+    fn triples_loaded(&self, _count: usize) {}
+
+    /// The dictionary has been built, with `resources` resources and `properties` properties
+    /// (including the properties known by the reasoner).
+    fn dictionary_built(&self, _resources: usize, _properties: usize) {}
+
+    /// The transitive closure of `property`, which has `triples` triples, is starting.
+    fn closure_started(&self, _property: &ArcTerm, _triples: usize) {}
+
+    /// The transitive closure of `property` is finished, and has `triples` triples.
+    fn closure_finished(&self, _property: &ArcTerm, _triples: usize) {}
+
+    /// Iteration `iteration` of the fixpoint (numbered from 1) is starting.
+    fn iteration_started(&self, _iteration: usize) {}
+
+    /// Iteration `iteration` of the fixpoint is finished, and added `added` triples to the graph.
+    fn iteration_finished(&self, _iteration: usize, _added: usize) {}
+
+    /// The rules applied after the fixpoint are finished, and added `added` triples to the graph.
+    fn after_rules_finished(&self, _added: usize) {}
+}
+
+/// An observer ignoring every event.
+pub(crate) struct Silent;
+
+impl Observer for Silent {}
+
+/// An observer emitting the events as `tracing` events, at the `INFO` level
+/// (requires the `tracing` feature).
+///
+/// Closures and fixpoint iterations are also spans,
+/// recording the number of triples they produced when they close.
+/// Those spans are not entered, as the work they cover runs on other threads,
+/// but the events marking their start and end are emitted inside them,
+/// with the same fields (so that they are displayed even by subscribers ignoring spans).
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default)]
+pub struct TracingObserver {
+    /// the spans of the running closures, indexed by property
+    closures: Mutex<HashMap<String, tracing::Span>>,
+    /// the span of the running iteration, if any
+    iteration: Mutex<Option<tracing::Span>>,
+}
+
+#[cfg(feature = "tracing")]
+impl TracingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Observer for TracingObserver {
+    fn triples_loaded(&self, count: usize) {
+        tracing::info!(count, "triples loaded");
+    }
+
+    fn dictionary_built(&self, resources: usize, properties: usize) {
+        tracing::info!(resources, properties, "dictionary built");
+    }
+
+    fn closure_started(&self, property: &ArcTerm, triples: usize) {
+        let span = tracing::info_span!(
+            "closure",
+            property = %property,
+            triples,
+            closed_triples = tracing::field::Empty,
+        );
+        tracing::info!(parent: &span, property = %property, triples, "closure started");
+        self.closures.lock().unwrap().insert(property.to_string(), span);
+    }
+
+    fn closure_finished(&self, property: &ArcTerm, triples: usize) {
+        // the span closes when dropped
+        let span = self.closures.lock().unwrap().remove(&property.to_string());
+        match span {
+            Some(span) => {
+                span.record("closed_triples", &triples);
+                tracing::info!(parent: &span, property = %property, closed_triples = triples, "closure finished");
+            }
+            None => tracing::info!(property = %property, closed_triples = triples, "closure finished"),
+        }
+    }
+
+    fn iteration_started(&self, iteration: usize) {
+        let span = tracing::info_span!("iteration", iteration, added = tracing::field::Empty);
+        tracing::info!(parent: &span, iteration, "iteration started");
+        *self.iteration.lock().unwrap() = Some(span);
+    }
+
+    fn iteration_finished(&self, iteration: usize, added: usize) {
+        let span = self.iteration.lock().unwrap().take();
+        match span {
+            Some(span) => {
+                span.record("added", &added);
+                tracing::info!(parent: &span, iteration, added, "iteration finished");
+            }
+            None => tracing::info!(iteration, added, "iteration finished"),
+        }
+    }
+
+    fn after_rules_finished(&self, added: usize) {
+        tracing::info!(added, "after rules done");
+    }
+}
@@ -0,0 +1,137 @@
+//! Parallel iterators, provided by rayon when the `parallel` feature is enabled
+//! (the default), and by sequential stand-ins otherwise
+//! (e.g. for `wasm32-unknown-unknown`, which has no threads).
+//!
+//! The stand-ins only cover the part of rayon's API used in this crate,
+//! with the same semantics.
+
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::prelude::*;
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use self::sequential::*;
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    use std::iter::{FlatMap, FromIterator, Map, Zip};
+
+    /// A sequential stand-in for rayon's parallel iterators.
+    pub struct SeqIter<I>(I);
+
+    impl<I: Iterator> SeqIter<I> {
+        pub fn map<B, F>(self, f: F) -> SeqIter<Map<I, F>>
+        where
+            F: FnMut(I::Item) -> B,
+        {
+            SeqIter(self.0.map(f))
+        }
+
+        pub fn zip<J>(self, other: J) -> SeqIter<Zip<I, J::IntoIter>>
+        where
+            J: IntoIterator,
+        {
+            SeqIter(self.0.zip(other))
+        }
+
+        pub fn flat_map_iter<U, F>(self, f: F) -> SeqIter<FlatMap<I, U, F>>
+        where
+            U: IntoIterator,
+            F: FnMut(I::Item) -> U,
+        {
+            SeqIter(self.0.flat_map(f))
+        }
+
+        pub fn for_each<F>(self, f: F)
+        where
+            F: FnMut(I::Item),
+        {
+            self.0.for_each(f)
+        }
+
+        pub fn collect<C>(self) -> C
+        where
+            C: FromIterator<I::Item>,
+        {
+            self.0.collect()
+        }
+
+        /// Like rayon's `reduce`, `identity` is used when this iterator is empty.
+        pub fn reduce<ID, OP>(self, identity: ID, op: OP) -> I::Item
+        where
+            ID: Fn() -> I::Item,
+            OP: Fn(I::Item, I::Item) -> I::Item,
+        {
+            let mut iter = self.0;
+            match iter.next() {
+                Some(first) => iter.fold(first, op),
+                None => identity(),
+            }
+        }
+    }
+
+    pub trait IntoParallelIterator {
+        type Iter: Iterator;
+        fn into_par_iter(self) -> SeqIter<Self::Iter>;
+    }
+
+    impl<T: IntoIterator> IntoParallelIterator for T {
+        type Iter = T::IntoIter;
+        fn into_par_iter(self) -> SeqIter<Self::Iter> {
+            SeqIter(self.into_iter())
+        }
+    }
+
+    pub trait IntoParallelRefIterator<'data> {
+        type Iter: Iterator;
+        fn par_iter(&'data self) -> SeqIter<Self::Iter>;
+    }
+
+    impl<'data, T: 'data + ?Sized> IntoParallelRefIterator<'data> for T
+    where
+        &'data T: IntoIterator,
+    {
+        type Iter = <&'data T as IntoIterator>::IntoIter;
+        fn par_iter(&'data self) -> SeqIter<Self::Iter> {
+            SeqIter(self.into_iter())
+        }
+    }
+
+    pub trait IntoParallelRefMutIterator<'data> {
+        type Iter: Iterator;
+        fn par_iter_mut(&'data mut self) -> SeqIter<Self::Iter>;
+    }
+
+    impl<'data, T: 'data + ?Sized> IntoParallelRefMutIterator<'data> for T
+    where
+        &'data mut T: IntoIterator,
+    {
+        type Iter = <&'data mut T as IntoIterator>::IntoIter;
+        fn par_iter_mut(&'data mut self) -> SeqIter<Self::Iter> {
+            SeqIter(self.into_iter())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_par() {
+        let v: Vec<u64> = (0..100).collect();
+        let doubled: Vec<u64> = v.par_iter().map(|x| x * 2).collect();
+        assert_eq!(doubled[99], 198);
+        let sum = v.clone().into_par_iter().map(|x| x).reduce(|| 0, |a, b| a + b);
+        assert_eq!(sum, 4950);
+        let empty: Vec<u64> = vec![];
+        assert_eq!(empty.into_par_iter().reduce(|| 42, |a, b| a + b), 42);
+        let mut w = v.clone();
+        w.par_iter_mut().for_each(|x| *x += 1);
+        let pairs: Vec<u64> = v
+            .into_par_iter()
+            .zip(w)
+            .flat_map_iter(|(a, b)| vec![a, b])
+            .collect();
+        assert_eq!(&pairs[..4], &[0, 1, 1, 2]);
+    }
+}
@@ -4,6 +4,9 @@
 mod _rules;
 pub(crate) use self::_rules::*;
 
+mod join;
+pub(crate) use self::join::*;
+
 mod profiles;
 pub use self::profiles::*;
 
@@ -1,31 +1,237 @@
+use crate::closure::ClosureGraph;
 use crate::inferray::*;
+use crate::interrupt::{Interrupt, Stop};
+use crate::observer::{Observer, Silent};
+use crate::par::*;
+use crate::rules::ClosureProfile;
 
-use rayon::prelude::*;
+use std::ops::Deref;
 
 /// Type aliases to unify all the rules of the reasoner
-pub(crate) type Rule = fn(&TripleStore) -> RuleResult;
-/// Type aliases for the result of a rule (actually a vector)
-pub(crate) type RuleResult = Vec<[u64; 3]>;
+pub(crate) type Rule = fn(RuleInput) -> RuleResult;
+
+/// The input of a rule: the store it reads,
+/// and the interruption state of the reasoning process,
+/// that long scans should check regularly (see `RuleInput::interrupted`).
+///
+/// A `RuleInput` dereferences to its store.
+#[derive(Clone, Copy)]
+pub(crate) struct RuleInput<'a> {
+    pub ts: &'a TripleStore,
+    pub interrupt: &'a Interrupt,
+}
+
+impl<'a> RuleInput<'a> {
+    pub fn new(ts: &'a TripleStore, interrupt: &'a Interrupt) -> Self {
+        Self { ts, interrupt }
+    }
+
+    /// Whether the reasoning process has been interrupted,
+    /// in which case the rule should return as soon as possible
+    /// (its result will be discarded).
+    #[inline]
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.check()
+    }
+}
+
+impl<'a> Deref for RuleInput<'a> {
+    type Target = TripleStore;
+
+    #[inline]
+    fn deref(&self) -> &TripleStore {
+        self.ts
+    }
+}
+
+/// The result of a rule: the new (subject-object) pairs of each predicate.
+///
+/// Pairs that are already present in the store read by the rule
+/// are discarded as soon as they are pushed,
+/// so that only new pairs are buffered.
+/// Rules scanning in parallel fill one `RuleResult` per thread,
+/// and reduce them with `RuleResult::concat`.
+///
+/// New pairs are accounted for in the inference budget
+/// of the reasoning process (see `Interrupt::reserve`) every `BUDGET_STEP` pairs,
+/// blaming the predicate having most of them;
+/// once the process is interrupted, pushed pairs are ignored.
+///
+/// The budget is thus approximate:
+/// each result may exceed it by less than `BUDGET_STEP` pairs before being stopped,
+/// and pairs pushed by several rules (or threads) are charged several times.
+/// The exact number of new triples is checked once the results are merged
+/// (see `InfGraph::check_new_triples`).
+pub(crate) struct RuleResult<'a> {
+    /// the store read by the rule
+    ts: &'a TripleStore,
+    /// the interruption state of the reasoning process
+    interrupt: &'a Interrupt,
+    /// the buffered pairs of each predicate, indexed by chunk offset
+    buffers: Vec<Vec<[u64; 2]>>,
+    /// the total number of buffered pairs
+    len: usize,
+    /// the number of pairs of each buffer already accounted for in the budget
+    charged: Vec<usize>,
+    /// whether the process was found interrupted
+    stopped: bool,
+}
+
+/// The number of new pairs that a `RuleResult` accounts for at once.
+const BUDGET_STEP: usize = 1 << 12;
+
+impl<'a> RuleResult<'a> {
+    /// An empty result for a rule reading `input`.
+    pub fn new(input: RuleInput<'a>) -> Self {
+        Self {
+            ts: input.ts,
+            interrupt: input.interrupt,
+            buffers: vec![],
+            len: 0,
+            charged: vec![],
+            stopped: false,
+        }
+    }
+
+    /// Add the triple `[s, p, o]` to this result, unless it is already in the store
+    /// (or `p` is not a property).
+    #[inline]
+    pub fn push(&mut self, triple: [u64; 3]) {
+        let [s, p, o] = triple;
+        // only properties can be predicates
+        if p >= NodeDictionary::START_INDEX as u64 || self.stopped {
+            return;
+        }
+        let offset = NodeDictionary::prop_idx_to_offset(p);
+        let pair = [s, o];
+        if let Some(chunk) = self.ts.chunks().get(offset) {
+            if chunk.contains(&pair) {
+                return;
+            }
+        }
+        if offset >= self.buffers.len() {
+            self.buffers.resize_with(offset + 1, Vec::new);
+        }
+        let buffer = &mut self.buffers[offset];
+        // rules often output the same triple several times in a row
+        if buffer.last() == Some(&pair) {
+            return;
+        }
+        buffer.push(pair);
+        self.len += 1;
+        if self.len % BUDGET_STEP == 0 {
+            self.charge();
+        }
+    }
+
+    /// Account for the pairs buffered since the last charge in the inference budget,
+    /// blaming the predicate having most of them.
+    fn charge(&mut self) {
+        if self.charged.len() < self.buffers.len() {
+            self.charged.resize(self.buffers.len(), 0);
+        }
+        let mut total = 0;
+        // (number of uncharged pairs, offset) of the predicate having most of them
+        let mut culprit = (0, 0);
+        for (offset, (buffer, charged)) in self.buffers.iter().zip(self.charged.iter_mut()).enumerate() {
+            let uncharged = buffer.len() - *charged;
+            if uncharged > culprit.0 {
+                culprit = (uncharged, offset);
+            }
+            total += uncharged;
+            *charged = buffer.len();
+        }
+        if total > 0 {
+            let predicate = NodeDictionary::offset_to_prop_idx(culprit.1);
+            self.stopped = self.interrupt.reserve(total, predicate);
+        }
+    }
+
+    /// Check that the `owl:sameAs` clique of `member`, with at least `size` members,
+    /// does not exceed the clique limit.
+    ///
+    /// Return true if the process is (now) interrupted, in which case nothing more is pushed.
+    pub fn check_clique(&mut self, member: u64, size: usize) -> bool {
+        self.stopped |= self.interrupt.check_clique(member, size);
+        self.stopped
+    }
+
+    /// The number of buffered pairs (possibly including duplicates).
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no pair was buffered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Merge two results of rules reading the same store
+    /// (useful to reduce parallel results).
+    pub fn concat(mut a: Self, mut b: Self) -> Self {
+        if a.len < b.len {
+            std::mem::swap(&mut a, &mut b);
+        }
+        if a.buffers.len() < b.buffers.len() {
+            a.buffers.resize_with(b.buffers.len(), Vec::new);
+        }
+        if a.charged.len() < b.charged.len() {
+            a.charged.resize(b.charged.len(), 0);
+        }
+        for (charged, other) in a.charged.iter_mut().zip(b.charged) {
+            *charged += other;
+        }
+        a.len += b.len;
+        a.stopped |= b.stopped;
+        for (buffer, mut other) in a.buffers.iter_mut().zip(b.buffers) {
+            if buffer.is_empty() {
+                *buffer = other;
+            } else {
+                buffer.append(&mut other);
+            }
+        }
+        a
+    }
+
+    /// Sort and deduplicate the buffered pairs into a store
+    /// (one chunk per predicate, built in parallel).
+    pub fn into_store(self) -> TripleStore {
+        TripleStore::from_chunks(
+            self.buffers
+                .into_par_iter()
+                .map(Chunk::from_unsorted)
+                .collect(),
+        )
+    }
+}
 
 /// A set of Rule, which can be applied on a InfGraph
 pub(crate) trait RuleSet {
     /// Process this ruleset, possibly using multiple threads
-    fn process(&self, graph: &mut InfGraph);
+    ///
+    /// If `interrupt` is triggered, the triples inferred by the interrupted rules are discarded.
+    fn process(&self, graph: &mut InfGraph, interrupt: &Interrupt) -> Result<(), Stop>;
     fn is_empty(&self) -> bool;
 }
 
 impl RuleSet for Vec<Box<Rule>> {
-    fn process(&self, graph: &mut InfGraph) {
+    fn process(&self, graph: &mut InfGraph, interrupt: &Interrupt) -> Result<(), Stop> {
         if self.is_empty() {
-            return;
+            return Ok(());
         }
-        let ts = graph.store();
-        let results: Vec<_> = self.par_iter().map(|rule| rule(ts)).collect();
-        graph.merge_store(TripleStore::new(
-            results
-            .into_iter()
-            .flat_map(|a| a.into_iter())
-        ));
+        interrupt.start_round(0, graph.size())?;
+        let input = RuleInput::new(graph.store(), interrupt);
+        let result = self
+            .par_iter()
+            .map(|rule| rule(input))
+            .reduce(|| RuleResult::new(input), RuleResult::concat);
+        interrupt.status()?;
+        let store = result.into_store();
+        graph.check_new_triples(&store, interrupt)?;
+        graph.merge_store(store);
+        graph.check_limits(interrupt)
     }
 
     fn is_empty(&self) -> bool {
@@ -33,26 +239,353 @@ impl RuleSet for Vec<Box<Rule>> {
     }
 }
 
+/// The predicates read or written by a rule.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Predicates {
+    /// only the given property indexes
+    Only(&'static [u32]),
+    /// the given property index, and the properties listed in its chunk
+    /// (e.g. the properties having a domain, for `rdfs:domain`),
+    /// which are only known while reasoning
+    Schema(u32),
+    /// any predicate
+    All,
+}
+
+impl Predicates {
+    /// Whether `self` and `other` have at least one predicate in common.
+    ///
+    /// Since the properties listed in the chunk of a `Schema` predicate are not known in advance,
+    /// it may have any predicate in common with `other`.
+    fn overlaps(&self, other: &Predicates) -> bool {
+        match (self, other) {
+            (Predicates::Only(a), Predicates::Only(b)) => a.iter().any(|p| b.contains(p)),
+            (Predicates::Only(a), _) | (_, Predicates::Only(a)) => !a.is_empty(),
+            _ => true,
+        }
+    }
+}
+
+/// The properties listed (as subject or object) in the chunk of `p`,
+/// i.e. the properties whose chunks are read by a rule reading `Predicates::Schema(p)`.
+fn listed_properties(store: &TripleStore, p: u32) -> Vec<u64> {
+    let start = NodeDictionary::START_INDEX as u64;
+    let chunk = match store.chunks().get(NodeDictionary::prop_idx_to_offset(p as u64)) {
+        Some(chunk) => chunk,
+        None => return vec![],
+    };
+    // property indexes are lower than resource indexes, so they come first in both lists
+    let subjects = chunk.so_list().iter().map(|[s, _]| s);
+    let objects = chunk.os_list().iter().map(|[o, _]| o);
+    subjects
+        .take_while(|s| *s < start)
+        .chain(objects.take_while(|o| *o < start))
+        .collect()
+}
+
+/// A rule of a `FixPointRuleSet`, with the predicates it reads and writes.
+#[derive(Clone)]
+pub(crate) struct ScheduledRule {
+    pub rule: Box<Rule>,
+    pub reads: Predicates,
+    pub writes: Predicates,
+}
+
+impl ScheduledRule {
+    pub fn new(rule: Rule, reads: Predicates, writes: Predicates) -> Self {
+        Self {
+            rule: Box::new(rule),
+            reads,
+            writes,
+        }
+    }
+}
+
 /// A specific ruleset (run rules until fixpoint is reached)
+///
+/// Rules are stratified according to the predicates they read and write:
+/// a stratum is run until its fixpoint before the next ones,
+/// and a rule is only run again if one of the chunks it reads has changed
+/// since it last ran.
 pub(crate) struct FixPointRuleSet {
-    pub rules: Vec<Box<Rule>>,
+    pub rules: Vec<ScheduledRule>,
 }
 
-impl RuleSet for FixPointRuleSet {
-    fn process(&self, graph: &mut InfGraph) {
+impl FixPointRuleSet {
+    /// Group the rules in strata (lists of rule indexes),
+    /// in such an order that no rule writes a predicate read by a rule of a previous stratum
+    /// (unless they are in the same stratum).
+    fn strata(&self) -> Vec<Vec<usize>> {
+        let n = self.rules.len();
+        let mut edges = vec![];
+        for (i, r1) in self.rules.iter().enumerate() {
+            // self-loops ensure that every rule is a node of the graph
+            edges.push([i as u64, i as u64]);
+            for (j, r2) in self.rules.iter().enumerate() {
+                if i != j && r1.writes.overlaps(&r2.reads) {
+                    edges.push([i as u64, j as u64]);
+                }
+            }
+        }
+        edges.sort_unstable();
+        let graph = ClosureGraph::from(&edges);
+        let (comp, members) = graph.components();
+        debug_assert_eq!(comp.len(), n);
+        // components are numbered in reverse topological order,
+        // and the local identifiers of the graph are the rule indexes
+        members
+            .into_iter()
+            .rev()
+            .map(|mut m| {
+                m.sort_unstable();
+                m.into_iter().map(|i| i as usize).collect()
+            })
+            .collect()
+    }
+
+    /// Run rules until fixpoint is reached,
+    /// maintaining the transitive closures prescribed by `cl_profile`.
+    ///
+    /// After each round of rules, the closures of the properties
+    /// that received new triples are extended with the paths through those triples,
+    /// and those of the properties newly declared transitive are computed
+    /// (see `InfGraph::reclose`), rather than being left to slower rules such as PRP-TRP.
+    /// If `owl:sameAs` cliques are canonicalized, this is also done after each round.
+    ///
+    /// Every round is reported to `observer`, as an iteration of the fixpoint.
+    ///
+    /// `interrupt` is checked before each round, and regularly by the rules.
+    /// If it is triggered (including by a limit) while the rules run,
+    /// the triples inferred during the current round are discarded,
+    /// so the graph contains the results of the previous rounds only;
+    /// if it is triggered while re-closing, those triples are kept,
+    /// but the closures may be incomplete.
+    pub(crate) fn process_closing(
+        &self,
+        graph: &mut InfGraph,
+        cl_profile: &ClosureProfile,
+        interrupt: &Interrupt,
+        observer: &dyn Observer,
+    ) -> Result<(), Stop> {
         if self.rules.is_empty() {
-            return;
+            return Ok(());
         }
-        let mut size = graph.size();
-        let mut prev_size = size + 1;
-        while prev_size != size {
-            prev_size = size;
-            self.rules.process(graph);
-            size = graph.size();
+        let strata = self.strata();
+        // rounds are numbered from 1;
+        // last_run[r] is the last round where rule r ran (0 if never),
+        // changed_at[o] is the last round where chunk o changed
+        let mut round = 0;
+        let mut last_run = vec![0; self.rules.len()];
+        let mut changed_at: Vec<usize> = vec![];
+        let mut all_changed_at = 0;
+        let mut ran = true;
+        while ran {
+            ran = false;
+            for stratum in &strata {
+                loop {
+                    let ready: Vec<usize> = stratum
+                        .iter()
+                        .cloned()
+                        .filter(|r| {
+                            let changed = |p: u64| {
+                                let o = NodeDictionary::prop_idx_to_offset(p);
+                                changed_at.get(o).cloned().unwrap_or(0) >= last_run[*r]
+                            };
+                            last_run[*r] == 0
+                                || match self.rules[*r].reads {
+                                    Predicates::All => all_changed_at >= last_run[*r],
+                                    Predicates::Only(preds) => preds.iter().any(|p| changed(*p as u64)),
+                                    Predicates::Schema(p) => {
+                                        changed(p as u64)
+                                            || listed_properties(graph.store(), p).into_iter().any(changed)
+                                    }
+                                }
+                        })
+                        .collect();
+                    if ready.is_empty() {
+                        break;
+                    }
+                    ran = true;
+                    round += 1;
+                    interrupt.start_round(round, graph.size())?;
+                    observer.iteration_started(round);
+                    for r in &ready {
+                        last_run[*r] = round;
+                    }
+
+                    let size = graph.size();
+                    let closed = graph.closed_properties(cl_profile);
+                    let chunk_lens: Vec<usize> = graph.store().chunks().iter().map(|c| c.len()).collect();
+                    let input = RuleInput::new(graph.store(), interrupt);
+                    let result = ready
+                        .par_iter()
+                        .map(|r| (self.rules[*r].rule)(input))
+                        .reduce(|| RuleResult::new(input), RuleResult::concat);
+                    interrupt.status()?;
+                    let store = result.into_store();
+                    graph.check_new_triples(&store, interrupt)?;
+                    let added = graph.added_pairs(&closed, &store);
+                    graph.merge_store(store);
+                    let canonicalized = graph.canonicalize_same_as();
+                    if canonicalized {
+                        // every chunk may have changed
+                        graph.reclose(cl_profile, &[], &[], interrupt, observer)?;
+                    } else if graph.size() != size {
+                        graph.reclose(cl_profile, &closed, &added, interrupt, observer)?;
+                    }
+                    graph.check_limits(interrupt)?;
+                    // canonicalizing owl:sameAs cliques may remove triples
+                    observer.iteration_finished(round, graph.size().saturating_sub(size));
+
+                    let chunks = graph.store().chunks();
+                    if changed_at.len() < chunks.len() {
+                        changed_at.resize(chunks.len(), 0);
+                    }
+                    for (o, chunk) in chunks.iter().enumerate() {
+                        if canonicalized || chunk_lens.get(o).cloned().unwrap_or(0) != chunk.len() {
+                            changed_at[o] = round;
+                            all_changed_at = round;
+                        }
+                    }
+                }
+            }
         }
+        Ok(())
+    }
+}
+
+impl RuleSet for FixPointRuleSet {
+    fn process(&self, graph: &mut InfGraph, interrupt: &Interrupt) -> Result<(), Stop> {
+        let no_closure = ClosureProfile {
+            on_sa: false,
+            on_sco: false,
+            on_spo: false,
+            on_trp: false,
+            on_eq: false,
+        };
+        self.process_closing(graph, &no_closure, interrupt, &Silent)
     }
 
     fn is_empty(&self) -> bool {
         self.rules.is_empty()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interrupt::ProcessOptions;
+    use crate::rules::{RuleProfile, PRP_INV_1_2, PRP_TRP};
+    use sophia::parser::turtle::parse_str as parse_ttl;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn nop(input: RuleInput) -> RuleResult {
+        RuleResult::new(input)
+    }
+
+    static INV_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counted_inv(input: RuleInput) -> RuleResult {
+        INV_RUNS.fetch_add(1, Ordering::Relaxed);
+        PRP_INV_1_2(input)
+    }
+
+    #[test]
+    fn test_rule_result() {
+        let ip = NodeDictionary::rdftype as u64;
+        let ts = TripleStore::new(vec![[10, ip, 20], [11, ip, 21]]);
+        let input = RuleInput::new(&ts, Interrupt::never());
+        let mut a = RuleResult::new(input);
+        a.push([10, ip, 20]);
+        assert!(a.is_empty());
+        a.push([12, ip, 22]);
+        a.push([12, ip, 22]);
+        assert_eq!(a.len(), 1);
+        let mut b = RuleResult::new(input);
+        b.push([11, ip, 21]);
+        b.push([12, ip, 22]);
+        b.push([10, ip, 21]);
+        let store = RuleResult::concat(a, b).into_store();
+        assert_eq!(store.size(), 2);
+        assert_eq!(
+            &store.chunks()[NodeDictionary::prop_idx_to_offset(ip)].so()[..],
+            &[[10, 21], [12, 22]][..]
+        );
+    }
+
+    #[test]
+    fn test_rule_result_budget() {
+        let ts = TripleStore::default();
+        let interrupt = Interrupt::new(&ProcessOptions::new().with_max_inferred_triples(100));
+        interrupt.start(0);
+        let input = RuleInput::new(&ts, &interrupt);
+        let (many, few) = (NodeDictionary::rdftype as u64, NodeDictionary::owlsameAs as u64);
+        let mut result = RuleResult::new(input);
+        for i in 0..BUDGET_STEP as u64 - 10 {
+            result.push([i, many, i]);
+        }
+        assert_eq!(interrupt.status(), Ok(()));
+        // the last pushed pairs trigger the charge, but most pairs are blamed
+        for i in 0..10 {
+            result.push([i, few, i]);
+        }
+        assert_eq!(interrupt.status(), Err(Stop::Inferences { limit: 100, predicate: many }));
+        let len = result.len();
+        result.push([0, many, 1]);
+        assert_eq!(result.len(), len);
+    }
+
+    #[test]
+    fn test_strata() {
+        let rule = |reads, writes| ScheduledRule::new(nop, reads, writes);
+        let rule_set = FixPointRuleSet {
+            rules: vec![
+                rule(Predicates::Only(&[2]), Predicates::Only(&[3])),
+                rule(Predicates::Only(&[1]), Predicates::Only(&[2])),
+                rule(Predicates::Only(&[3]), Predicates::Only(&[1])),
+                rule(Predicates::Only(&[0]), Predicates::Only(&[1])),
+                rule(Predicates::Only(&[3]), Predicates::Only(&[4])),
+                rule(Predicates::All, Predicates::Only(&[5])),
+            ],
+        };
+        let strata = rule_set.strata();
+        assert_eq!(strata.len(), 4);
+        let position = |r| strata.iter().position(|s| s.contains(&r)).unwrap();
+        // rules 0, 1 and 2 depend on each other
+        assert_eq!(position(0), position(1));
+        assert_eq!(position(0), position(2));
+        assert!(position(3) < position(0));
+        assert!(position(0) < position(4));
+        assert!(position(0) < position(5));
+        assert!(position(4) < position(5));
+    }
+
+    #[test]
+    fn test_schema_reads() {
+        let mut input = String::from(
+            "@prefix : <http://example.org/> .
+            @prefix owl: <http://www.w3.org/2002/07/owl#> .
+            :p owl:inverseOf :q. :x :p :y. :z :q :w.
+            :next a owl:TransitiveProperty.
+            ",
+        );
+        // a chain of 16 nodes, whose closure takes several rounds of PRP-TRP
+        for i in 0..15 {
+            input.push_str(&format!(":n{} :next :n{}.\n", i, i + 1));
+        }
+        let mut graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RhoDF()).unwrap();
+        let size = graph.size();
+        let rule = |rule: Rule, p| ScheduledRule::new(rule, Predicates::Schema(p), Predicates::All);
+        let rule_set = FixPointRuleSet {
+            rules: vec![
+                rule(counted_inv, NodeDictionary::owlinverseOf),
+                rule(PRP_TRP, NodeDictionary::rdftype),
+            ],
+        };
+        rule_set.process(&mut graph, Interrupt::never()).unwrap();
+        // 2 inverse triples, and 120 - 15 pairs in the closure of the chain
+        assert_eq!(graph.size(), size + 2 + 105);
+        // PRP-INV-1-2 runs again after its own triples, but not when only :next changes
+        assert_eq!(INV_RUNS.load(Ordering::Relaxed), 2);
+    }
+}
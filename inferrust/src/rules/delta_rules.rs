@@ -1,41 +1,49 @@
-use crate::inferray::NodeDictionary;
-use crate::inferray::TripleStore;
+use crate::inferray::{NodeDictionary, PairList};
+use crate::par::*;
 use crate::rules::*;
 
-fn apply_delta_rule(ts: &TripleStore, prop_idx: usize, invert: bool) -> RuleResult {
-    let mut output = vec![];
-    if let Some(pairs) = ts.chunks().get(prop_idx) {
-        for pair in pairs.so() {
-            if pair[0] != pair[1] {
-                let prop_idx = NodeDictionary::prop_idx_to_offset(pair[0]);
-                if let Some(usable_pairs) = ts.chunks().get(prop_idx) {
-                    let usable_pairs = if invert {
-                        usable_pairs.os()
-                    } else {
-                        usable_pairs.so()
-                    };
-                    for usable_pair in usable_pairs {
-                        output.push([usable_pair[0], pair[1], usable_pair[1]]);
-                    }
-                }
-                let prop_idx = NodeDictionary::prop_idx_to_offset(pair[1]);
-                if let Some(usable_pairs) = ts.chunks().get(prop_idx) {
-                    let usable_pairs = if invert {
-                        usable_pairs.os()
-                    } else {
-                        usable_pairs.so()
-                    };
-                    for usable_pair in usable_pairs {
-                        output.push([usable_pair[0], pair[0], usable_pair[1]]);
-                    }
-                }
+fn apply_delta_rule(ts: RuleInput, prop_idx: usize, invert: bool) -> RuleResult {
+    let pairs = match ts.chunks().get(prop_idx) {
+        Some(chunk) => chunk,
+        None => return RuleResult::new(ts),
+    };
+    // (usable pairs, predicate of the inferred triples)
+    let mut tasks: Vec<(PairList, u64)> = vec![];
+    for pair in pairs.iter_so() {
+        if pair[0] == pair[1] {
+            continue;
+        }
+        for (usable_prop, new_prop) in &[(pair[0], pair[1]), (pair[1], pair[0])] {
+            // a resource that is not a property has no triple
+            if *usable_prop >= NodeDictionary::START_INDEX as u64 {
+                continue;
+            }
+            if let Some(usable_pairs) = ts.chunks().get(NodeDictionary::prop_idx_to_offset(*usable_prop)) {
+                let usable_pairs = if invert {
+                    usable_pairs.os_list()
+                } else {
+                    usable_pairs.so_list()
+                };
+                tasks.extend(split_pairs(usable_pairs).into_iter().map(|part| (part, *new_prop)));
             }
         }
     }
-    output
+    tasks
+        .into_par_iter()
+        .map(|(part, new_prop)| {
+            let mut output = RuleResult::new(ts);
+            if ts.interrupted() {
+                return output;
+            }
+            for usable_pair in part.iter() {
+                output.push([usable_pair[0], new_prop, usable_pair[1]]);
+            }
+            output
+        })
+        .reduce(|| RuleResult::new(ts), RuleResult::concat)
 }
 
-pub(crate) fn PRP_INV_1_2(ts: &TripleStore) -> RuleResult {
+pub(crate) fn PRP_INV_1_2(ts: RuleInput) -> RuleResult {
     apply_delta_rule(
         ts,
         NodeDictionary::prop_idx_to_offset(NodeDictionary::owlinverseOf as u64),
@@ -43,7 +51,7 @@ pub(crate) fn PRP_INV_1_2(ts: &TripleStore) -> RuleResult {
     )
 }
 
-pub(crate) fn PRP_EQP_1_2(ts: &TripleStore) -> RuleResult {
+pub(crate) fn PRP_EQP_1_2(ts: RuleInput) -> RuleResult {
     apply_delta_rule(
         ts,
         NodeDictionary::prop_idx_to_offset(NodeDictionary::owlequivalentProperty as u64),
@@ -1,37 +1,49 @@
-use crate::inferray::{NodeDictionary, TripleStore};
+use crate::inferray::{NodeDictionary, PairList};
+use crate::par::*;
 use crate::rules::*;
 
 fn apply_gamma_rule(
-    ts: &TripleStore,
+    ts: RuleInput,
     head_prop: usize,
     output_prop: u64,
     subject: bool,
     raw_idx: bool,
 ) -> RuleResult {
-    let mut output = vec![];
-    let pairs1 = ts.chunks().get(head_prop);
-    if pairs1 == None {
-        return output;
-    }
-    let pairs1 = pairs1.unwrap().so();
-    for pair1 in pairs1 {
-        let pairs2 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(pair1[0]));
-        if pairs2 == None {
-            break;
-        }
-        let pairs2 = pairs2.unwrap().so();
-        for pair2 in pairs2 {
-            if raw_idx {
-                output.push([pair2[if subject { 0 } else { 1 }], output_prop, pair1[1]]);
-            } else {
-                output.push([pair2[0], pair1[1], pair2[1]]);
+    let pairs1 = match ts.chunks().get(head_prop) {
+        Some(chunk) => chunk,
+        None => return RuleResult::new(ts),
+    };
+    // split the scan of every chunk matched by pairs1, so that they all run in parallel
+    let tasks: Vec<([u64; 2], PairList)> = pairs1
+        .iter_so()
+        // a subject that is not a property has no triple
+        .filter(|pair1| pair1[0] < NodeDictionary::START_INDEX as u64)
+        .filter_map(|pair1| {
+            let pairs2 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(pair1[0]))?;
+            Some((pair1, pairs2.so_list()))
+        })
+        .flat_map(|(pair1, pairs2)| split_pairs(pairs2).into_iter().map(move |part| (pair1, part)))
+        .collect();
+    tasks
+        .into_par_iter()
+        .map(|(pair1, part)| {
+            let mut output = RuleResult::new(ts);
+            if ts.interrupted() {
+                return output;
             }
-        }
-    }
-    output
+            for pair2 in part.iter() {
+                if raw_idx {
+                    output.push([pair2[if subject { 0 } else { 1 }], output_prop, pair1[1]]);
+                } else {
+                    output.push([pair2[0], pair1[1], pair2[1]]);
+                }
+            }
+            output
+        })
+        .reduce(|| RuleResult::new(ts), RuleResult::concat)
 }
 
-pub(crate) fn PRP_DOM(ts: &TripleStore) -> RuleResult {
+pub(crate) fn PRP_DOM(ts: RuleInput) -> RuleResult {
     apply_gamma_rule(
         ts,
         NodeDictionary::prop_idx_to_offset(NodeDictionary::rdfsdomain as u64),
@@ -41,7 +53,7 @@ pub(crate) fn PRP_DOM(ts: &TripleStore) -> RuleResult {
     )
 }
 
-pub(crate) fn PRP_RNG(ts: &TripleStore) -> RuleResult {
+pub(crate) fn PRP_RNG(ts: RuleInput) -> RuleResult {
     apply_gamma_rule(
         ts,
         NodeDictionary::prop_idx_to_offset(NodeDictionary::rdfsrange as u64),
@@ -51,7 +63,7 @@ pub(crate) fn PRP_RNG(ts: &TripleStore) -> RuleResult {
     )
 }
 
-pub(crate) fn PRP_SPO1(ts: &TripleStore) -> RuleResult {
+pub(crate) fn PRP_SPO1(ts: RuleInput) -> RuleResult {
     apply_gamma_rule(
         ts,
         NodeDictionary::prop_idx_to_offset(NodeDictionary::rdfssubPropertyOf as u64),
@@ -61,55 +73,35 @@ pub(crate) fn PRP_SPO1(ts: &TripleStore) -> RuleResult {
     )
 }
 
-pub(crate) fn PRP_SYMP(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let expected_ip = NodeDictionary::prop_idx_to_offset(NodeDictionary::rdftype as u64);
-    let expected_io = NodeDictionary::owlsymmetricProperty as u64;
-    let pairs1 = ts.chunks().get(expected_ip);
-    if pairs1 == None {
-        return output;
-    }
-    let pairs1 = pairs1.unwrap().os(); // os sorted copy
-    for pair1 in &*pairs1 {
-        if pair1[0] == expected_io {
-            let pairs2 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(pair1[1]));
-            if pairs2 == None {
-                break;
-            }
-            let pairs2 = pairs2.unwrap().so();
-            for pair2 in pairs2 {
-                output.push([pair2[1], pair1[1], pair2[0]]);
-            }
-        }
-        if pair1[0] > expected_io {
+pub(crate) fn PRP_SYMP(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    for (prop, chunk) in declared_properties(&ts, NodeDictionary::owlsymmetricProperty as u64) {
+        if ts.interrupted() {
             break;
         }
+        for [s, o] in chunk.iter_so() {
+            output.push([o, prop, s]);
+        }
     }
     output
 }
 
-pub(crate) fn EQ_TRANS(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let pairs = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
+pub(crate) fn EQ_TRANS(ts: RuleInput) -> RuleResult {
+    let pairs = match ts.chunks().get(NodeDictionary::prop_idx_to_offset(
         NodeDictionary::owlsameAs as u64,
-    ));
-    if pairs == None {
-        return output;
-    }
-    let pairs1 = pairs.unwrap();
-    let pairs2 = pairs.unwrap();
-    for pair1 in pairs1.so() {
-        for pair2 in pairs2.so() {
-            if pair1[1] == pair2[0] {
-                if pair1[0] != pair2[1] {
-                    output.push([pair1[0], NodeDictionary::owlsameAs as u64, pair2[1]]);
-                    output.push([pair2[1], NodeDictionary::owlsameAs as u64, pair1[0]]);
+    )) {
+        Some(chunk) => chunk,
+        None => return RuleResult::new(ts),
+    };
+    // join (x sameAs y) and (y sameAs z) on y
+    par_join(ts, pairs.os_list(), pairs.so_list(), |os_group, so_group, output| {
+        for [_, x] in os_group {
+            for [_, z] in so_group {
+                if x != z {
+                    output.push([*x, NodeDictionary::owlsameAs as u64, *z]);
+                    output.push([*z, NodeDictionary::owlsameAs as u64, *x]);
                 }
             }
-            if pair2[0] > pair1[1] {
-                break;
-            }
         }
-    }
-    output
+    })
 }
@@ -0,0 +1,347 @@
+//! A small join engine over lists of pairs,
+//! typically the `so` and `os` lists of chunks,
+//! on which the rule families are built.
+//!
+//! All lists are joined on the first element of their pairs,
+//! and must be sorted using the lexicographic order on pairs.
+//! Searches gallop (exponential search followed by binary search),
+//! so that skipping ahead in a list costs a logarithm of the distance skipped,
+//! rather than of the length of the list.
+//!
+//! The lists of chunks are read as `PairList`s, which may be compressed:
+//! scans (`par_scan`, `par_join`) decode them one part at a time,
+//! and the other side of a join is decoded as it is merged.
+
+use crate::inferray::PairList;
+use crate::par::*;
+use crate::rules::*;
+
+/// Return the position of the first pair of `pairs` for which `pred` is false,
+/// assuming that `pred` is true for a (possibly empty) prefix of `pairs` and false afterwards.
+fn gallop_by<P>(pairs: &[[u64; 2]], pred: P) -> usize
+where
+    P: Fn(&[u64; 2]) -> bool,
+{
+    if pairs.is_empty() || !pred(&pairs[0]) {
+        return 0;
+    }
+    // invariant: pred(pairs[lo]) is true
+    let mut lo = 0;
+    let mut step = 1;
+    while lo + step < pairs.len() && pred(&pairs[lo + step]) {
+        lo += step;
+        step *= 2;
+    }
+    let hi = (lo + step).min(pairs.len());
+    lo + 1 + pairs[lo + 1..hi].partition_point(pred)
+}
+
+/// Return the position of the first pair of `pairs` whose first element is at least `x`
+/// (or the length of `pairs` if there is none).
+#[inline]
+pub(crate) fn gallop(pairs: &[[u64; 2]], x: u64) -> usize {
+    gallop_by(pairs, |p| p[0] < x)
+}
+
+/// Split `pairs` into the group of pairs sharing its first element, and the remaining pairs.
+#[inline]
+fn split_group(pairs: &[[u64; 2]]) -> (&[[u64; 2]], &[[u64; 2]]) {
+    let first = match pairs.first() {
+        Some(pair) => pair[0],
+        None => return (pairs, pairs),
+    };
+    pairs.split_at(gallop_by(pairs, |p| p[0] == first))
+}
+
+/// The (possibly empty) group of pairs of `pairs` whose first element is `x`.
+pub(crate) fn pairs_with(pairs: &[[u64; 2]], x: u64) -> &[[u64; 2]] {
+    let start = pairs.partition_point(|p| p[0] < x);
+    let rest = &pairs[start..];
+    &rest[..rest.partition_point(|p| p[0] == x)]
+}
+
+/// Iterator returned by `merge_join`.
+pub(crate) struct MergeJoin<'p> {
+    left: &'p [[u64; 2]],
+    right: &'p [[u64; 2]],
+}
+
+impl<'p> Iterator for MergeJoin<'p> {
+    type Item = (&'p [[u64; 2]], &'p [[u64; 2]]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let x = self.left.first()?[0];
+            let y = self.right.first()?[0];
+            if x < y {
+                self.left = &self.left[gallop(self.left, y)..];
+            } else if y < x {
+                self.right = &self.right[gallop(self.right, x)..];
+            } else {
+                let (left_group, left) = split_group(self.left);
+                let (right_group, right) = split_group(self.right);
+                self.left = left;
+                self.right = right;
+                return Some((left_group, right_group));
+            }
+        }
+    }
+}
+
+/// Join `left` and `right` on the first element of their pairs:
+/// iterate, in increasing order of that element,
+/// over every group of `left` and the group of `right` with the same first element.
+pub(crate) fn merge_join<'p>(left: &'p [[u64; 2]], right: &'p [[u64; 2]]) -> MergeJoin<'p> {
+    MergeJoin { left, right }
+}
+
+/// Join any number of lists on the first element of their pairs (leapfrog join):
+/// `f` is called, in increasing order of `x`, with every `x` present in all the lists,
+/// and the group of each list whose first element is `x`.
+pub(crate) fn intersect<F>(lists: &[&[[u64; 2]]], mut f: F)
+where
+    F: FnMut(u64, &[&[[u64; 2]]]),
+{
+    if lists.is_empty() {
+        return;
+    }
+    let mut rests: Vec<&[[u64; 2]]> = lists.to_vec();
+    let mut groups: Vec<&[[u64; 2]]> = lists.to_vec();
+    let mut x = 0;
+    loop {
+        // move every list to x, raising x when a list has no pair for it
+        let mut agreed = true;
+        for rest in rests.iter_mut() {
+            let r = *rest;
+            *rest = &r[gallop(r, x)..];
+            match rest.first() {
+                None => return,
+                Some(pair) if pair[0] > x => {
+                    x = pair[0];
+                    agreed = false;
+                }
+                _ => (),
+            }
+        }
+        if agreed {
+            for (rest, group) in rests.iter_mut().zip(groups.iter_mut()) {
+                let (g, r) = split_group(*rest);
+                *group = g;
+                *rest = r;
+            }
+            f(x, &groups);
+        }
+    }
+}
+
+/// The number of pairs above which rules split their scans across threads.
+const PAR_SCAN_SIZE: usize = 1 << 14;
+
+/// Split `pairs` into parts of (roughly) at most `PAR_SCAN_SIZE` pairs,
+/// without splitting any group of pairs sharing the same first element.
+pub(crate) fn split_pairs(pairs: PairList) -> Vec<PairList> {
+    pairs.split(PAR_SCAN_SIZE)
+}
+
+/// Apply `f` to the parts of `pairs` (see `split_pairs`) in parallel,
+/// and merge the triples they output.
+///
+/// Each part is decoded (if compressed) just before `f` is applied to it.
+/// Parts are skipped once the reasoning process is interrupted.
+pub(crate) fn par_scan<'a, F>(input: RuleInput<'a>, pairs: PairList, f: F) -> RuleResult<'a>
+where
+    F: Fn(&[[u64; 2]], &mut RuleResult<'a>) + Sync,
+{
+    let parts = split_pairs(pairs);
+    if parts.len() <= 1 {
+        let mut output = RuleResult::new(input);
+        if let Some(part) = parts.first() {
+            if !input.interrupted() {
+                f(&part.decode(), &mut output);
+            }
+        }
+        return output;
+    }
+    parts
+        .into_par_iter()
+        .map(|part| {
+            let mut output = RuleResult::new(input);
+            if !input.interrupted() {
+                f(&part.decode(), &mut output);
+            }
+            output
+        })
+        .reduce(|| RuleResult::new(input), RuleResult::concat)
+}
+
+/// Join `left` and `right` on the first element of their pairs, in parallel:
+/// `f` is applied to every group of `left` and the group of `right` with the same first element.
+///
+/// # Pre-condition
+/// `left` and `right` are sorted using the lexicographic order on pairs.
+pub(crate) fn par_join<'a, F>(
+    input: RuleInput<'a>,
+    left: PairList,
+    right: PairList,
+    f: F,
+) -> RuleResult<'a>
+where
+    F: Fn(&[[u64; 2]], &[[u64; 2]], &mut RuleResult<'a>) + Sync,
+{
+    if right.is_empty() {
+        return RuleResult::new(input);
+    }
+    par_scan(input, left, |part, output| join_with(part, right, |l, r| f(l, r, output)))
+}
+
+/// Join `left` and `right` on the first element of their pairs (see `merge_join`),
+/// decoding `right` as it is read if it is compressed.
+pub(crate) fn join_with<F>(left: &[[u64; 2]], right: PairList, mut f: F)
+where
+    F: FnMut(&[[u64; 2]], &[[u64; 2]]),
+{
+    let first = match left.first() {
+        Some(pair) => pair[0],
+        None => return,
+    };
+    match right {
+        PairList::Slice(right) => {
+            let start = gallop(right, first);
+            for (left_group, right_group) in merge_join(left, &right[start..]) {
+                f(left_group, right_group);
+            }
+        }
+        PairList::Compressed { .. } => {
+            let mut right = right.iter_from(first).peekable();
+            let mut right_group = vec![];
+            let mut rest = left;
+            while !rest.is_empty() {
+                let (left_group, r) = split_group(rest);
+                rest = r;
+                let x = left_group[0][0];
+                while right.peek().map_or(false, |p| p[0] < x) {
+                    right.next();
+                }
+                right_group.clear();
+                while let Some(pair) = right.peek().filter(|p| p[0] == x) {
+                    right_group.push(*pair);
+                    right.next();
+                }
+                if right.peek().is_none() && right_group.is_empty() {
+                    return;
+                }
+                if !right_group.is_empty() {
+                    f(left_group, &right_group);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::inferray::*;
+    use crate::interrupt::Interrupt;
+
+    #[test]
+    fn test_gallop() {
+        let pairs: Vec<[u64; 2]> = (0..1000).map(|i| [i / 3 * 2, i]).collect();
+        for x in 0..700 {
+            assert_eq!(gallop(&pairs, x), pairs.partition_point(|p| p[0] < x));
+        }
+        assert_eq!(gallop(&[], 3), 0);
+        assert_eq!(pairs_with(&pairs, 4), &[[4, 6], [4, 7], [4, 8]][..]);
+        assert!(pairs_with(&pairs, 5).is_empty());
+    }
+
+    #[test]
+    fn test_merge_join() {
+        let left = [[1, 10], [1, 11], [2, 20], [4, 40], [7, 70], [7, 71]];
+        let right = [[0, 0], [1, 100], [4, 400], [4, 401], [5, 500], [7, 700]];
+        let joined: Vec<_> = merge_join(&left, &right).collect();
+        assert_eq!(
+            joined,
+            vec![
+                (&left[0..2], &right[1..2]),
+                (&left[3..4], &right[2..4]),
+                (&left[4..6], &right[5..6]),
+            ]
+        );
+        assert_eq!(merge_join(&left, &[]).count(), 0);
+    }
+
+    #[test]
+    fn test_intersect() {
+        let a: Vec<[u64; 2]> = (0..100).map(|i| [i * 2, i]).collect();
+        let b: Vec<[u64; 2]> = (0..100).map(|i| [i * 3, i]).collect();
+        let c: Vec<[u64; 2]> = (0..100).flat_map(|i| vec![[i * 5, 0], [i * 5, 1]]).collect();
+        let mut found = vec![];
+        intersect(&[&a[..], &b[..], &c[..]], |x, groups| {
+            assert_eq!(groups.len(), 3);
+            assert!(groups.iter().all(|g| g.iter().all(|p| p[0] == x)));
+            assert_eq!(groups[2].len(), 2);
+            found.push(x);
+        });
+        let expected: Vec<u64> = (0..200).step_by(30).collect();
+        assert_eq!(found, expected);
+        intersect(&[&a[..], &[]], |_, _| panic!());
+    }
+
+    #[test]
+    fn test_split_pairs() {
+        let pairs: Vec<[u64; 2]> = (0..3 * PAR_SCAN_SIZE as u64).map(|i| [i / 3, i]).collect();
+        let parts: Vec<Vec<[u64; 2]>> = split_pairs(PairList::from(&pairs[..]))
+            .into_iter()
+            .map(|part| part.decode().into_owned())
+            .collect();
+        assert!(parts.len() > 1);
+        assert_eq!(parts.concat(), pairs);
+        for w in parts.windows(2) {
+            assert!(w[0].last().unwrap()[0] < w[1][0][0]);
+        }
+        assert!(split_pairs(PairList::from(&[][..])).is_empty());
+    }
+
+    #[test]
+    fn test_par_join() {
+        let ts = TripleStore::default();
+        let input = RuleInput::new(&ts, Interrupt::never());
+        let ip = NodeDictionary::rdftype as u64;
+        let left: Vec<[u64; 2]> = (0..4 * PAR_SCAN_SIZE as u64).map(|i| [i / 2, i]).collect();
+        let right: Vec<[u64; 2]> = (0..PAR_SCAN_SIZE as u64).map(|i| [i * 3, i]).collect();
+        let mut expected = vec![];
+        for [x, a] in &left {
+            let start = right.partition_point(|p| p[0] < *x);
+            for [_, b] in right[start..].iter().take_while(|p| p[0] == *x) {
+                expected.push([*a, *b]);
+            }
+        }
+        expected.sort_unstable();
+
+        // compressed lists are joined the same way as slices
+        let mut compressed_left = Chunk::from_sorted(left.clone()).unwrap();
+        compressed_left.compress();
+        let mut compressed_right = Chunk::from_sorted(right.clone()).unwrap();
+        compressed_right.compress();
+        let lists = vec![
+            (PairList::from(&left[..]), PairList::from(&right[..])),
+            (compressed_left.so_list(), PairList::from(&right[..])),
+            (PairList::from(&left[..]), compressed_right.so_list()),
+            (compressed_left.so_list(), compressed_right.so_list()),
+        ];
+        for (left, right) in lists {
+            let output = par_join(input, left, right, |l, r, output| {
+                for [_, a] in l {
+                    for [_, b] in r {
+                        output.push([*a, ip, *b]);
+                    }
+                }
+            });
+            assert_eq!(output.len(), expected.len());
+            let store = output.into_store();
+            assert_eq!(store.size(), expected.len());
+            assert_eq!(&store.chunks()[NodeDictionary::prop_idx_to_offset(ip)].so()[..], &expected[..]);
+        }
+    }
+}
@@ -1,159 +1,122 @@
 use crate::inferray::*;
 use crate::rules::*;
 
-pub(crate) fn PRP_FP(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let pairs_mut = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
+use crate::interrupt::Interrupt;
+use crate::utils::pair_groups;
+
+use std::borrow::Cow;
+
+/// The (`class`-instance) pairs of the `rdf:type` triples whose object is `class`.
+pub(crate) fn instances_of(ts: &TripleStore, class: u64) -> Cow<[[u64; 2]]> {
+    match ts.chunks().get(NodeDictionary::prop_idx_to_offset(
         NodeDictionary::rdftype as u64,
-    ));
-    if pairs_mut == None {
-        return output;
+    )) {
+        Some(chunk) => chunk.os_list().with(class),
+        None => Cow::Borrowed(&[]),
+    }
+}
+
+/// Iterate over the properties declared with `rdf:type` `class`,
+/// and their chunk (properties with no triple are skipped).
+pub(crate) fn declared_properties(
+    ts: &TripleStore,
+    class: u64,
+) -> impl Iterator<Item = (u64, &Chunk)> {
+    instances_of(ts, class)
+        .into_owned()
+        .into_iter()
+        // a property that is not used as a predicate has a resource index
+        .filter(|pair| pair[1] < NodeDictionary::START_INDEX as u64)
+        .filter_map(move |pair| {
+            let chunk = ts.chunks().get(NodeDictionary::prop_idx_to_offset(pair[1]))?;
+            if chunk.is_empty() {
+                None
+            } else {
+                Some((pair[1], chunk))
+            }
+        })
+}
+
+/// Infer that all the second elements of each group of `pairs` are `owl:sameAs`.
+///
+/// Each element is only linked (both ways) to the first one of its group,
+/// so that a group of n elements yields 2(n-1) triples rather than n(n-1);
+/// the rest of the clique is derived by the closure of `owl:sameAs`
+/// (or by canonicalization, if cliques are canonicalized).
+///
+/// Stop as soon as a group is larger than the clique limit.
+fn same_as_in_groups(pairs: &[[u64; 2]], output: &mut RuleResult) {
+    for group in pair_groups(pairs).filter(|g| g.len() > 1) {
+        if output.check_clique(group[0][1], group.len()) {
+            return;
+        }
+        let first = group[0][1];
+        for [_, x] in &group[1..] {
+            output.push([first, NodeDictionary::owlsameAs as u64, *x]);
+            output.push([*x, NodeDictionary::owlsameAs as u64, first]);
+        }
     }
-    let pairs: &[[u64; 2]] = pairs_mut.unwrap().os();
-    let expected_o = NodeDictionary::owlfunctionalProperty as u64;
-    for pair in pairs {
-        if pair[0] > expected_o {
+}
+
+pub(crate) fn PRP_FP(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    for (_, chunk) in declared_properties(&ts, NodeDictionary::owlfunctionalProperty as u64) {
+        if ts.interrupted() {
             break;
         }
-        if pair[0] == expected_o {
-            let prop = pair[1];
-            let raw_prop = NodeDictionary::prop_idx_to_offset(prop);
-            let pairs1 = ts.chunks().get(raw_prop);
-            if pairs1 == None {
-                break;
-            }
-            let pairs2 = pairs1.unwrap().so();
-            if pairs2.is_empty() {
-                break;
-            }
-            let pairs1 = pairs1.unwrap().so();
-            for pair1 in pairs1 {
-                for pair2 in pairs2 {
-                    if pair1[0] > pair2[0] {
-                        break;
-                    }
-                    if pair1[0] == pair2[0] {
-                        if pair1[1] != pair2[1] {
-                            output.push([pair1[1], NodeDictionary::owlsameAs as u64, pair2[1]])
-                        }
-                    }
-                }
-            }
+        for part in split_pairs(chunk.so_list()) {
+            same_as_in_groups(&part.decode(), &mut output);
         }
     }
     output
 }
 
-pub(crate) fn PRP_IFP(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let pairs = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
-        NodeDictionary::rdftype as u64,
-    ));
-    if pairs == None {
-        return output;
-    }
-    let pairs = pairs.unwrap().os(); // os copy
-    let expected_o = NodeDictionary::owlinverseFunctionalProperty as u64;
-    for pair in &*pairs {
-        if pair[0] > expected_o {
+pub(crate) fn PRP_IFP(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    for (_, chunk) in declared_properties(&ts, NodeDictionary::owlinverseFunctionalProperty as u64) {
+        if ts.interrupted() {
             break;
         }
-        if pair[0] == expected_o {
-            let prop = pair[1];
-            let raw_prop = NodeDictionary::prop_idx_to_offset(prop);
-            let pairs1 = ts.chunks().get(raw_prop);
-            if pairs1 == None {
-                break;
-            }
-            let pairs2 = pairs1.unwrap().os();
-            if pairs2.is_empty() {
-                break;
-            }
-            let pairs1 = pairs1.unwrap().os();
-            for pair1 in &*pairs1 {
-                for pair2 in &*pairs2 {
-                    if pair1[0] > pair2[0] {
-                        break;
-                    }
-                    if pair1[0] == pair2[0] {
-                        if pair1[1] != pair2[1] {
-                            output.push([pair1[1], NodeDictionary::owlsameAs as u64, pair2[1]])
-                        }
-                    }
-                }
-            }
+        for part in split_pairs(chunk.os_list()) {
+            same_as_in_groups(&part.decode(), &mut output);
         }
     }
     output
 }
 
-pub(crate) fn PRP_TRP(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let pairs = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
-        NodeDictionary::rdftype as u64,
-    ));
-    if pairs == None {
-        return output;
-    }
-    let pairs = pairs.unwrap().os();
-    if pairs.is_empty() {
-        return output;
-    }
-    let transitive = NodeDictionary::owltransitiveProperty as u64;
-    let mut start = 0;
-    let mut val = pairs[start][0];
-    if val > transitive {
-        return output;
-    }
-    if pairs[pairs.len() - 1][0] < transitive {
-        return output;
-    }
-    while val < transitive {
-        start += 1;
-        val = pairs[start][0];
-    }
-    for idx in start..pairs.len() {
-        let [val, prop] = pairs[idx];
-        if val != transitive {
+pub(crate) fn PRP_TRP(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    for (prop, chunk) in declared_properties(&ts, NodeDictionary::owltransitiveProperty as u64) {
+        if ts.interrupted() {
             break;
         }
-        if prop != NodeDictionary::rdfssubClassOf as u64
-            && prop != NodeDictionary::rdfssubPropertyOf as u64
-            && prop != NodeDictionary::owlsameAs as u64
+        if prop == NodeDictionary::rdfssubClassOf as u64
+            || prop == NodeDictionary::rdfssubPropertyOf as u64
+            || prop == NodeDictionary::owlsameAs as u64
         {
-            let pairs = ts.chunks().get(NodeDictionary::prop_idx_to_offset(prop));
-            if pairs == None {
-                break;
-            }
-            let pairs2 = pairs.unwrap().so();
-            let pairs3 = pairs.unwrap().os();
-            let mut counter = 0;
-            for i in 0..pairs2.len() {
-                let [s1, o1] = pairs2[i];
-                for j in counter..pairs3.len() {
-                    let [o2, s2] = pairs3[j];
-                    if s1 == o2 {
-                        output.push([s2, prop, o1]);
-                    } else if o2 > s1 {
-                        counter = j;
-                        break;
+            continue;
+        }
+        // join (x prop y) and (y prop z) on y
+        for part in split_pairs(chunk.so_list()) {
+            join_with(&part.decode(), chunk.os_list(), |so_group, os_group| {
+                for [_, z] in so_group {
+                    for [_, x] in os_group {
+                        output.push([*x, prop, *z]);
                     }
                 }
-            }
+            });
         }
     }
     output
 }
 
 /// Add `rdf:type` `rdf:Resource` to all nodes of the graph.
-pub fn type_all_resources(graph: &InfGraph) -> RuleResult {
-    ((NodeDictionary::START_INDEX as u64 + 1)..=graph.dict().get_res_ctr())
-    .filter_map(|e|
+pub(crate) fn type_all_resources(graph: &InfGraph) -> RuleResult {
+    let mut output = RuleResult::new(RuleInput::new(graph.store(), Interrupt::never()));
+    for e in (NodeDictionary::START_INDEX as u64 + 1)..=graph.dict().get_res_ctr() {
         if !graph.dict().was_remapped(e) {
-            Some([e, NodeDictionary::rdftype as u64, NodeDictionary::rdfsResource])
-        } else {
-            None
+            output.push([e, NodeDictionary::rdftype as u64, NodeDictionary::rdfsResource]);
         }
-    )
-    .collect()
+    }
+    output
 }
@@ -4,11 +4,27 @@ use crate::inferray::*;
 use crate::rules::*;
 use std::fmt;
 
+use crate::rules::Predicates::{All, Only, Schema};
+
+const TYPE: u32 = NodeDictionary::rdftype;
+const SCO: u32 = NodeDictionary::rdfssubClassOf;
+const SPO: u32 = NodeDictionary::rdfssubPropertyOf;
+const DOM: u32 = NodeDictionary::rdfsdomain;
+const RNG: u32 = NodeDictionary::rdfsrange;
+const EQC: u32 = NodeDictionary::owlequivalentClass;
+const EQP: u32 = NodeDictionary::owlequivalentProperty;
+const INV: u32 = NodeDictionary::owlinverseOf;
+const SA: u32 = NodeDictionary::owlsameAs;
+
+#[derive(Clone, Copy)]
 pub struct ClosureProfile {
     pub on_sa: bool,
     pub on_sco: bool,
     pub on_spo: bool,
     pub on_trp: bool,
+    /// derive `owl:equivalentClass` (resp. `owl:equivalentProperty`)
+    /// from the cycles of `rdfs:subClassOf` (resp. `rdfs:subPropertyOf`)
+    pub on_eq: bool,
 }
 
 /// A set of rules used for reasoning.
@@ -17,24 +33,31 @@ pub struct RuleProfile {
     pub(crate) axiomatic_triples: bool,
     pub(crate) before_rules: Vec<Box<Rule>>,
     pub(crate) rules: FixPointRuleSet,
-    pub(crate) after_rules: Option<Box<dyn Fn(&InfGraph) -> RuleResult>>,
+    /// the subset of `rules` that reads instance data (used by `CompiledSchema`)
+    pub(crate) instance_rules: FixPointRuleSet,
+    pub(crate) after_rules: Option<Box<dyn Fn(&InfGraph) -> RuleResult + Send + Sync>>,
+    /// whether `owl:sameAs` cliques are canonicalized instead of materialized
+    pub(crate) canonical_same_as: bool,
     name: String,
 }
 
 impl RuleProfile {
     /// The standard set of rules for RDF-Schema
     pub fn RDFS() -> Self {
-        let rules: Vec<Box<Rule>> = vec![
+        let schema_rules: Vec<ScheduledRule> = vec![
+            // Alpha class
+            ScheduledRule::new(SCM_DOM1, Only(&[SCO, DOM]), Only(&[DOM])),
+            ScheduledRule::new(SCM_DOM2, Only(&[DOM, SPO]), Only(&[DOM])),
+            ScheduledRule::new(SCM_RNG1, Only(&[SCO, RNG]), Only(&[RNG])),
+            ScheduledRule::new(SCM_RNG2, Only(&[RNG, SPO]), Only(&[RNG])),
+        ];
+        let instance_rules: Vec<ScheduledRule> = vec![
             // Alpha class
-            Box::new(CAX_SCO),
-            Box::new(SCM_DOM1),
-            Box::new(SCM_DOM2),
-            Box::new(SCM_RNG1),
-            Box::new(SCM_RNG2),
+            ScheduledRule::new(CAX_SCO, Only(&[SCO, TYPE]), Only(&[TYPE])),
             // Gamma class
-            Box::new(PRP_DOM),
-            Box::new(PRP_RNG),
-            Box::new(PRP_SPO1),
+            ScheduledRule::new(PRP_DOM, Schema(DOM), Only(&[TYPE])),
+            ScheduledRule::new(PRP_RNG, Schema(RNG), Only(&[TYPE])),
+            ScheduledRule::new(PRP_SPO1, Schema(SPO), All),
         ];
         let before_rules: Vec<Box<Rule>> = vec![
             // Zeta class (trivial rules)
@@ -51,13 +74,21 @@ impl RuleProfile {
                 on_sco: true,
                 on_spo: true,
                 on_trp: false,
+                on_eq: false,
             },
             axiomatic_triples: true,
             before_rules: before_rules,
             rules: FixPointRuleSet {
-                rules: rules,
+                rules: schema_rules
+                    .into_iter()
+                    .chain(instance_rules.iter().cloned())
+                    .collect(),
+            },
+            instance_rules: FixPointRuleSet {
+                rules: instance_rules,
             },
             after_rules: Some(Box::new(type_all_resources)),
+            canonical_same_as: false,
             name: "RDFS".to_string(),
         }
     }
@@ -69,15 +100,18 @@ impl RuleProfile {
             // Zeta class (trivial rules)
             Box::new(RDFS4),
         ];
-        let rules: Vec<Box<Rule>> = vec![
+        let schema_rules: Vec<ScheduledRule> = vec![
+            // Alpha class
+            ScheduledRule::new(SCM_DOM2, Only(&[DOM, SPO]), Only(&[DOM])),
+            ScheduledRule::new(SCM_RNG2, Only(&[RNG, SPO]), Only(&[RNG])),
+        ];
+        let instance_rules: Vec<ScheduledRule> = vec![
             // Alpha class
-            Box::new(CAX_SCO),
-            Box::new(SCM_DOM2),
-            Box::new(SCM_RNG2),
+            ScheduledRule::new(CAX_SCO, Only(&[SCO, TYPE]), Only(&[TYPE])),
             // Gamma class
-            Box::new(PRP_DOM),
-            Box::new(PRP_RNG),
-            Box::new(PRP_SPO1),
+            ScheduledRule::new(PRP_DOM, Schema(DOM), Only(&[TYPE])),
+            ScheduledRule::new(PRP_RNG, Schema(RNG), Only(&[TYPE])),
+            ScheduledRule::new(PRP_SPO1, Schema(SPO), All),
         ];
         Self {
             cl_profile: ClosureProfile {
@@ -85,13 +119,21 @@ impl RuleProfile {
                 on_sco: true,
                 on_spo: true,
                 on_trp: false,
+                on_eq: false,
             },
             axiomatic_triples: false,
             before_rules: before_rules,
             rules: FixPointRuleSet {
-                rules: rules,
+                rules: schema_rules
+                    .into_iter()
+                    .chain(instance_rules.iter().cloned())
+                    .collect(),
+            },
+            instance_rules: FixPointRuleSet {
+                rules: instance_rules,
             },
             after_rules: None,
+            canonical_same_as: false,
             name: "RHODF".to_string(),
         }
     }
@@ -101,55 +143,90 @@ impl RuleProfile {
     /// `equivalentClass`, `sameAs`, `equivalentProperty`, `FunctionalProperty`,
     /// `InverseFunctionalProperty`, `inverseOf`, `SymmetricProperty`, `TransitiveProperty`.
     pub fn RDFSPlus() -> Self {
+        Self::rdfs_plus(false)
+    }
+
+    /// Same as `RDFSPlus`, except that `owl:sameAs` cliques are canonicalized:
+    /// instead of copying every triple for every member of a clique,
+    /// only one representative per clique is kept in the underlying store,
+    /// and cliques are expanded on the fly when the graph is queried
+    /// (see also `InfGraph::same_as_clique`).
+    ///
+    /// NB: equality between properties is still materialized.
+    pub fn RDFSPlusCanonical() -> Self {
+        Self::rdfs_plus(true)
+    }
+
+    fn rdfs_plus(canonical_same_as: bool) -> Self {
         let before_rules: Vec<Box<Rule>> = vec![
             // Zeta class (trivial rules)
             Box::new(RDFS4),
             Box::new(SCM_DP_OP),
             Box::new(SCM_CLS),
         ];
-        let rules: Vec<Box<Rule>> = vec![
+        let schema_rules: Vec<ScheduledRule> = vec![
             // Alpha class
-            Box::new(CAX_SCO),
-            Box::new(CAX_EQC1),
-            Box::new(SCM_DOM1),
-            Box::new(SCM_DOM2),
-            Box::new(SCM_RNG1),
-            Box::new(SCM_RNG2),
+            ScheduledRule::new(SCM_DOM1, Only(&[SCO, DOM]), Only(&[DOM])),
+            ScheduledRule::new(SCM_DOM2, Only(&[DOM, SPO]), Only(&[DOM])),
+            ScheduledRule::new(SCM_RNG1, Only(&[SCO, RNG]), Only(&[RNG])),
+            ScheduledRule::new(SCM_RNG2, Only(&[RNG, SPO]), Only(&[RNG])),
             // Beta class
-            Box::new(SCM_SCO_EQC2),
-            Box::new(SCM_SPO_EQP2),
-            Box::new(SCM_EQC1),
-            Box::new(SCM_EQP1),
+            ScheduledRule::new(SCM_SCO_EQC2, Only(&[SCO]), Only(&[SCO, EQC])),
+            ScheduledRule::new(SCM_SPO_EQP2, Only(&[SPO]), Only(&[SPO, EQP])),
+            ScheduledRule::new(SCM_EQC1, Only(&[EQC]), Only(&[EQC, SCO])),
+            ScheduledRule::new(SCM_EQP1, Only(&[EQP]), Only(&[EQP, SPO])),
+        ];
+        let mut instance_rules: Vec<ScheduledRule> = vec![
+            // Alpha class
+            ScheduledRule::new(CAX_SCO, Only(&[SCO, TYPE]), Only(&[TYPE])),
+            ScheduledRule::new(CAX_EQC1, Only(&[EQC, TYPE]), Only(&[TYPE])),
             // Delta class
-            Box::new(PRP_INV_1_2),
-            Box::new(PRP_EQP_1_2),
+            ScheduledRule::new(PRP_INV_1_2, Schema(INV), All),
+            ScheduledRule::new(PRP_EQP_1_2, Schema(EQP), All),
             // Gamma class
-            Box::new(PRP_DOM),
-            Box::new(PRP_RNG),
-            Box::new(PRP_SPO1),
-            Box::new(PRP_SYMP),
-            Box::new(EQ_TRANS),
-            // Same as class
-            Box::new(SAME_AS),
-            // Other rules
-            Box::new(PRP_FP),
-            Box::new(PRP_IFP),
-            Box::new(PRP_TRP),
+            ScheduledRule::new(PRP_DOM, Schema(DOM), Only(&[TYPE])),
+            ScheduledRule::new(PRP_RNG, Schema(RNG), Only(&[TYPE])),
+            ScheduledRule::new(PRP_SPO1, Schema(SPO), All),
+            ScheduledRule::new(PRP_SYMP, Schema(TYPE), All),
         ];
+        if canonical_same_as {
+            instance_rules.push(ScheduledRule::new(EQ_REP_P, Schema(SA), All));
+        } else {
+            instance_rules.push(ScheduledRule::new(EQ_TRANS, Only(&[SA]), Only(&[SA])));
+            // Same as class
+            // (EQ-REP-S and EQ-REP-O read the triples of every property)
+            instance_rules.push(ScheduledRule::new(SAME_AS, All, All));
+        }
+        // Other rules
+        instance_rules.push(ScheduledRule::new(PRP_FP, Schema(TYPE), Only(&[SA])));
+        instance_rules.push(ScheduledRule::new(PRP_IFP, Schema(TYPE), Only(&[SA])));
+        instance_rules.push(ScheduledRule::new(PRP_TRP, Schema(TYPE), All));
         Self {
             cl_profile: ClosureProfile {
                 on_sa: true,
                 on_sco: true,
                 on_spo: true,
                 on_trp: true,
+                on_eq: true,
             },
             axiomatic_triples: false,
             before_rules: before_rules,
             rules: FixPointRuleSet {
-                rules: rules,
+                rules: schema_rules
+                    .into_iter()
+                    .chain(instance_rules.iter().cloned())
+                    .collect(),
+            },
+            instance_rules: FixPointRuleSet {
+                rules: instance_rules,
             },
             after_rules: Some(Box::new(type_all_resources)),
-            name: "RDFSPLUS".to_string(),
+            canonical_same_as,
+            name: if canonical_same_as {
+                "RDFSPLUS-CANONICAL".to_string()
+            } else {
+                "RDFSPLUS".to_string()
+            },
         }
     }
 
@@ -157,6 +234,17 @@ impl RuleProfile {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// Return the RuleProfile with the given name (see `name`), ignoring case.
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "RDFS" => Some(Self::RDFS()),
+            "RHODF" => Some(Self::RhoDF()),
+            "RDFSPLUS" => Some(Self::RDFSPlus()),
+            "RDFSPLUS-CANONICAL" => Some(Self::RDFSPlusCanonical()),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for RuleProfile {
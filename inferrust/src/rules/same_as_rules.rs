@@ -1,4 +1,4 @@
-use crate::inferray::{NodeDictionary, TripleStore};
+use crate::inferray::NodeDictionary;
 use crate::rules::*;
 
 // /**
@@ -16,67 +16,78 @@ use crate::rules::*;
 //  *
 //  *         Dec. 13
 //  */
-fn apply_same_as_rule(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
+fn apply_same_as_rule(ts: RuleInput) -> RuleResult {
     let sameas_chunk = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
         NodeDictionary::owlsameAs as u64,
     ));
-    if let Some(sameas_chunk) = sameas_chunk {
-        for same in sameas_chunk.so() {
+    let sameas_chunk = match sameas_chunk {
+        Some(chunk) => chunk,
+        None => return RuleResult::new(ts),
+    };
+    par_scan(ts, sameas_chunk.so_list(), |part, output| {
+        for same in part {
             output.push([same[1], NodeDictionary::owlsameAs as u64, same[0]]);
             if same[0] < NodeDictionary::START_INDEX as u64 {
                 // EQ-REP-P
                 if let Some(pairs) = ts.chunks().get(NodeDictionary::prop_idx_to_offset(same[0])) {
-                    for [si, oi] in pairs.so() {
+                    for [si, oi] in pairs.iter_so() {
                         // TODO: ensure that same[1] is a property index
-                        output.push([*si, same[1], *oi]);
+                        output.push([si, same[1], oi]);
                     }
                 }
             } else {
                 for (idx, chunk) in ts.chunks().iter().enumerate() {
                     let pi = NodeDictionary::offset_to_prop_idx(idx);
-                    if pi == NodeDictionary::owlsameAs as u64 {
+                    if pi == NodeDictionary::owlsameAs as u64 || chunk.is_empty() {
                         continue;
                     }
                     // EQ-REP-S
-                    let so_pairs = chunk.so();
-                    if !so_pairs.is_empty() {
-                        let first_s = so_pairs[0][0];
-                        let last_s = so_pairs[so_pairs.len() - 1][0];
-                        if first_s <= same[0] && same[0] <= last_s {
-                            for [si, oi] in so_pairs {
-                                if *si > same[0] {
-                                    break;
-                                }
-                                if *si == same[0] {
-                                    output.push([same[1], pi, *oi]);
-                                }
-                            }
-                        }
+                    for [_, oi] in chunk.so_with_s(same[0]) {
+                        output.push([same[1], pi, oi]);
                     }
                     // EQ-REP-O
-                    let os_pairs = chunk.os();
-                    if !os_pairs.is_empty() {
-                        let first_o = os_pairs[0][0];
-                        let last_o = os_pairs[os_pairs.len() - 1][0];
-                        if first_o <= same[0] && same[0] <= last_o {
-                            for [oi, si] in os_pairs {
-                                if *oi > same[0] {
-                                    break;
-                                }
-                                if *oi == same[0] {
-                                    output.push([*si, pi, same[1]]);
-                                }
-                            }
-                        }
+                    for [_, si] in chunk.os_with_o(same[0]) {
+                        output.push([si, pi, same[1]]);
                     }
                 }
             }
         }
-    }
-    output
+    })
 }
 
-pub(crate) fn SAME_AS(ts: &TripleStore) -> RuleResult {
+pub(crate) fn SAME_AS(ts: RuleInput) -> RuleResult {
     apply_same_as_rule(ts)
 }
+
+/// EQ-REP-P and EQ-SYM, restricted to properties.
+///
+/// This is used instead of `SAME_AS` when `owl:sameAs` cliques are canonicalized,
+/// as only cliques of resources are handled by the canonicalization.
+pub(crate) fn EQ_REP_P(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    let sameas_chunk = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
+        NodeDictionary::owlsameAs as u64,
+    ));
+    if let Some(sameas_chunk) = sameas_chunk {
+        let start_index = NodeDictionary::START_INDEX as u64;
+        for [p1, p2] in sameas_chunk.iter_so() {
+            if p1 >= start_index {
+                // the remaining pairs have a resource as subject
+                break;
+            }
+            if p2 >= start_index || p1 == p2 {
+                continue;
+            }
+            if ts.interrupted() {
+                break;
+            }
+            output.push([p2, NodeDictionary::owlsameAs as u64, p1]);
+            if let Some(pairs) = ts.chunks().get(NodeDictionary::prop_idx_to_offset(p1)) {
+                for [si, oi] in pairs.iter_so() {
+                    output.push([si, p2, oi]);
+                }
+            }
+        }
+    }
+    output
+}
@@ -1,173 +1,112 @@
-use crate::inferray::{NodeDictionary, TripleStore};
+use crate::inferray::NodeDictionary;
 use crate::rules::*;
 
 fn apply_zeta_rule(
-    ts: &TripleStore,
+    ts: RuleInput,
     input_o: u64,
     output_p: u64,
     output_o: u64,
     object_is_subject: bool,
 ) -> RuleResult {
-    let mut output = vec![];
-    let pairs1 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
-        NodeDictionary::rdftype as u64,
-    ));
-    if pairs1 == None {
-        return output;
-    }
-    let pairs1 = pairs1.unwrap().os();
-    if pairs1.is_empty() {
-        return output;
-    }
-    for pair1 in &*pairs1 {
-        if pair1[0] > input_o {
-            break;
-        }
-        if pair1[0] == input_o {
-            if !object_is_subject {
-                output.push([pair1[1], output_p, output_o]);
-            } else {
-                output.push([pair1[1], output_p, pair1[1]]);
-            }
+    let mut output = RuleResult::new(ts);
+    for [_, s] in instances_of(&ts, input_o).iter() {
+        if !object_is_subject {
+            output.push([*s, output_p, output_o]);
+        } else {
+            output.push([*s, output_p, *s]);
         }
     }
     output
 }
 
-pub(crate) fn RDFS6(ts: &TripleStore) -> RuleResult {
+pub(crate) fn RDFS6(ts: RuleInput) -> RuleResult {
     let input_o = NodeDictionary::rdfProperty as u64;
     let output_p = NodeDictionary::rdfssubPropertyOf as u64;
     apply_zeta_rule(ts, input_o, output_p, 0, true)
 }
 
-pub(crate) fn RDFS8(ts: &TripleStore) -> RuleResult {
+pub(crate) fn RDFS8(ts: RuleInput) -> RuleResult {
     let input_o = NodeDictionary::rdfsClass;
     let output_p = NodeDictionary::rdftype as u64;
     let output_o = NodeDictionary::rdfsResource;
     apply_zeta_rule(ts, input_o, output_p, output_o, false)
 }
 
-pub(crate) fn RDFS10(ts: &TripleStore) -> RuleResult {
+pub(crate) fn RDFS10(ts: RuleInput) -> RuleResult {
     let input_o = NodeDictionary::rdfsClass;
     let output_p = NodeDictionary::rdfssubClassOf as u64;
     apply_zeta_rule(ts, input_o, output_p, 0, true)
 }
 
-pub(crate) fn RDFS12(ts: &TripleStore) -> RuleResult {
+pub(crate) fn RDFS12(ts: RuleInput) -> RuleResult {
     let input_o = NodeDictionary::rdfsContainerMembershipProperty as u64;
     let output_p = NodeDictionary::rdfssubPropertyOf as u64;
     let output_o = NodeDictionary::rdfsMember as u64;
     apply_zeta_rule(ts, input_o, output_p, output_o, false)
 }
 
-pub(crate) fn RDFS13(ts: &TripleStore) -> RuleResult {
+pub(crate) fn RDFS13(ts: RuleInput) -> RuleResult {
     let input_o = NodeDictionary::rdfsDatatype;
     let output_p = NodeDictionary::rdfssubClassOf as u64;
     let output_o = NodeDictionary::rdfsLiteral;
     apply_zeta_rule(ts, input_o, output_p, output_o, false)
 }
 
-pub(crate) fn SCM_DP_OP(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let pairs1 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
-        NodeDictionary::rdftype as u64,
-    ));
-    if pairs1 == None {
-        return output;
-    }
-    let pairs1 = pairs1.unwrap().os();
-    if pairs1.is_empty() {
-        return output;
-    }
-    for pair1 in pairs1 {
-        for object in [
-            NodeDictionary::owldataTypeProperty as u64,
-            NodeDictionary::owlobjectProperty as u64,
-        ]
-        .iter()
-        {
-            if pair1[0] > *object {
-                break;
-            }
-            if pair1[0] == *object {
-                output.push([pair1[1], NodeDictionary::rdfssubPropertyOf as u64, pair1[1]]);
-                output.push([
-                    pair1[1],
-                    NodeDictionary::owlequivalentProperty as u64,
-                    pair1[1],
-                ]);
-            }
+pub(crate) fn SCM_DP_OP(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    for class in &[
+        NodeDictionary::owldataTypeProperty as u64,
+        NodeDictionary::owlobjectProperty as u64,
+    ] {
+        for [_, p] in instances_of(&ts, *class).iter() {
+            output.push([*p, NodeDictionary::rdfssubPropertyOf as u64, *p]);
+            output.push([*p, NodeDictionary::owlequivalentProperty as u64, *p]);
         }
     }
     output
 }
 
-pub(crate) fn SCM_CLS(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let pairs1 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
-        NodeDictionary::rdftype as u64,
-    ));
-    if pairs1 == None {
-        return output;
-    }
-    let pairs1 = pairs1.unwrap().os();
-    if pairs1.is_empty() {
-        return output;
-    }
-    let object = NodeDictionary::owlclass;
-    for pair1 in pairs1 {
-        if pair1[0] > object {
-            break;
-        }
-        if pair1[0] == object {
-            output.push([pair1[1], NodeDictionary::rdfssubClassOf as u64, pair1[1]]);
-            output.push([
-                pair1[1],
-                NodeDictionary::owlequivalentClass as u64,
-                pair1[1],
-            ]);
-            output.push([
-                pair1[1],
-                NodeDictionary::rdfssubClassOf as u64,
-                NodeDictionary::owlthing as u64,
-            ]);
-            output.push([
-                NodeDictionary::nothing as u64,
-                NodeDictionary::rdfssubClassOf as u64,
-                pair1[1],
-            ]);
-        }
+pub(crate) fn SCM_CLS(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    for [_, c] in instances_of(&ts, NodeDictionary::owlclass).iter() {
+        output.push([*c, NodeDictionary::rdfssubClassOf as u64, *c]);
+        output.push([*c, NodeDictionary::owlequivalentClass as u64, *c]);
+        output.push([
+            *c,
+            NodeDictionary::rdfssubClassOf as u64,
+            NodeDictionary::owlthing as u64,
+        ]);
+        output.push([
+            NodeDictionary::nothing as u64,
+            NodeDictionary::rdfssubClassOf as u64,
+            *c,
+        ]);
     }
     output
 }
 
-pub(crate) fn RDFS4(ts: &TripleStore) -> RuleResult {
-    let mut output = vec![];
-    let mut resources_idx = Vec::new();
-    let pairs1 = ts.chunks().get(NodeDictionary::prop_idx_to_offset(
-        NodeDictionary::rdftype as u64,
-    ));
-    if pairs1 == None {
-        return output;
-    }
-    let pairs1 = pairs1.unwrap().os();
-    if pairs1.is_empty() {
+pub(crate) fn RDFS4(ts: RuleInput) -> RuleResult {
+    let mut output = RuleResult::new(ts);
+    let object = NodeDictionary::rdfsResource;
+    // the resources typed rdfs:Resource, keyed by themselves
+    let resources: Vec<[u64; 2]> = instances_of(&ts, object)
+        .iter()
+        .map(|[_, r]| [*r, *r])
+        .collect();
+    if resources.is_empty() {
         return output;
     }
-    let object = NodeDictionary::rdfsResource;
-    for pair1 in pairs1 {
-        if pair1[0] > object {
+    for chunk in ts.chunks() {
+        if ts.interrupted() {
             break;
         }
-        if pair1[0] == object {
-            resources_idx.push(pair1[1])
-        }
-    }
-    for pairs2 in ts.chunks() {
-        for pair2 in pairs2.so() {
-            if resources_idx.contains(&pair2[1]) {
-                output.push([pair2[0], NodeDictionary::rdftype as u64, object]);
-            }
+        for part in split_pairs(chunk.os_list()) {
+            let part = part.decode();
+            intersect(&[&part[..], &resources[..]], |_, groups| {
+                for [_, s] in groups[0] {
+                    output.push([*s, NodeDictionary::rdftype as u64, object]);
+                }
+            });
         }
     }
     output
@@ -0,0 +1,99 @@
+//! Provides type `CompiledSchema`.
+
+use sophia_api::triple::stream::TripleSource;
+
+use crate::inferray::InfGraph;
+use crate::interrupt::Interrupt;
+use crate::observer::Silent;
+use crate::rules::RuleProfile;
+
+/// A schema (TBox) on which reasoning has been performed once,
+/// and which can then be applied to many sets of instance data (ABoxes).
+///
+/// The compiled schema holds the closures of `rdfs:subClassOf`
+/// and `rdfs:subPropertyOf`, as well as every other triple that
+/// the profile infers from the schema alone
+/// (domains, ranges, property characteristics...).
+/// Applying it to an ABox only runs the instance-level rules of the profile,
+/// which is much cheaper than reasoning over the TBox and the ABox together.
+///
+/// # Limitation
+/// The ABox is assumed to contain instance data only.
+/// Schema triples occurring in the ABox are kept,
+/// but schema-level rules are *not* applied to them.
+///
+/// # Example
+/// ```
+/// use inferrust::*;
+/// use sophia_api::graph::Graph;
+/// use sophia_api::ns::rdf;
+/// use sophia_term::BoxTerm;
+///
+/// let tbox = r#"
+/// @prefix : <http://example.org/> .
+/// @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+///
+/// :Boy rdfs:subClassOf :Male .
+/// :Male rdfs:subClassOf :Person .
+/// "#;
+/// let abox = r#"
+/// @prefix : <http://example.org/> .
+///
+/// :bart a :Boy .
+/// "#;
+///
+/// let schema = CompiledSchema::new(
+///     sophia::parser::turtle::parse_str(tbox),
+///     RuleProfile::RDFS(),
+/// ).unwrap();
+/// let graph = schema.apply(sophia::parser::turtle::parse_str(abox)).unwrap();
+///
+/// let bart = BoxTerm::new_iri_unchecked("http://example.org/bart");
+/// let person = BoxTerm::new_iri_unchecked("http://example.org/Person");
+/// assert!(graph.contains(&bart, &rdf::type_, &person).unwrap());
+/// ```
+pub struct CompiledSchema {
+    graph: InfGraph,
+    profile: RuleProfile,
+}
+
+impl CompiledSchema {
+    /// Reason over the given TBox with the given profile,
+    /// and keep the result for later application to ABoxes.
+    pub fn new<TS>(tbox: TS, profile: RuleProfile) -> Result<Self, TS::Error>
+    where
+        TS: TripleSource,
+    {
+        let graph = InfGraph::new(tbox, &profile)?;
+        Ok(Self { graph, profile })
+    }
+
+    /// Build a new `InfGraph` containing this schema and the given ABox,
+    /// and apply the instance-level rules of the profile to it.
+    ///
+    /// This schema is left untouched, and can be applied to other ABoxes.
+    pub fn apply<TS>(&self, abox: TS) -> Result<InfGraph, TS::Error>
+    where
+        TS: TripleSource,
+    {
+        let mut graph = self.graph.clone();
+        graph.insert_triples(abox)?;
+        graph
+            .process_instances(&self.profile, Interrupt::never(), &Silent)
+            .expect("processing can not be interrupted without cancel token, deadline nor limit");
+        Ok(graph)
+    }
+
+    /// The rule profile used by this schema.
+    #[inline]
+    pub fn profile(&self) -> &RuleProfile {
+        &self.profile
+    }
+
+    /// The total number of triples (explicit + inferred)
+    /// in this schema.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.graph.size()
+    }
+}
@@ -0,0 +1,132 @@
+//! SPARQL queries, evaluated against an `InfGraph`.
+//!
+//! Since the inferences are materialized in the graph,
+//! queries are answered under the entailment regime of its rule profile
+//! (e.g. RDFS or RDFS-Plus) by simple pattern matching.
+//!
+//! The supported subset of SPARQL 1.1 is:
+//! - `SELECT` (with `DISTINCT`, `REDUCED` and `*`), `ASK` and `CONSTRUCT` (including `CONSTRUCT WHERE`);
+//! - `PREFIX` and `BASE`;
+//! - basic graph patterns (with `;`, `,` and `a`), `OPTIONAL`, `UNION`, groups and `FILTER`;
+//! - property paths: sequences (`/`), alternatives (`|`), inverses (`^`), and the `*`, `+` and `?` modifiers,
+//!   on any property (not only on those declared `owl:TransitiveProperty`);
+//! - in filters, the logical, comparison and arithmetic operators, `BOUND`,
+//!   and the functions `STR`, `LANG`, `DATATYPE`, `isIRI`, `isBlank`, `isLiteral`, `isNumeric`,
+//!   `sameTerm`, `langMatches`, `CONTAINS`, `STRSTARTS`, `STRENDS`, `STRLEN`, `UCASE`, `LCASE`
+//!   and `REGEX`;
+//! - `ORDER BY` (with `ASC` and `DESC`), `LIMIT` and `OFFSET`.
+//!
+//! Queries using other features (e.g. negated property sets, `BIND`, aggregates)
+//! are rejected by the parser.
+
+use std::error::Error;
+use std::fmt;
+
+use sophia_term::ArcTerm;
+
+use crate::inferray::InfGraph;
+use crate::interrupt::{Interrupt, ProcessError, ProcessOptions};
+
+mod algebra;
+mod eval;
+mod parser;
+mod path;
+
+/// A parsed SPARQL query.
+///
+/// Parsing a query once is useful to evaluate it against several graphs
+/// (see `InfGraph::execute`).
+#[derive(Clone, Debug)]
+pub struct Query(algebra::QueryAlgebra);
+
+impl Query {
+    /// Parse the SPARQL query `query`.
+    pub fn parse(query: &str) -> Result<Self, SparqlError> {
+        parser::parse_query(query).map(Query)
+    }
+}
+
+/// The results of a SPARQL query.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryResults {
+    /// the solutions of a `SELECT` query:
+    /// the projected variables, and for each solution, their values (`None` if unbound)
+    Solutions {
+        variables: Vec<String>,
+        rows: Vec<Vec<Option<ArcTerm>>>,
+    },
+    /// the result of an `ASK` query
+    Boolean(bool),
+    /// the triples built by a `CONSTRUCT` query
+    Graph(Vec<[ArcTerm; 3]>),
+}
+
+/// A syntax error in a SPARQL query, or the use of an unsupported feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SparqlError {
+    /// the byte offset of the error in the query
+    pub position: usize,
+    pub message: String,
+}
+
+impl SparqlError {
+    pub(crate) fn new<M: Into<String>>(position: usize, message: M) -> Self {
+        SparqlError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SparqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid SPARQL query at byte {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for SparqlError {}
+
+impl InfGraph {
+    /// Parse and evaluate the SPARQL query `query` against this graph.
+    ///
+    /// # Example
+    /// ```
+    /// # use inferrust::*;
+    /// let graph = InfGraph::new_rdfs(sophia::parser::turtle::parse_str(r#"
+    ///     @prefix : <http://example.org/> .
+    ///     @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+    ///     :bart a :Boy .
+    ///     :Boy rdfs:subClassOf :Person .
+    /// "#)).unwrap();
+    /// let results = graph.query("ASK { <http://example.org/bart> a <http://example.org/Person> }").unwrap();
+    /// assert_eq!(results, QueryResults::Boolean(true));
+    /// ```
+    pub fn query(&self, query: &str) -> Result<QueryResults, SparqlError> {
+        Ok(self.execute(&Query::parse(query)?))
+    }
+
+    /// Evaluate the parsed SPARQL query `query` against this graph.
+    pub fn execute(&self, query: &Query) -> QueryResults {
+        eval::evaluate(self, &query.0, Interrupt::never()).expect("evaluation can not be interrupted without options")
+    }
+
+    /// Evaluate the parsed SPARQL query `query` against this graph,
+    /// unless it is cancelled or times out according to `options`
+    /// (its limits on reasoning, its observer and its thread pool are ignored).
+    ///
+    /// # Example
+    /// ```
+    /// # use inferrust::*;
+    /// # use std::time::Duration;
+    /// let graph = InfGraph::new_rdfs(sophia::parser::turtle::parse_str(
+    ///     "<http://example.org/a> <http://example.org/p> <http://example.org/b> .",
+    /// )).unwrap();
+    /// let query = Query::parse("SELECT ?x { ?x <http://example.org/p>+ ?y }").unwrap();
+    /// let options = ProcessOptions::new().with_timeout(Duration::from_secs(10));
+    /// assert!(graph.execute_with(&query, &options).is_ok());
+    /// ```
+    pub fn execute_with(&self, query: &Query, options: &ProcessOptions) -> Result<QueryResults, ProcessError> {
+        let interrupt = Interrupt::new(options);
+        eval::evaluate(self, &query.0, &interrupt).map_err(|stop| self.process_error(stop))
+    }
+}
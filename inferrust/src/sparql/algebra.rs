@@ -0,0 +1,148 @@
+//! The algebra of the supported SPARQL queries,
+//! as produced by the parser and evaluated against an `InfGraph`.
+//!
+//! Variables are numbered (in order of first appearance in the query),
+//! so that solutions can be stored as vectors of indexes.
+
+use sophia_term::ArcTerm;
+
+/// A position of a triple pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TermPattern {
+    /// the variable with the given number
+    Var(usize),
+    Term(ArcTerm),
+}
+
+pub(crate) type TriplePattern = [TermPattern; 3];
+
+/// A graph pattern, translated as prescribed by section 18.2 of the SPARQL 1.1 recommendation.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum GraphPattern {
+    Bgp(Vec<TriplePattern>),
+    Join(Box<GraphPattern>, Box<GraphPattern>),
+    /// `OPTIONAL`, with the filters of the optional group (if any)
+    LeftJoin(Box<GraphPattern>, Box<GraphPattern>, Option<Expr>),
+    Union(Box<GraphPattern>, Box<GraphPattern>),
+    Filter(Expr, Box<GraphPattern>),
+    /// a triple pattern whose predicate is a property path (other than a single IRI)
+    Path(TermPattern, PropertyPath, TermPattern),
+}
+
+/// A property path.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PropertyPath {
+    Predicate(ArcTerm),
+    /// `^path`
+    Inverse(Box<PropertyPath>),
+    /// `path1/path2`
+    Sequence(Box<PropertyPath>, Box<PropertyPath>),
+    /// `path1|path2`
+    Alternative(Box<PropertyPath>, Box<PropertyPath>),
+    /// `path*`
+    ZeroOrMore(Box<PropertyPath>),
+    /// `path+`
+    OneOrMore(Box<PropertyPath>),
+    /// `path?`
+    ZeroOrOne(Box<PropertyPath>),
+}
+
+/// The supported built-in functions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Function {
+    Str,
+    Lang,
+    Datatype,
+    IsIri,
+    IsBlank,
+    IsLiteral,
+    IsNumeric,
+    SameTerm,
+    LangMatches,
+    Contains,
+    StrStarts,
+    StrEnds,
+    StrLen,
+    UCase,
+    LCase,
+    Regex,
+}
+
+impl Function {
+    /// The function named `name` (case insensitive), with its minimum and maximum arity.
+    pub fn by_name(name: &str) -> Option<(Self, usize, usize)> {
+        use Function::*;
+        Some(match name.to_ascii_uppercase().as_str() {
+            "STR" => (Str, 1, 1),
+            "LANG" => (Lang, 1, 1),
+            "DATATYPE" => (Datatype, 1, 1),
+            "ISIRI" | "ISURI" => (IsIri, 1, 1),
+            "ISBLANK" => (IsBlank, 1, 1),
+            "ISLITERAL" => (IsLiteral, 1, 1),
+            "ISNUMERIC" => (IsNumeric, 1, 1),
+            "SAMETERM" => (SameTerm, 2, 2),
+            "LANGMATCHES" => (LangMatches, 2, 2),
+            "CONTAINS" => (Contains, 2, 2),
+            "STRSTARTS" => (StrStarts, 2, 2),
+            "STRENDS" => (StrEnds, 2, 2),
+            "STRLEN" => (StrLen, 1, 1),
+            "UCASE" => (UCase, 1, 1),
+            "LCASE" => (LCase, 1, 1),
+            "REGEX" => (Regex, 2, 3),
+            _ => return None,
+        })
+    }
+}
+
+/// A filter expression.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Var(usize),
+    Term(ArcTerm),
+    Bound(usize),
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Equal(Box<Expr>, Box<Expr>),
+    NotEqual(Box<Expr>, Box<Expr>),
+    Less(Box<Expr>, Box<Expr>),
+    Greater(Box<Expr>, Box<Expr>),
+    LessOrEqual(Box<Expr>, Box<Expr>),
+    GreaterOrEqual(Box<Expr>, Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Subtract(Box<Expr>, Box<Expr>),
+    Multiply(Box<Expr>, Box<Expr>),
+    Divide(Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+/// A condition of `ORDER BY`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct OrderCondition {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+/// The form of a query, and what it returns.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum QueryForm {
+    /// the projected variables (by number), and whether solutions are `DISTINCT`
+    Select { projection: Vec<usize>, distinct: bool },
+    Ask,
+    /// the triples to instantiate with every solution
+    Construct(Vec<TriplePattern>),
+}
+
+/// A parsed query.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct QueryAlgebra {
+    pub form: QueryForm,
+    pub pattern: GraphPattern,
+    /// the names of the variables, by number
+    /// (blank nodes of the `WHERE` clause are variables named `_:label`)
+    pub variables: Vec<String>,
+    pub order_by: Vec<OrderCondition>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
@@ -0,0 +1,886 @@
+//! The evaluation of the SPARQL algebra against an `InfGraph`.
+//!
+//! Solutions are rows of optional indexes (one per variable);
+//! terms are only decoded for filter expressions and in the final results.
+//!
+//! Interruptions are checked before each operator, and during the search of property paths;
+//! once interrupted, operators give up and return no solutions.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+use sophia_api::term::{TTerm, TermKind};
+use sophia_term::ArcTerm;
+
+use super::algebra::*;
+use super::parser::XSD;
+use super::path;
+use super::QueryResults;
+use crate::inferray::{InfGraph, Slot};
+use crate::interrupt::{Interrupt, Stop};
+
+const RDF_LANG_STRING: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString";
+
+type Row = Vec<Option<u64>>;
+
+/// The first index given to the terms absent from the graph,
+/// above all the indexes of its dictionary (see `Evaluator::unknown`).
+const UNKNOWN_BASE: u64 = 1 << 62;
+
+/// Evaluate `query` against `graph`, unless `interrupt` stops it.
+pub(crate) fn evaluate(graph: &InfGraph, query: &QueryAlgebra, interrupt: &Interrupt) -> Result<QueryResults, Stop> {
+    let mut unknown = vec![];
+    unknown_ends(graph, &query.pattern, &mut unknown);
+    let evaluator = Evaluator {
+        graph,
+        interrupt,
+        unknown,
+        regexes: RefCell::new(HashMap::new()),
+    };
+    let mut rows = evaluator.eval_pattern(&query.pattern, query.variables.len());
+    interrupt.status()?;
+    if !query.order_by.is_empty() {
+        let mut keyed: Vec<(Vec<Option<Value>>, Row)> = rows
+            .into_iter()
+            .map(|row| {
+                let keys = query.order_by.iter().map(|c| evaluator.eval(&c.expr, &row)).collect();
+                (keys, row)
+            })
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| {
+            query
+                .order_by
+                .iter()
+                .zip(a.iter().zip(b.iter()))
+                .map(|(c, (a, b))| {
+                    let ord = order(a, b);
+                    if c.descending {
+                        ord.reverse()
+                    } else {
+                        ord
+                    }
+                })
+                .find(|ord| *ord != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+        rows = keyed.into_iter().map(|(_, row)| row).collect();
+    }
+    let limit = query.limit.unwrap_or(usize::MAX);
+    Ok(match &query.form {
+        QueryForm::Ask => QueryResults::Boolean(!rows.is_empty()),
+        QueryForm::Select { projection, distinct } => {
+            let mut projected: Vec<Row> = rows
+                .into_iter()
+                .map(|row| projection.iter().map(|v| row[*v]).collect())
+                .collect();
+            if *distinct {
+                let mut seen = HashSet::new();
+                projected.retain(|row| seen.insert(row.clone()));
+            }
+            let decoded = projected
+                .into_iter()
+                .skip(query.offset)
+                .take(limit)
+                .map(|row| row.into_iter().map(|idx| idx.map(|i| evaluator.term(i).clone())).collect())
+                .collect();
+            QueryResults::Solutions {
+                variables: projection.iter().map(|v| query.variables[*v].clone()).collect(),
+                rows: decoded,
+            }
+        }
+        QueryForm::Construct(template) => {
+            let mut triples = vec![];
+            let mut seen = HashSet::new();
+            let mut fresh = FreshBNodes { graph, counter: 0 };
+            for row in rows.into_iter().skip(query.offset).take(limit) {
+                let mut bnodes = HashMap::new();
+                for pattern in template {
+                    if let Some(triple) = instantiate(&evaluator, pattern, &row, &mut bnodes, &mut fresh) {
+                        if seen.insert(triple.clone()) {
+                            triples.push(triple);
+                        }
+                    }
+                }
+            }
+            QueryResults::Graph(triples)
+        }
+    })
+}
+
+/// Instantiate the template triple `pattern` with the solution `row`,
+/// unless a variable is unbound or the triple is not valid RDF.
+///
+/// Blank nodes of the template are replaced by fresh blank nodes,
+/// shared by the triples of the same solution (`bnodes`, by label).
+fn instantiate(
+    evaluator: &Evaluator,
+    pattern: &TriplePattern,
+    row: &Row,
+    bnodes: &mut HashMap<String, ArcTerm>,
+    fresh: &mut FreshBNodes,
+) -> Option<[ArcTerm; 3]> {
+    let mut term = |tp: &TermPattern| match tp {
+        TermPattern::Var(v) => row[*v].map(|i| evaluator.term(i).clone()),
+        TermPattern::Term(t) if t.kind() == TermKind::BlankNode => Some(
+            bnodes
+                .entry(t.value().to_string())
+                .or_insert_with(|| fresh.next())
+                .clone(),
+        ),
+        TermPattern::Term(t) => Some(t.clone()),
+    };
+    let triple = [term(&pattern[0])?, term(&pattern[1])?, term(&pattern[2])?];
+    if triple[0].kind() == TermKind::Literal || triple[1].kind() != TermKind::Iri {
+        return None;
+    }
+    Some(triple)
+}
+
+/// A generator of blank nodes that are not in `graph`
+/// (so that CONSTRUCT results do not merge them with the nodes of the graph).
+struct FreshBNodes<'g> {
+    graph: &'g InfGraph,
+    counter: usize,
+}
+
+impl<'g> FreshBNodes<'g> {
+    fn next(&mut self) -> ArcTerm {
+        loop {
+            let bnode = ArcTerm::new_bnode_unchecked(format!("c{}", self.counter));
+            self.counter += 1;
+            if self.graph.index_of(&bnode).is_none() {
+                return bnode;
+            }
+        }
+    }
+}
+
+/// Collect in `unknown` the constant ends of the path patterns of `pattern` that are absent from `graph`
+/// (which zero-length paths still match), without duplicates.
+fn unknown_ends(graph: &InfGraph, pattern: &GraphPattern, unknown: &mut Vec<ArcTerm>) {
+    match pattern {
+        GraphPattern::Bgp(_) => (),
+        GraphPattern::Join(left, right) | GraphPattern::LeftJoin(left, right, _) | GraphPattern::Union(left, right) => {
+            unknown_ends(graph, left, unknown);
+            unknown_ends(graph, right, unknown);
+        }
+        GraphPattern::Filter(_, inner) => unknown_ends(graph, inner, unknown),
+        GraphPattern::Path(subject, _, object) => {
+            for tp in [subject, object].iter() {
+                if let TermPattern::Term(t) = tp {
+                    if graph.index_of(t).is_none() && !unknown.contains(t) {
+                        unknown.push(t.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Evaluator<'g> {
+    graph: &'g InfGraph,
+    interrupt: &'g Interrupt,
+    /// the constant ends of path patterns absent from the graph,
+    /// indexed from `UNKNOWN_BASE` in solutions
+    unknown: Vec<ArcTerm>,
+    /// the compiled regular expressions, by pattern and flags (`None` if invalid)
+    regexes: RefCell<HashMap<(String, String), Option<Regex>>>,
+}
+
+impl<'g> Evaluator<'g> {
+    /// The term with index `idx`, in the graph or among the `unknown` ones.
+    fn term(&self, idx: u64) -> &ArcTerm {
+        if idx >= UNKNOWN_BASE {
+            &self.unknown[(idx - UNKNOWN_BASE) as usize]
+        } else {
+            self.graph.term(idx)
+        }
+    }
+
+    /// The index of `term`, one of the `unknown` terms.
+    fn unknown_index(&self, term: &ArcTerm) -> u64 {
+        let position = self.unknown.iter().position(|u| u == term).expect("collected by unknown_ends");
+        UNKNOWN_BASE + position as u64
+    }
+
+    fn eval_pattern(&self, pattern: &GraphPattern, nb_vars: usize) -> Vec<Row> {
+        if self.interrupt.check() {
+            return vec![];
+        }
+        match pattern {
+            GraphPattern::Bgp(triples) => self.eval_bgp(triples, nb_vars),
+            GraphPattern::Join(left, right) => {
+                let left = self.eval_pattern(left, nb_vars);
+                if left.is_empty() {
+                    return left;
+                }
+                let right = self.eval_pattern(right, nb_vars);
+                join(left, &right, |_| true)
+            }
+            GraphPattern::LeftJoin(left, right, filter) => {
+                let left = self.eval_pattern(left, nb_vars);
+                if left.is_empty() {
+                    return left;
+                }
+                let right = self.eval_pattern(right, nb_vars);
+                left_join(left, &right, |row| match filter {
+                    Some(expr) => self.holds(expr, row),
+                    None => true,
+                })
+            }
+            GraphPattern::Union(left, right) => {
+                let mut rows = self.eval_pattern(left, nb_vars);
+                rows.extend(self.eval_pattern(right, nb_vars));
+                rows
+            }
+            GraphPattern::Filter(expr, inner) => {
+                let mut rows = self.eval_pattern(inner, nb_vars);
+                rows.retain(|row| self.holds(expr, row));
+                rows
+            }
+            GraphPattern::Path(subject, path, object) => self.eval_path(subject, path, object, nb_vars),
+        }
+    }
+
+    /// Evaluate a basic graph pattern with `InfGraph::query_ids`,
+    /// then expand the `owl:sameAs` cliques (if they are canonicalized),
+    /// so that the other operators work on the actual terms.
+    fn eval_bgp(&self, triples: &[TriplePattern], nb_vars: usize) -> Vec<Row> {
+        let mut used = vec![false; nb_vars];
+        let mut encoded = Vec::with_capacity(triples.len());
+        for triple in triples {
+            let mut slots = [Slot::Const(0); 3];
+            for (slot, tp) in slots.iter_mut().zip(triple.iter()) {
+                *slot = match tp {
+                    TermPattern::Var(v) => {
+                        used[*v] = true;
+                        Slot::Var(*v)
+                    }
+                    TermPattern::Term(t) => match self.graph.index_of(t) {
+                        Some(idx) => Slot::Const(idx),
+                        // a term absent from the dictionary matches nothing
+                        None => return vec![],
+                    },
+                };
+            }
+            encoded.push(slots);
+        }
+        let ids = self.graph.query_ids(&encoded, nb_vars);
+        let mut rows = vec![];
+        for id_row in ids.rows {
+            let row = id_row
+                .into_iter()
+                .zip(used.iter())
+                .map(|(idx, used)| if *used { Some(idx) } else { None })
+                .collect();
+            rows.extend(self.expand(row));
+        }
+        rows
+    }
+
+    /// Evaluate the triple pattern `subject path object`,
+    /// by a search from its constant end if any, or from the relation denoted by `path`.
+    fn eval_path(&self, subject: &TermPattern, path: &PropertyPath, object: &TermPattern, nb_vars: usize) -> Vec<Row> {
+        let mut ends = [Slot::Const(0); 2];
+        for (end, tp) in ends.iter_mut().zip([subject, object].iter()) {
+            *end = match tp {
+                TermPattern::Var(v) => Slot::Var(*v),
+                TermPattern::Term(t) => match self.graph.index_of(t) {
+                    Some(idx) => Slot::Const(idx),
+                    // a term absent from the graph is only matched by zero-length paths,
+                    // which the search from it finds
+                    None => Slot::Const(self.unknown_index(t)),
+                },
+            };
+        }
+        // only the paths between two variables require the whole relation
+        let pairs: Vec<[u64; 2]> = match ends {
+            [Slot::Const(s), Slot::Const(o)] => {
+                match path::reachable(self.graph, path, &[s], true, self.interrupt).binary_search(&o) {
+                    Ok(_) => vec![[s, o]],
+                    Err(_) => vec![],
+                }
+            }
+            [Slot::Const(s), _] => path::reachable(self.graph, path, &[s], true, self.interrupt)
+                .into_iter()
+                .map(|y| [s, y])
+                .collect(),
+            [_, Slot::Const(o)] => path::reachable(self.graph, path, &[o], false, self.interrupt)
+                .into_iter()
+                .map(|x| [x, o])
+                .collect(),
+            _ => {
+                let relation = path::relation(self.graph, path, self.interrupt);
+                let mut pairs = relation.pairs.into_owned();
+                if relation.reflexive {
+                    pairs.extend(path::nodes(self.graph).into_iter().map(|x| [x, x]));
+                    pairs.sort_unstable();
+                    pairs.dedup();
+                }
+                pairs
+            }
+        };
+        let mut rows = vec![];
+        for [x, y] in pairs {
+            let mut row = vec![None; nb_vars];
+            match ends {
+                [Slot::Var(s), Slot::Var(o)] if s == o && x != y => continue,
+                _ => (),
+            }
+            for (end, idx) in ends.iter().zip([x, y].iter()) {
+                if let Slot::Var(v) = end {
+                    row[*v] = Some(*idx);
+                }
+            }
+            rows.extend(self.expand(row));
+        }
+        rows
+    }
+
+    /// Expand the `owl:sameAs` cliques (if they are canonicalized) of the indexes of `row`.
+    fn expand(&self, row: Row) -> Vec<Row> {
+        let mut expanded = vec![row];
+        for v in 0..expanded[0].len() {
+            if let Some(idx) = expanded[0][v] {
+                let members: Vec<u64> = self.graph.expand(idx).collect();
+                if members.len() > 1 {
+                    expanded = expanded
+                        .into_iter()
+                        .flat_map(|row| {
+                            members.iter().map(move |idx| {
+                                let mut row = row.clone();
+                                row[v] = Some(*idx);
+                                row
+                            })
+                        })
+                        .collect();
+                }
+            }
+        }
+        expanded
+    }
+
+    /// Whether the effective boolean value of `expr` is true for `row`
+    /// (errors are false).
+    fn holds(&self, expr: &Expr, row: &Row) -> bool {
+        self.ebv(expr, row) == Some(true)
+    }
+
+    /// The effective boolean value of `expr` for `row`, or `None` in case of error.
+    fn ebv(&self, expr: &Expr, row: &Row) -> Option<bool> {
+        self.eval(expr, row)?.ebv()
+    }
+
+    /// The value of `expr` for `row`, or `None` in case of error.
+    fn eval(&self, expr: &Expr, row: &Row) -> Option<Value> {
+        use Expr::*;
+        Some(match expr {
+            Var(v) => Value::from_term(self.term(row[*v]?)),
+            Term(t) => Value::from_term(t),
+            Bound(v) => Value::boolean(row[*v].is_some()),
+            Or(a, b) => match (self.ebv(a, row), self.ebv(b, row)) {
+                (Some(true), _) | (_, Some(true)) => Value::boolean(true),
+                (Some(false), Some(false)) => Value::boolean(false),
+                _ => return None,
+            },
+            And(a, b) => match (self.ebv(a, row), self.ebv(b, row)) {
+                (Some(false), _) | (_, Some(false)) => Value::boolean(false),
+                (Some(true), Some(true)) => Value::boolean(true),
+                _ => return None,
+            },
+            Not(a) => Value::boolean(!self.ebv(a, row)?),
+            Equal(a, b) => Value::boolean(self.eval(a, row)?.equals(&self.eval(b, row)?)?),
+            NotEqual(a, b) => Value::boolean(!self.eval(a, row)?.equals(&self.eval(b, row)?)?),
+            Less(a, b) => Value::boolean(self.compare(a, b, row)? == Ordering::Less),
+            Greater(a, b) => Value::boolean(self.compare(a, b, row)? == Ordering::Greater),
+            LessOrEqual(a, b) => Value::boolean(self.compare(a, b, row)? != Ordering::Greater),
+            GreaterOrEqual(a, b) => Value::boolean(self.compare(a, b, row)? != Ordering::Less),
+            Add(a, b) => self.arithmetic(a, b, row, |x, y| x + y)?,
+            Subtract(a, b) => self.arithmetic(a, b, row, |x, y| x - y)?,
+            Multiply(a, b) => self.arithmetic(a, b, row, |x, y| x * y)?,
+            Divide(a, b) => {
+                let (x, tx) = self.eval(a, row)?.numeric()?;
+                let (y, ty) = self.eval(b, row)?.numeric()?;
+                let kind = tx.max(ty).max(NumKind::Decimal);
+                if y == 0.0 && kind == NumKind::Decimal {
+                    return None;
+                }
+                Value::number(x / y, kind)
+            }
+            Negate(a) => {
+                let (x, kind) = self.eval(a, row)?.numeric()?;
+                Value::number(-x, kind)
+            }
+            Call(function, args) => {
+                let args = args.iter().map(|a| self.eval(a, row)).collect::<Option<Vec<_>>>()?;
+                self.call(*function, args)?
+            }
+        })
+    }
+
+    fn compare(&self, a: &Expr, b: &Expr, row: &Row) -> Option<Ordering> {
+        self.eval(a, row)?.compare(&self.eval(b, row)?)
+    }
+
+    fn arithmetic(&self, a: &Expr, b: &Expr, row: &Row, op: fn(f64, f64) -> f64) -> Option<Value> {
+        let (x, tx) = self.eval(a, row)?.numeric()?;
+        let (y, ty) = self.eval(b, row)?.numeric()?;
+        Some(Value::number(op(x, y), tx.max(ty)))
+    }
+
+    fn call(&self, function: Function, mut args: Vec<Value>) -> Option<Value> {
+        use Function::*;
+        let arg = &args[0];
+        Some(match function {
+            Str => match arg {
+                Value::Iri(i) => Value::text(i.clone()),
+                Value::Literal { lexical, .. } => Value::text(lexical.clone()),
+                Value::Blank(_) => return None,
+            },
+            Lang => match arg {
+                Value::Literal { lang, .. } => Value::text(lang.clone().unwrap_or_default()),
+                _ => return None,
+            },
+            Datatype => match arg {
+                Value::Literal { datatype, .. } => Value::Iri(datatype.clone()),
+                _ => return None,
+            },
+            IsIri => Value::boolean(matches!(arg, Value::Iri(_))),
+            IsBlank => Value::boolean(matches!(arg, Value::Blank(_))),
+            IsLiteral => Value::boolean(matches!(arg, Value::Literal { .. })),
+            IsNumeric => Value::boolean(arg.numeric().is_some()),
+            SameTerm => Value::boolean(args[0] == args[1]),
+            LangMatches => {
+                let tag = args[0].simple_string()?.to_ascii_lowercase();
+                let range = args[1].simple_string()?.to_ascii_lowercase();
+                Value::boolean(if range == "*" {
+                    !tag.is_empty()
+                } else {
+                    tag == range || tag.starts_with(&format!("{}-", range))
+                })
+            }
+            Contains | StrStarts | StrEnds => {
+                let (text, pattern) = string_args(&args[0], &args[1])?;
+                Value::boolean(match function {
+                    Contains => text.contains(pattern),
+                    StrStarts => text.starts_with(pattern),
+                    _ => text.ends_with(pattern),
+                })
+            }
+            StrLen => {
+                let (text, _) = arg.string()?;
+                Value::number(text.chars().count() as f64, NumKind::Integer)
+            }
+            UCase | LCase => {
+                arg.string()?;
+                let mut value = args.swap_remove(0);
+                if let Value::Literal { lexical, .. } = &mut value {
+                    *lexical = if function == UCase {
+                        lexical.to_uppercase()
+                    } else {
+                        lexical.to_lowercase()
+                    };
+                }
+                value
+            }
+            Regex => {
+                let (text, _) = args[0].string()?;
+                let pattern = args[1].simple_string()?;
+                let flags = match args.get(2) {
+                    Some(flags) => flags.simple_string()?,
+                    None => "",
+                };
+                let mut regexes = self.regexes.borrow_mut();
+                let regex = regexes
+                    .entry((pattern.to_string(), flags.to_string()))
+                    .or_insert_with(|| compile_regex(pattern, flags));
+                Value::boolean(regex.as_ref()?.is_match(text))
+            }
+        })
+    }
+}
+
+/// Compile a SPARQL regular expression with its flags (`s`, `m`, `i` and `x`).
+fn compile_regex(pattern: &str, flags: &str) -> Option<Regex> {
+    if flags.chars().any(|c| !"smix".contains(c)) {
+        return None;
+    }
+    let pattern = if flags.is_empty() {
+        pattern.to_string()
+    } else {
+        format!("(?{}){}", flags, pattern)
+    };
+    Regex::new(&pattern).ok()
+}
+
+/// The lexical forms of two string arguments, if they are compatible
+/// (the second one has no language tag, or the same one as the first).
+fn string_args<'a>(a: &'a Value, b: &'a Value) -> Option<(&'a str, &'a str)> {
+    let (text, lang_a) = a.string()?;
+    let (pattern, lang_b) = b.string()?;
+    if lang_b.is_some() && lang_a != lang_b {
+        return None;
+    }
+    Some((text, pattern))
+}
+
+/// Whether the rows `a` and `b` agree on the variables bound in both.
+fn compatible(a: &Row, b: &Row) -> bool {
+    a.iter().zip(b.iter()).all(|(x, y)| x.is_none() || y.is_none() || x == y)
+}
+
+fn merge(a: &Row, b: &Row) -> Row {
+    a.iter().zip(b.iter()).map(|(x, y)| x.or(*y)).collect()
+}
+
+/// Group `right` by the values of the variables bound in all the rows of both sides,
+/// so that each row of `left` only has to be checked against one group.
+fn buckets<'r>(left: &[Row], right: &'r [Row]) -> (Vec<usize>, HashMap<Vec<u64>, Vec<&'r Row>>) {
+    let nb_vars = left.first().or_else(|| right.first()).map_or(0, Vec::len);
+    let keys: Vec<usize> = (0..nb_vars)
+        .filter(|v| left.iter().chain(right.iter()).all(|row| row[*v].is_some()))
+        .collect();
+    let mut buckets: HashMap<Vec<u64>, Vec<&Row>> = HashMap::new();
+    for row in right {
+        buckets.entry(key(&keys, row)).or_default().push(row);
+    }
+    (keys, buckets)
+}
+
+fn key(keys: &[usize], row: &Row) -> Vec<u64> {
+    keys.iter().map(|v| row[*v].unwrap()).collect()
+}
+
+/// The merged compatible pairs of `left` and `right` satisfying `filter`.
+fn join<F: Fn(&Row) -> bool>(left: Vec<Row>, right: &[Row], filter: F) -> Vec<Row> {
+    let (keys, buckets) = buckets(&left, right);
+    let mut rows = vec![];
+    for l in &left {
+        if let Some(bucket) = buckets.get(&key(&keys, l)) {
+            rows.extend(
+                bucket
+                    .iter()
+                    .filter(|r| compatible(l, r))
+                    .map(|r| merge(l, r))
+                    .filter(|row| filter(row)),
+            );
+        }
+    }
+    rows
+}
+
+/// Like `join`, but keeping the rows of `left` having no compatible row in `right`.
+fn left_join<F: Fn(&Row) -> bool>(left: Vec<Row>, right: &[Row], filter: F) -> Vec<Row> {
+    let (keys, buckets) = buckets(&left, right);
+    let mut rows = vec![];
+    for l in left {
+        let before = rows.len();
+        if let Some(bucket) = buckets.get(&key(&keys, &l)) {
+            rows.extend(
+                bucket
+                    .iter()
+                    .filter(|r| compatible(&l, r))
+                    .map(|r| merge(&l, r))
+                    .filter(|row| filter(row)),
+            );
+        }
+        if rows.len() == before {
+            rows.push(l);
+        }
+    }
+    rows
+}
+
+/// The order of `ORDER BY`: unbound, then blank nodes, IRIs and literals.
+///
+/// Literals are ranked by class (numbers, booleans, strings, then the others),
+/// ordered by value inside their class, and by lexical form and datatype otherwise,
+/// so that this is a total order (as `sort_by` requires), even with NaN.
+fn order(a: &Option<Value>, b: &Option<Value>) -> Ordering {
+    let rank = |v: &Option<Value>| match v {
+        None => 0,
+        Some(Value::Blank(_)) => 1,
+        Some(Value::Iri(_)) => 2,
+        Some(Value::Literal { .. }) => 3,
+    };
+    match (a, b) {
+        (Some(Value::Blank(x)), Some(Value::Blank(y))) | (Some(Value::Iri(x)), Some(Value::Iri(y))) => x.cmp(y),
+        (Some(x @ Value::Literal { .. }), Some(y @ Value::Literal { .. })) => {
+            let class = |v: &Value| match (v.numeric(), v.bool(), v.string()) {
+                (Some(_), _, _) => 0,
+                (_, Some(_), _) => 1,
+                (_, _, Some(_)) => 2,
+                _ => 3,
+            };
+            let by_value = match (x.numeric(), y.numeric(), x.bool(), y.bool()) {
+                (Some((x, _)), Some((y, _)), _, _) => x.total_cmp(&y),
+                (_, _, Some(x), Some(y)) => x.cmp(&y),
+                // strings are ordered by lexical form first, as in the derived order
+                _ => Ordering::Equal,
+            };
+            class(x).cmp(&class(y)).then(by_value).then_with(|| x.cmp(y))
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// The numeric datatypes, in the order of type promotion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum NumKind {
+    Integer,
+    Decimal,
+    Float,
+    Double,
+}
+
+impl NumKind {
+    fn of(datatype: &str) -> Option<Self> {
+        let local = datatype.strip_prefix(XSD)?;
+        Some(match local {
+            "integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger" | "positiveInteger"
+            | "negativeInteger" | "nonPositiveInteger" | "unsignedLong" | "unsignedInt" | "unsignedShort"
+            | "unsignedByte" => NumKind::Integer,
+            "decimal" => NumKind::Decimal,
+            "float" => NumKind::Float,
+            "double" => NumKind::Double,
+            _ => return None,
+        })
+    }
+
+    fn datatype(self) -> String {
+        let local = match self {
+            NumKind::Integer => "integer",
+            NumKind::Decimal => "decimal",
+            NumKind::Float => "float",
+            NumKind::Double => "double",
+        };
+        format!("{}{}", XSD, local)
+    }
+}
+
+/// The value of an expression: an RDF term, decoded.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Iri(String),
+    Blank(String),
+    Literal {
+        lexical: String,
+        datatype: String,
+        lang: Option<String>,
+    },
+}
+
+impl Value {
+    fn from_term(term: &ArcTerm) -> Self {
+        match term.kind() {
+            TermKind::BlankNode => Value::Blank(term.value().to_string()),
+            TermKind::Literal => match term.language() {
+                Some(lang) => Value::Literal {
+                    lexical: term.value().to_string(),
+                    datatype: RDF_LANG_STRING.to_string(),
+                    lang: Some(lang.to_string()),
+                },
+                None => Value::Literal {
+                    lexical: term.value().to_string(),
+                    datatype: term
+                        .datatype()
+                        .map_or_else(|| format!("{}string", XSD), |dt| dt.value().to_string()),
+                    lang: None,
+                },
+            },
+            _ => Value::Iri(term.value().to_string()),
+        }
+    }
+
+    fn typed(lexical: String, datatype: String) -> Self {
+        Value::Literal { lexical, datatype, lang: None }
+    }
+
+    fn boolean(b: bool) -> Self {
+        Value::typed(b.to_string(), format!("{}boolean", XSD))
+    }
+
+    fn text(s: String) -> Self {
+        Value::typed(s, format!("{}string", XSD))
+    }
+
+    fn number(x: f64, kind: NumKind) -> Self {
+        let lexical = match kind {
+            NumKind::Integer => format!("{}", x as i64),
+            _ => format!("{}", x),
+        };
+        Value::typed(lexical, kind.datatype())
+    }
+
+    /// The value of a numeric literal, with its kind.
+    fn numeric(&self) -> Option<(f64, NumKind)> {
+        match self {
+            Value::Literal { lexical, datatype, .. } => {
+                let kind = NumKind::of(datatype)?;
+                let x = match lexical.trim() {
+                    "INF" | "+INF" => f64::INFINITY,
+                    "-INF" => f64::NEG_INFINITY,
+                    "NaN" => f64::NAN,
+                    lexical => lexical.parse().ok()?,
+                };
+                Some((x, kind))
+            }
+            _ => None,
+        }
+    }
+
+    fn bool(&self) -> Option<bool> {
+        match self {
+            Value::Literal { lexical, datatype, .. } if *datatype == format!("{}boolean", XSD) => {
+                match lexical.as_str() {
+                    "true" | "1" => Some(true),
+                    "false" | "0" => Some(false),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The lexical form and language tag of a string (possibly with a language tag).
+    fn string(&self) -> Option<(&str, Option<&str>)> {
+        match self {
+            Value::Literal { lexical, lang: Some(lang), .. } => Some((lexical.as_str(), Some(lang.as_str()))),
+            Value::Literal { lexical, datatype, .. } if *datatype == format!("{}string", XSD) => {
+                Some((lexical.as_str(), None))
+            }
+            _ => None,
+        }
+    }
+
+    /// The lexical form of a string without language tag.
+    fn simple_string(&self) -> Option<&str> {
+        match self.string()? {
+            (s, None) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The effective boolean value of this value.
+    fn ebv(&self) -> Option<bool> {
+        if let Some(b) = self.bool() {
+            return Some(b);
+        }
+        if let Some((x, _)) = self.numeric() {
+            return Some(x != 0.0 && !x.is_nan());
+        }
+        self.string().map(|(s, _)| !s.is_empty())
+    }
+
+    /// Whether this value is equal to `other` (the `=` operator),
+    /// `None` if this cannot be decided (literals of unsupported datatypes).
+    fn equals(&self, other: &Value) -> Option<bool> {
+        if let (Some((x, _)), Some((y, _))) = (self.numeric(), other.numeric()) {
+            return Some(x == y);
+        }
+        if let (Some(x), Some(y)) = (self.bool(), other.bool()) {
+            return Some(x == y);
+        }
+        if self == other {
+            return Some(true);
+        }
+        let known = |v: &Value| v.numeric().is_some() || v.bool().is_some() || v.string().is_some();
+        match (self, other) {
+            (Value::Literal { .. }, Value::Literal { .. }) if !known(self) || !known(other) => None,
+            _ => Some(false),
+        }
+    }
+
+    /// The order of this value and `other` (for `<`, `>`, `<=` and `>=`),
+    /// if they are both numbers, booleans or simple strings.
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        if let (Some((x, _)), Some((y, _))) = (self.numeric(), other.numeric()) {
+            return x.partial_cmp(&y);
+        }
+        if let (Some(x), Some(y)) = (self.bool(), other.bool()) {
+            return Some(x.cmp(&y));
+        }
+        match (self.string()?, other.string()?) {
+            ((x, lx), (y, ly)) if lx == ly => Some(x.cmp(y)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::parser::{iri, typed_literal};
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        let int = |x: &str| Value::from_term(&typed_literal(x, &format!("{}integer", XSD)));
+        let dec = |x: &str| Value::from_term(&typed_literal(x, &format!("{}decimal", XSD)));
+        assert_eq!(int("2").equals(&dec("2.0")), Some(true));
+        assert_eq!(int("2").compare(&int("10")), Some(Ordering::Less));
+        assert_eq!(Value::text("b".into()).compare(&Value::text("a".into())), Some(Ordering::Greater));
+        assert_eq!(int("0").ebv(), Some(false));
+        assert_eq!(Value::text("".into()).ebv(), Some(false));
+        assert_eq!(Value::from_term(&iri("http://example.org/")).ebv(), None);
+        let unknown = Value::typed("x".into(), "http://example.org/dt".into());
+        assert_eq!(unknown.equals(&Value::typed("y".into(), "http://example.org/dt".into())), None);
+        assert_eq!(unknown.equals(&Value::Iri("x".into())), Some(false));
+        assert_eq!(Value::number(7.0, NumKind::Integer), int("7"));
+    }
+
+    #[test]
+    fn test_order() {
+        let int = |x: &str| Value::from_term(&typed_literal(x, &format!("{}integer", XSD)));
+        let double = |x: &str| Value::from_term(&typed_literal(x, &format!("{}double", XSD)));
+        let mut values = vec![];
+        for i in 0..2000 {
+            values.push(Some(match i % 5 {
+                0 => int(&(i % 97).to_string()),
+                1 => Value::text((i % 89).to_string()),
+                2 => double(if i % 3 == 0 { "NaN" } else { "-1.5" }),
+                3 => Value::boolean(i % 2 == 0),
+                _ => Value::Iri(format!("http://example.org/{}", i % 7)),
+            }));
+        }
+        values.push(None);
+        // the sort would panic on an inconsistent order
+        values.sort_by(order);
+        assert_eq!(values[0], None);
+        for pair in values.windows(2) {
+            assert_ne!(order(&pair[0], &pair[1]), Ordering::Greater);
+        }
+        let literals: Vec<&Value> = values
+            .iter()
+            .flatten()
+            .filter(|v| matches!(v, Value::Literal { .. }))
+            .collect();
+        // numbers (NaN last), then booleans, then strings
+        assert_eq!(literals[0], &double("-1.5"));
+        assert_eq!(literals[1..].iter().position(|v| v.numeric().is_none()), Some(799));
+        assert!(literals[799].numeric().unwrap().0.is_nan());
+        assert_eq!(literals[800], &Value::boolean(false));
+        assert_eq!(literals.last().unwrap(), &&Value::text("9".into()));
+
+        // 9 < 10 < "5", and not "5" < 9
+        let (nine, ten, five) = (Some(int("9")), Some(int("10")), Some(Value::text("5".into())));
+        assert_eq!(order(&nine, &ten), Ordering::Less);
+        assert_eq!(order(&ten, &five), Ordering::Less);
+        assert_eq!(order(&nine, &five), Ordering::Less);
+    }
+
+    #[test]
+    fn test_joins() {
+        let left = vec![vec![Some(1), None], vec![Some(2), None]];
+        let right = vec![vec![Some(1), Some(10)], vec![None, Some(20)]];
+        assert_eq!(
+            join(left.clone(), &right, |_| true),
+            vec![vec![Some(1), Some(10)], vec![Some(1), Some(20)], vec![Some(2), Some(20)]]
+        );
+        assert_eq!(
+            left_join(left, &right, |row| row[1] == Some(10)),
+            vec![vec![Some(1), Some(10)], vec![Some(2), None]]
+        );
+    }
+}
@@ -0,0 +1,935 @@
+//! A parser for the supported subset of SPARQL 1.1 (see the [module documentation](../index.html)).
+
+use std::collections::HashMap;
+
+use itertools::Either;
+use sophia_term::ArcTerm;
+
+use super::algebra::*;
+use super::SparqlError;
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+pub(crate) const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Iri(String),
+    /// a prefixed name, split into prefix and local name
+    PName(String, String),
+    Var(String),
+    BNode(String),
+    Str(String),
+    LangTag(String),
+    Integer(String),
+    Decimal(String),
+    Double(String),
+    /// keywords, function names, `a`, `true` and `false`
+    Word(String),
+    Punct(&'static str),
+}
+
+/// Split `input` into tokens, with their byte position.
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, SparqlError> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let offset = |i: usize| chars.get(i).map_or(input.len(), |(o, _)| *o);
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    let mut tokens = vec![];
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '#' => {
+                while at(i).map_or(false, |c| c != '\n') {
+                    i += 1;
+                }
+                continue;
+            }
+            '<' => {
+                // an IRI, unless it contains forbidden characters (then, an operator)
+                let mut j = i + 1;
+                while let Some(c) = at(j) {
+                    if c == '>' || c.is_whitespace() || "<\"{}|^`\\".contains(c) {
+                        break;
+                    }
+                    j += 1;
+                }
+                if at(j) == Some('>') {
+                    i = j + 1;
+                    Token::Iri(input[offset(start + 1)..offset(j)].to_string())
+                } else if at(i + 1) == Some('=') {
+                    i += 2;
+                    Token::Punct("<=")
+                } else {
+                    i += 1;
+                    Token::Punct("<")
+                }
+            }
+            '?' if !at(i + 1).map_or(false, is_name) => {
+                // the `?` modifier of property paths
+                i += 1;
+                Token::Punct("?")
+            }
+            '?' | '$' => {
+                i += 1;
+                while at(i).map_or(false, is_name) {
+                    i += 1;
+                }
+                if i == start + 1 {
+                    return Err(SparqlError::new(offset(start), "empty variable name"));
+                }
+                Token::Var(input[offset(start + 1)..offset(i)].to_string())
+            }
+            '_' if at(i + 1) == Some(':') => {
+                i += 2;
+                while at(i).map_or(false, |c| is_name(c) || c == '-') {
+                    i += 1;
+                }
+                Token::BNode(input[offset(start + 2)..offset(i)].to_string())
+            }
+            '"' | '\'' => {
+                let long = at(i + 1) == Some(c) && at(i + 2) == Some(c);
+                i += if long { 3 } else { 1 };
+                let mut value = String::new();
+                loop {
+                    match at(i) {
+                        None => return Err(SparqlError::new(offset(start), "unterminated string")),
+                        Some(d) if d == c && !long => {
+                            i += 1;
+                            break;
+                        }
+                        Some(d) if d == c && at(i + 1) == Some(c) && at(i + 2) == Some(c) => {
+                            i += 3;
+                            break;
+                        }
+                        Some('\\') => {
+                            let (escaped, len) = unescape(&chars[i + 1..])
+                                .ok_or_else(|| SparqlError::new(offset(i), "invalid escape sequence"))?;
+                            value.push(escaped);
+                            i += 1 + len;
+                        }
+                        Some('\n') if !long => {
+                            return Err(SparqlError::new(offset(start), "unterminated string"))
+                        }
+                        Some(d) => {
+                            value.push(d);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Str(value)
+            }
+            '@' => {
+                i += 1;
+                while at(i).map_or(false, |c| c.is_ascii_alphanumeric() || c == '-') {
+                    i += 1;
+                }
+                Token::LangTag(input[offset(start + 1)..offset(i)].to_string())
+            }
+            c if c.is_ascii_digit() => {
+                while at(i).map_or(false, |c| c.is_ascii_digit()) {
+                    i += 1;
+                }
+                let mut decimal = false;
+                if at(i) == Some('.') && at(i + 1).map_or(false, |c| c.is_ascii_digit()) {
+                    decimal = true;
+                    i += 1;
+                    while at(i).map_or(false, |c| c.is_ascii_digit()) {
+                        i += 1;
+                    }
+                }
+                let lexical = |i| input[offset(start)..offset(i)].to_string();
+                if at(i).map_or(false, |c| c == 'e' || c == 'E') {
+                    let mut j = i + 1;
+                    if at(j).map_or(false, |c| c == '+' || c == '-') {
+                        j += 1;
+                    }
+                    if at(j).map_or(false, |c| c.is_ascii_digit()) {
+                        i = j;
+                        while at(i).map_or(false, |c| c.is_ascii_digit()) {
+                            i += 1;
+                        }
+                        tokens.push((Token::Double(lexical(i)), offset(start)));
+                        continue;
+                    }
+                }
+                if decimal {
+                    Token::Decimal(lexical(i))
+                } else {
+                    Token::Integer(lexical(i))
+                }
+            }
+            c if c.is_alphabetic() || c == ':' => {
+                while at(i).map_or(false, |c| is_name(c) || c == '-' || c == '.' || c == ':') {
+                    i += 1;
+                }
+                // a name does not end with a dot (which ends the triple)
+                while at(i - 1) == Some('.') {
+                    i -= 1;
+                }
+                let word = &input[offset(start)..offset(i)];
+                match word.find(':') {
+                    Some(colon) => Token::PName(word[..colon].to_string(), word[colon + 1..].to_string()),
+                    None => Token::Word(word.to_string()),
+                }
+            }
+            _ => {
+                let two: String = chars[i..].iter().take(2).map(|(_, c)| c).collect();
+                let punct = ["<=", ">=", "!=", "&&", "||", "^^"]
+                    .iter()
+                    .find(|p| **p == two)
+                    .cloned()
+                    .or_else(|| {
+                        ["{", "}", "(", ")", ".", ";", ",", "*", "=", ">", "!", "+", "-", "/", "|", "^"]
+                            .iter()
+                            .find(|p| p.starts_with(c))
+                            .cloned()
+                    })
+                    .ok_or_else(|| SparqlError::new(offset(start), format!("unexpected character {:?}", c)))?;
+                i += punct.chars().count();
+                Token::Punct(punct)
+            }
+        };
+        tokens.push((token, offset(start)));
+    }
+    Ok(tokens)
+}
+
+/// Decode the escape sequence starting at `chars` (just after the backslash),
+/// returning the escaped character and the length of the sequence.
+fn unescape(chars: &[(usize, char)]) -> Option<(char, usize)> {
+    let c = chars.first()?.1;
+    let simple = match c {
+        't' => '\t',
+        'n' => '\n',
+        'r' => '\r',
+        'b' => '\u{8}',
+        'f' => '\u{c}',
+        '"' | '\'' | '\\' => c,
+        'u' | 'U' => {
+            let len = if c == 'u' { 4 } else { 8 };
+            let hex: String = chars.get(1..=len)?.iter().map(|(_, c)| c).collect();
+            let code = u32::from_str_radix(&hex, 16).ok()?;
+            return Some((std::char::from_u32(code)?, 1 + len));
+        }
+        _ => return None,
+    };
+    Some((simple, 1))
+}
+
+pub(crate) fn iri(iri: &str) -> ArcTerm {
+    ArcTerm::new_iri_unchecked(iri)
+}
+
+pub(crate) fn typed_literal(lexical: &str, datatype: &str) -> ArcTerm {
+    ArcTerm::new_literal_dt_unchecked(lexical, iri(datatype))
+}
+
+/// Parse the SPARQL query `input`.
+pub(crate) fn parse_query(input: &str) -> Result<QueryAlgebra, SparqlError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        end: input.len(),
+        prefixes: HashMap::new(),
+        base: None,
+        variables: vec![],
+    };
+    let query = parser.parse_query()?;
+    match parser.peek() {
+        None => Ok(query),
+        Some(_) => Err(parser.error("unexpected content after the query")),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// the length of the input, i.e. the position of errors at the end of the input
+    end: usize,
+    prefixes: HashMap<String, String>,
+    base: Option<String>,
+    variables: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn error<M: Into<String>>(&self, message: M) -> SparqlError {
+        let position = self.tokens.get(self.pos).map_or(self.end, |(_, p)| *p);
+        SparqlError::new(position, message)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Consume `punct` if it is the next token.
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Consume `keyword` if it is the next token.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), SparqlError> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", punct)))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SparqlError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {}", keyword)))
+        }
+    }
+
+    /// The number of the variable named `name`.
+    fn var(&mut self, name: &str) -> usize {
+        match self.variables.iter().position(|v| v == name) {
+            Some(i) => i,
+            None => {
+                self.variables.push(name.to_string());
+                self.variables.len() - 1
+            }
+        }
+    }
+
+    fn parse_query(&mut self) -> Result<QueryAlgebra, SparqlError> {
+        self.parse_prologue()?;
+        let mut short_construct = false;
+        let form = if self.eat_keyword("SELECT") {
+            let distinct = self.eat_keyword("DISTINCT");
+            if !distinct {
+                // REDUCED allows, but does not require, to eliminate duplicates
+                self.eat_keyword("REDUCED");
+            }
+            let mut projection = vec![];
+            if !self.eat_punct("*") {
+                while let Some(Token::Var(name)) = self.peek().cloned() {
+                    self.pos += 1;
+                    projection.push(self.var(&name));
+                }
+                if projection.is_empty() {
+                    return Err(self.error("expected '*' or variables (expressions are not supported)"));
+                }
+            }
+            QueryForm::Select { projection, distinct }
+        } else if self.eat_keyword("ASK") {
+            QueryForm::Ask
+        } else if self.eat_keyword("CONSTRUCT") {
+            if self.is_keyword("WHERE") {
+                // short form: the template is the pattern
+                short_construct = true;
+                QueryForm::Construct(vec![])
+            } else {
+                self.expect_punct("{")?;
+                let mut template = vec![];
+                while !self.eat_punct("}") {
+                    if !self.eat_punct(".") {
+                        template.extend(self.parse_triples(false, None)?);
+                    }
+                }
+                QueryForm::Construct(template)
+            }
+        } else if self.is_keyword("DESCRIBE") {
+            return Err(self.error("DESCRIBE queries are not supported"));
+        } else {
+            return Err(self.error("expected SELECT, ASK or CONSTRUCT"));
+        };
+        let has_where = self.eat_keyword("WHERE");
+        let (form, pattern) = if short_construct {
+            self.expect_punct("{")?;
+            let mut triples = vec![];
+            while !self.eat_punct("}") {
+                if !self.eat_punct(".") {
+                    triples.extend(self.parse_triples(true, None)?);
+                }
+            }
+            (QueryForm::Construct(triples.clone()), GraphPattern::Bgp(triples))
+        } else {
+            if !has_where && !self.is_punct("{") {
+                return Err(self.error("expected WHERE"));
+            }
+            (form, self.parse_group()?)
+        };
+        let form = match form {
+            QueryForm::Select { projection, distinct } if projection.is_empty() => QueryForm::Select {
+                projection: (0..self.variables.len())
+                    .filter(|v| !self.variables[*v].starts_with("_:"))
+                    .collect(),
+                distinct,
+            },
+            form => form,
+        };
+        let mut order_by = vec![];
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let descending = if self.eat_keyword("DESC") {
+                    true
+                } else {
+                    self.eat_keyword("ASC");
+                    false
+                };
+                order_by.push(OrderCondition { expr: self.parse_primary()?, descending });
+                let more = match self.peek() {
+                    Some(Token::Var(_)) | Some(Token::Punct("(")) => true,
+                    Some(Token::Word(w)) => {
+                        let w = w.to_ascii_uppercase();
+                        w == "ASC" || w == "DESC" || Function::by_name(&w).is_some() || w == "BOUND"
+                    }
+                    _ => false,
+                };
+                if !more {
+                    break;
+                }
+            }
+        }
+        let mut offset = 0;
+        let mut limit = None;
+        loop {
+            if self.eat_keyword("LIMIT") {
+                limit = Some(self.parse_count()?);
+            } else if self.eat_keyword("OFFSET") {
+                offset = self.parse_count()?;
+            } else {
+                break;
+            }
+        }
+        Ok(QueryAlgebra {
+            form,
+            pattern,
+            variables: std::mem::take(&mut self.variables),
+            order_by,
+            offset,
+            limit,
+        })
+    }
+
+    fn parse_prologue(&mut self) -> Result<(), SparqlError> {
+        loop {
+            if self.eat_keyword("PREFIX") {
+                let prefix = match self.next() {
+                    Some(Token::PName(prefix, local)) if local.is_empty() => prefix,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a prefix"));
+                    }
+                };
+                let iri = self.parse_iriref()?;
+                self.prefixes.insert(prefix, iri);
+            } else if self.eat_keyword("BASE") {
+                self.base = Some(self.parse_iriref()?);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_iriref(&mut self) -> Result<String, SparqlError> {
+        match self.next() {
+            Some(Token::Iri(iri)) => Ok(self.resolve(iri)),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected an IRI"))
+            }
+        }
+    }
+
+    /// Resolve `iri` against the base IRI (naively: relative IRIs are appended to it).
+    fn resolve(&self, iri: String) -> String {
+        match &self.base {
+            Some(base) if !iri.contains(':') => format!("{}{}", base, iri),
+            _ => iri,
+        }
+    }
+
+    fn parse_count(&mut self) -> Result<usize, SparqlError> {
+        match self.next() {
+            Some(Token::Integer(n)) => n.parse().map_err(|_| self.error("invalid integer")),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected an integer"))
+            }
+        }
+    }
+
+    /// Parse a group graph pattern, translating it as prescribed by the SPARQL algebra.
+    fn parse_group(&mut self) -> Result<GraphPattern, SparqlError> {
+        fn join(g: Option<GraphPattern>, p: GraphPattern) -> GraphPattern {
+            match g {
+                None => p,
+                Some(g) => GraphPattern::Join(Box::new(g), Box::new(p)),
+            }
+        }
+        /// Join `g` with the pending triples and paths.
+        fn flush(g: &mut Option<GraphPattern>, triples: &mut Vec<TriplePattern>, paths: &mut Vec<GraphPattern>) {
+            if !triples.is_empty() {
+                *g = Some(join(g.take(), GraphPattern::Bgp(std::mem::take(triples))));
+            }
+            for path in paths.drain(..) {
+                *g = Some(join(g.take(), path));
+            }
+        }
+        self.expect_punct("{")?;
+        let mut g: Option<GraphPattern> = None;
+        let mut triples = vec![];
+        let mut paths = vec![];
+        let mut filters = vec![];
+        loop {
+            if self.eat_punct("}") {
+                break;
+            } else if self.eat_punct(".") {
+                continue;
+            } else if self.eat_keyword("FILTER") {
+                filters.push(self.parse_primary()?);
+                continue;
+            }
+            // other elements end the current basic graph pattern
+            if self.eat_keyword("OPTIONAL") {
+                flush(&mut g, &mut triples, &mut paths);
+                let (optional, filter) = match self.parse_group()? {
+                    GraphPattern::Filter(expr, p) => (*p, Some(expr)),
+                    p => (p, None),
+                };
+                let left = g.take().unwrap_or_else(|| GraphPattern::Bgp(vec![]));
+                g = Some(GraphPattern::LeftJoin(Box::new(left), Box::new(optional), filter));
+            } else if self.is_punct("{") {
+                flush(&mut g, &mut triples, &mut paths);
+                let mut p = self.parse_group()?;
+                while self.eat_keyword("UNION") {
+                    p = GraphPattern::Union(Box::new(p), Box::new(self.parse_group()?));
+                }
+                g = Some(join(g, p));
+            } else if let Some(Token::Word(w)) = self.peek() {
+                let w = w.to_ascii_uppercase();
+                if ["MINUS", "BIND", "VALUES", "GRAPH", "SERVICE", "NOT", "EXISTS"].contains(&w.as_str()) {
+                    return Err(self.error(format!("{} is not supported", w)));
+                }
+                triples.extend(self.parse_triples(true, Some(&mut paths))?);
+            } else if self.peek().is_none() {
+                return Err(self.error("expected '}'"));
+            } else {
+                triples.extend(self.parse_triples(true, Some(&mut paths))?);
+            }
+        }
+        flush(&mut g, &mut triples, &mut paths);
+        let mut g = g.unwrap_or_else(|| GraphPattern::Bgp(vec![]));
+        let mut filters = filters.into_iter();
+        if let Some(first) = filters.next() {
+            let filter = filters.fold(first, |a, b| Expr::And(Box::new(a), Box::new(b)));
+            g = GraphPattern::Filter(filter, Box::new(g));
+        }
+        Ok(g)
+    }
+
+    /// Parse the triples sharing the same subject (with `;` and `,`).
+    ///
+    /// Blank nodes are variables in patterns (`bnode_vars`), but not in templates.
+    /// Triples whose predicate is a property path are added to `paths`,
+    /// where they are allowed.
+    fn parse_triples(
+        &mut self,
+        bnode_vars: bool,
+        mut paths: Option<&mut Vec<GraphPattern>>,
+    ) -> Result<Vec<TriplePattern>, SparqlError> {
+        let subject = self.parse_term(bnode_vars)?;
+        let mut triples = vec![];
+        loop {
+            let predicate = match self.peek() {
+                Some(Token::Var(_)) | Some(Token::BNode(_)) => Either::Left(self.parse_term(bnode_vars)?),
+                _ => match self.parse_path()? {
+                    PropertyPath::Predicate(p) => Either::Left(TermPattern::Term(p)),
+                    path if paths.is_some() => Either::Right(path),
+                    _ => return Err(self.error("property paths are only allowed in WHERE clauses")),
+                },
+            };
+            loop {
+                let object = self.parse_term(bnode_vars)?;
+                match (&predicate, &mut paths) {
+                    (Either::Left(p), _) => triples.push([subject.clone(), p.clone(), object]),
+                    (Either::Right(path), Some(paths)) => {
+                        paths.push(GraphPattern::Path(subject.clone(), path.clone(), object))
+                    }
+                    (Either::Right(_), None) => unreachable!(),
+                }
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            if !self.eat_punct(";") {
+                break;
+            }
+            while self.eat_punct(";") {}
+            if self.is_punct(".") || self.is_punct("}") {
+                break;
+            }
+        }
+        Ok(triples)
+    }
+
+    /// Parse a property path (a single IRI being the simplest one).
+    fn parse_path(&mut self) -> Result<PropertyPath, SparqlError> {
+        let mut path = self.parse_path_sequence()?;
+        while self.eat_punct("|") {
+            path = PropertyPath::Alternative(Box::new(path), Box::new(self.parse_path_sequence()?));
+        }
+        Ok(path)
+    }
+
+    fn parse_path_sequence(&mut self) -> Result<PropertyPath, SparqlError> {
+        let mut path = self.parse_path_element()?;
+        while self.eat_punct("/") {
+            path = PropertyPath::Sequence(Box::new(path), Box::new(self.parse_path_element()?));
+        }
+        Ok(path)
+    }
+
+    fn parse_path_element(&mut self) -> Result<PropertyPath, SparqlError> {
+        if self.eat_punct("^") {
+            return Ok(PropertyPath::Inverse(Box::new(self.parse_path_element()?)));
+        }
+        let primary = if self.eat_punct("(") {
+            let path = self.parse_path()?;
+            self.expect_punct(")")?;
+            path
+        } else if self.eat_keyword("a") {
+            PropertyPath::Predicate(iri(RDF_TYPE))
+        } else {
+            match self.next() {
+                Some(Token::Iri(i)) => PropertyPath::Predicate(iri(&self.resolve(i))),
+                Some(Token::PName(prefix, local)) => PropertyPath::Predicate(iri(&self.expand(&prefix, &local)?)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected a variable, an IRI or a property path"));
+                }
+            }
+        };
+        Ok(if self.eat_punct("*") {
+            PropertyPath::ZeroOrMore(Box::new(primary))
+        } else if self.eat_punct("+") {
+            PropertyPath::OneOrMore(Box::new(primary))
+        } else if self.eat_punct("?") {
+            PropertyPath::ZeroOrOne(Box::new(primary))
+        } else {
+            primary
+        })
+    }
+
+    fn parse_term(&mut self, bnode_vars: bool) -> Result<TermPattern, SparqlError> {
+        if let Some(Token::Var(name)) = self.peek().cloned() {
+            self.pos += 1;
+            return Ok(TermPattern::Var(self.var(&name)));
+        }
+        if let Some(Token::BNode(label)) = self.peek().cloned() {
+            self.pos += 1;
+            return Ok(if bnode_vars {
+                TermPattern::Var(self.var(&format!("_:{}", label)))
+            } else {
+                TermPattern::Term(ArcTerm::new_bnode_unchecked(label))
+            });
+        }
+        if self.is_punct("[") || self.is_punct("(") {
+            return Err(self.error("anonymous blank nodes and collections are not supported"));
+        }
+        Ok(TermPattern::Term(self.parse_constant()?))
+    }
+
+    /// Parse an IRI or a literal.
+    fn parse_constant(&mut self) -> Result<ArcTerm, SparqlError> {
+        let negative = self.eat_punct("-");
+        let sign = if negative { "-" } else { "" };
+        let term = match self.next() {
+            Some(Token::Integer(n)) => typed_literal(&format!("{}{}", sign, n), &format!("{}integer", XSD)),
+            Some(Token::Decimal(n)) => typed_literal(&format!("{}{}", sign, n), &format!("{}decimal", XSD)),
+            Some(Token::Double(n)) => typed_literal(&format!("{}{}", sign, n), &format!("{}double", XSD)),
+            _ if negative => {
+                self.pos -= 1;
+                return Err(self.error("expected a number"));
+            }
+            Some(Token::Iri(i)) => iri(&self.resolve(i)),
+            Some(Token::PName(prefix, local)) => iri(&self.expand(&prefix, &local)?),
+            Some(Token::Word(w)) if w == "true" || w == "false" => typed_literal(&w, &format!("{}boolean", XSD)),
+            Some(Token::Str(lexical)) => match self.peek().cloned() {
+                Some(Token::LangTag(tag)) => {
+                    self.pos += 1;
+                    ArcTerm::new_literal_lang_unchecked(lexical, tag)
+                }
+                Some(Token::Punct("^^")) => {
+                    self.pos += 1;
+                    let datatype = match self.next() {
+                        Some(Token::Iri(i)) => self.resolve(i),
+                        Some(Token::PName(prefix, local)) => self.expand(&prefix, &local)?,
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("expected a datatype IRI"));
+                        }
+                    };
+                    typed_literal(&lexical, &datatype)
+                }
+                _ => typed_literal(&lexical, &format!("{}string", XSD)),
+            },
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("expected a variable, an IRI or a literal"));
+            }
+        };
+        Ok(term)
+    }
+
+    fn expand(&mut self, prefix: &str, local: &str) -> Result<String, SparqlError> {
+        match self.prefixes.get(prefix) {
+            Some(ns) => Ok(format!("{}{}", ns, local)),
+            None => {
+                self.pos -= 1;
+                Err(self.error(format!("undeclared prefix '{}:'", prefix)))
+            }
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, SparqlError> {
+        let mut expr = self.parse_and()?;
+        while self.eat_punct("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, SparqlError> {
+        let mut expr = self.parse_relational()?;
+        while self.eat_punct("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_relational()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, SparqlError> {
+        let left = self.parse_additive()?;
+        let op: fn(Box<Expr>, Box<Expr>) -> Expr = match self.peek() {
+            Some(Token::Punct("=")) => Expr::Equal,
+            Some(Token::Punct("!=")) => Expr::NotEqual,
+            Some(Token::Punct("<")) => Expr::Less,
+            Some(Token::Punct(">")) => Expr::Greater,
+            Some(Token::Punct("<=")) => Expr::LessOrEqual,
+            Some(Token::Punct(">=")) => Expr::GreaterOrEqual,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(op(Box::new(left), Box::new(self.parse_additive()?)))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, SparqlError> {
+        let mut expr = self.parse_multiplicative()?;
+        loop {
+            if self.eat_punct("+") {
+                expr = Expr::Add(Box::new(expr), Box::new(self.parse_multiplicative()?));
+            } else if self.eat_punct("-") {
+                expr = Expr::Subtract(Box::new(expr), Box::new(self.parse_multiplicative()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, SparqlError> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            if self.eat_punct("*") {
+                expr = Expr::Multiply(Box::new(expr), Box::new(self.parse_unary()?));
+            } else if self.eat_punct("/") {
+                expr = Expr::Divide(Box::new(expr), Box::new(self.parse_unary()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, SparqlError> {
+        if self.eat_punct("!") {
+            Ok(Expr::Not(Box::new(self.parse_unary()?)))
+        } else if self.eat_punct("-") {
+            Ok(Expr::Negate(Box::new(self.parse_unary()?)))
+        } else if self.eat_punct("+") {
+            self.parse_unary()
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    /// Parse a bracketted expression, a function call, a variable or a constant.
+    fn parse_primary(&mut self) -> Result<Expr, SparqlError> {
+        if self.eat_punct("(") {
+            let expr = self.parse_expr()?;
+            self.expect_punct(")")?;
+            return Ok(expr);
+        }
+        match self.peek().cloned() {
+            Some(Token::Var(name)) => {
+                self.pos += 1;
+                Ok(Expr::Var(self.var(&name)))
+            }
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("BOUND") => {
+                self.pos += 1;
+                self.expect_punct("(")?;
+                let v = match self.next() {
+                    Some(Token::Var(name)) => self.var(&name),
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("expected a variable"));
+                    }
+                };
+                self.expect_punct(")")?;
+                Ok(Expr::Bound(v))
+            }
+            Some(Token::Word(w)) if w != "true" && w != "false" => {
+                let (function, min, max) = Function::by_name(&w)
+                    .ok_or_else(|| self.error(format!("unknown or unsupported function {}", w)))?;
+                self.pos += 1;
+                self.expect_punct("(")?;
+                let mut args = vec![];
+                if !self.eat_punct(")") {
+                    loop {
+                        args.push(self.parse_expr()?);
+                        if !self.eat_punct(",") {
+                            break;
+                        }
+                    }
+                    self.expect_punct(")")?;
+                }
+                if args.len() < min || args.len() > max {
+                    return Err(self.error(format!("wrong number of arguments for {}", w)));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            _ => Ok(Expr::Term(self.parse_constant()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<Token> = tokenize("SELECT ?x { ?x <a> ex:b, \"c\\n\"@en. FILTER(?x<=2.5) } # comment")
+            .unwrap()
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("SELECT".into()),
+                Token::Var("x".into()),
+                Token::Punct("{"),
+                Token::Var("x".into()),
+                Token::Iri("a".into()),
+                Token::PName("ex".into(), "b".into()),
+                Token::Punct(","),
+                Token::Str("c\n".into()),
+                Token::LangTag("en".into()),
+                Token::Punct("."),
+                Token::Word("FILTER".into()),
+                Token::Punct("("),
+                Token::Var("x".into()),
+                Token::Punct("<="),
+                Token::Decimal("2.5".into()),
+                Token::Punct(")"),
+                Token::Punct("}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = parse_query(
+            "PREFIX : <http://example.org/>
+             SELECT DISTINCT ?x ?y WHERE {
+                ?x a :Person; :name ?n .
+                OPTIONAL { ?x :age ?y FILTER(?y > 18) }
+                { ?x :p ?z } UNION { ?x :q ?z }
+                FILTER(bound(?y) || !isIRI(?x))
+             } ORDER BY DESC(?y) LIMIT 10 OFFSET 2",
+        )
+        .unwrap();
+        assert_eq!(query.variables, vec!["x", "y", "n", "z"]);
+        assert_eq!(query.form, QueryForm::Select { projection: vec![0, 1], distinct: true });
+        assert_eq!((query.offset, query.limit), (2, Some(10)));
+        assert_eq!(query.order_by.len(), 1);
+        assert!(query.order_by[0].descending);
+        match &query.pattern {
+            GraphPattern::Filter(_, p) => match &**p {
+                GraphPattern::Join(left, right) => {
+                    assert!(matches!(&**left, GraphPattern::LeftJoin(_, _, Some(_))));
+                    assert!(matches!(&**right, GraphPattern::Union(_, _)));
+                }
+                p => panic!("unexpected pattern {:?}", p),
+            },
+            p => panic!("unexpected pattern {:?}", p),
+        }
+
+        let query = parse_query("CONSTRUCT WHERE { ?s ?p ?o }").unwrap();
+        let spo = [TermPattern::Var(0), TermPattern::Var(1), TermPattern::Var(2)];
+        assert_eq!(query.form, QueryForm::Construct(vec![spo]));
+
+        let query =
+            parse_query("PREFIX : <http://example.org/> SELECT * { ?c :sub* :Agent . ?x (:p/^:q)+ ?y }").unwrap();
+        let ex = |name: &str| Box::new(PropertyPath::Predicate(iri(&format!("http://example.org/{}", name))));
+        assert_eq!(
+            query.pattern,
+            GraphPattern::Join(
+                Box::new(GraphPattern::Path(
+                    TermPattern::Var(0),
+                    PropertyPath::ZeroOrMore(ex("sub")),
+                    TermPattern::Term(iri("http://example.org/Agent")),
+                )),
+                Box::new(GraphPattern::Path(
+                    TermPattern::Var(1),
+                    PropertyPath::OneOrMore(Box::new(PropertyPath::Sequence(
+                        ex("p"),
+                        Box::new(PropertyPath::Inverse(ex("q"))),
+                    ))),
+                    TermPattern::Var(2),
+                )),
+            )
+        );
+        assert!(parse_query("CONSTRUCT { ?s <p>+ ?o } WHERE { ?s <p> ?o }").is_err());
+
+        let err = parse_query("SELECT * { ?s ex:p ?o }").unwrap_err();
+        assert_eq!(err.position, 14);
+        assert!(parse_query("ASK { ?s ?p ?o ").is_err());
+        assert!(parse_query("SELECT * { ?s ?p ?o } garbage").is_err());
+    }
+}
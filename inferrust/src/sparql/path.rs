@@ -0,0 +1,306 @@
+//! The evaluation of property paths, as binary relations between the nodes of an `InfGraph`.
+//!
+//! When one end of a path pattern is constant,
+//! the nodes at the other end are found by a breadth-first search from it (`reachable`).
+//! Otherwise, the whole relation is computed (`relation`),
+//! and the transitive operators (`*` and `+`) are computed with `ClosureGraph`,
+//! like the closures of transitive properties during reasoning,
+//! but the resulting pairs are not added to the store.
+//!
+//! Both give up (returning no nodes or pairs) when the query is interrupted.
+
+use crate::closure::ClosureGraph;
+use crate::inferray::{InfGraph, NodeDictionary};
+use crate::interrupt::Interrupt;
+use crate::rules::pairs_with;
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::iter::once;
+
+use super::algebra::PropertyPath;
+
+/// The relation between nodes denoted by a property path.
+///
+/// Nodes are the indexes used in the store
+/// (i.e. `owl:sameAs` clique representatives, if they are canonicalized).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct Relation<'g> {
+    /// sorted, without duplicates
+    /// (borrowed from the store for a single predicate, unless its chunk is compressed)
+    pub pairs: Cow<'g, [[u64; 2]]>,
+    /// whether the relation also contains every pair `[x, x]` (zero-length paths),
+    /// which are not listed in `pairs`, since `x` can be any term
+    pub reflexive: bool,
+}
+
+/// The relation denoted by `path` in `graph`.
+pub(super) fn relation<'g>(graph: &'g InfGraph, path: &PropertyPath, interrupt: &Interrupt) -> Relation<'g> {
+    match path {
+        PropertyPath::Predicate(p) => {
+            let pairs = match graph.index_of(p) {
+                Some(p) if p < NodeDictionary::START_INDEX as u64 => graph
+                    .store()
+                    .chunks()
+                    .get(NodeDictionary::prop_idx_to_offset(p))
+                    .map_or_else(Cow::default, |chunk| chunk.so_list().decode()),
+                _ => Cow::default(),
+            };
+            Relation { pairs, reflexive: false }
+        }
+        PropertyPath::Inverse(path) => {
+            let inner = relation(graph, path, interrupt);
+            let mut pairs: Vec<[u64; 2]> = inner.pairs.iter().map(|[x, y]| [*y, *x]).collect();
+            pairs.sort_unstable();
+            Relation { pairs: pairs.into(), reflexive: inner.reflexive }
+        }
+        PropertyPath::Sequence(left, right) => {
+            let left = relation(graph, left, interrupt);
+            let right = relation(graph, right, interrupt);
+            let mut pairs = vec![];
+            for [x, y] in left.pairs.iter() {
+                pairs.extend(pairs_with(&right.pairs, *y).iter().map(|[_, z]| [*x, *z]));
+            }
+            // zero-length paths on one side keep the pairs of the other side
+            if right.reflexive {
+                pairs.extend_from_slice(&left.pairs);
+            }
+            if left.reflexive {
+                pairs.extend_from_slice(&right.pairs);
+            }
+            pairs.sort_unstable();
+            pairs.dedup();
+            Relation {
+                pairs: pairs.into(),
+                reflexive: left.reflexive && right.reflexive,
+            }
+        }
+        PropertyPath::Alternative(left, right) => {
+            let left = relation(graph, left, interrupt);
+            let right = relation(graph, right, interrupt);
+            let mut pairs = left.pairs.into_owned();
+            pairs.extend_from_slice(&right.pairs);
+            pairs.sort_unstable();
+            pairs.dedup();
+            Relation {
+                pairs: pairs.into(),
+                reflexive: left.reflexive || right.reflexive,
+            }
+        }
+        PropertyPath::ZeroOrMore(path) => Relation {
+            pairs: closure(&relation(graph, path, interrupt).pairs, interrupt).into(),
+            reflexive: true,
+        },
+        PropertyPath::OneOrMore(path) => {
+            let inner = relation(graph, path, interrupt);
+            Relation {
+                pairs: closure(&inner.pairs, interrupt).into(),
+                reflexive: inner.reflexive,
+            }
+        }
+        PropertyPath::ZeroOrOne(path) => Relation {
+            reflexive: true,
+            ..relation(graph, path, interrupt)
+        },
+    }
+}
+
+/// The nodes reachable from one of the `start` nodes through `path`
+/// (or from which one of them is reachable, if not `forward`),
+/// sorted and without duplicates.
+///
+/// Zero-length paths reach the `start` nodes themselves.
+pub(super) fn reachable(
+    graph: &InfGraph,
+    path: &PropertyPath,
+    start: &[u64],
+    forward: bool,
+    interrupt: &Interrupt,
+) -> Vec<u64> {
+    let mut nodes = match path {
+        PropertyPath::Predicate(p) => {
+            let chunk = match graph.index_of(p) {
+                Some(p) if p < NodeDictionary::START_INDEX as u64 => {
+                    graph.store().chunks().get(NodeDictionary::prop_idx_to_offset(p))
+                }
+                _ => None,
+            };
+            let mut nodes = vec![];
+            if let Some(chunk) = chunk {
+                for x in start {
+                    if forward {
+                        nodes.extend(chunk.so_with_s(*x).map(|[_, y]| y));
+                    } else {
+                        nodes.extend(chunk.os_with_o(*x).map(|[_, y]| y));
+                    }
+                }
+            }
+            nodes
+        }
+        PropertyPath::Inverse(path) => reachable(graph, path, start, !forward, interrupt),
+        PropertyPath::Sequence(left, right) => {
+            let (first, second) = if forward { (left, right) } else { (right, left) };
+            let middle = reachable(graph, first, start, forward, interrupt);
+            reachable(graph, second, &middle, forward, interrupt)
+        }
+        PropertyPath::Alternative(left, right) => {
+            let mut nodes = reachable(graph, left, start, forward, interrupt);
+            nodes.extend(reachable(graph, right, start, forward, interrupt));
+            nodes
+        }
+        PropertyPath::ZeroOrMore(path) => search(graph, path, start.to_vec(), forward, interrupt),
+        PropertyPath::OneOrMore(path) => {
+            let first = reachable(graph, path, start, forward, interrupt);
+            search(graph, path, first, forward, interrupt)
+        }
+        PropertyPath::ZeroOrOne(path) => {
+            let mut nodes = start.to_vec();
+            nodes.extend(reachable(graph, path, start, forward, interrupt));
+            nodes
+        }
+    };
+    nodes.sort_unstable();
+    nodes.dedup();
+    nodes
+}
+
+/// The nodes reachable from `nodes` through zero or more `path`s, in no particular order.
+fn search(graph: &InfGraph, path: &PropertyPath, nodes: Vec<u64>, forward: bool, interrupt: &Interrupt) -> Vec<u64> {
+    let mut visited: HashSet<u64> = nodes.iter().cloned().collect();
+    let mut frontier = nodes;
+    while !frontier.is_empty() {
+        if interrupt.check() {
+            return vec![];
+        }
+        frontier = reachable(graph, path, &frontier, forward, interrupt)
+            .into_iter()
+            .filter(|x| visited.insert(*x))
+            .collect();
+    }
+    visited.into_iter().collect()
+}
+
+/// The transitive closure of the sorted `pairs` (empty if interrupted).
+fn closure(pairs: &[[u64; 2]], interrupt: &Interrupt) -> Vec<[u64; 2]> {
+    if pairs.is_empty() {
+        return vec![];
+    }
+    ClosureGraph::from(pairs)
+        .close_until(|| interrupt.check())
+        .map_or_else(Vec::new, |closure| closure.pairs())
+}
+
+/// All the subjects and objects of `graph`, sorted and without duplicates
+/// (i.e. the nodes matched by both ends of an unbound zero-length path).
+pub(super) fn nodes(graph: &InfGraph) -> Vec<u64> {
+    let mut nodes: Vec<u64> = graph
+        .store()
+        .chunks()
+        .iter()
+        .flat_map(|chunk| chunk.iter_so().flat_map(|[s, o]| once(s).chain(once(o))))
+        .collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+    nodes
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::parser::iri;
+    use super::*;
+    use sophia::parser::turtle::parse_str as parse_ttl;
+
+    #[test]
+    fn test_relation() {
+        let graph = InfGraph::new_rdfs(parse_ttl(
+            "@prefix : <http://example.org/> . :a :p :b . :b :p :c . :c :q :d .",
+        ))
+        .unwrap();
+        let ex = |name: &str| iri(&format!("http://example.org/{}", name));
+        let id = |name: &str| graph.index_of(&ex(name)).unwrap();
+        let p = || Box::new(PropertyPath::Predicate(ex("p")));
+        let q = || Box::new(PropertyPath::Predicate(ex("q")));
+        let sorted = |mut pairs: Vec<[u64; 2]>| {
+            pairs.sort_unstable();
+            pairs
+        };
+
+        let plus = relation(&graph, &PropertyPath::OneOrMore(p()), Interrupt::never());
+        assert_eq!(
+            plus.pairs,
+            sorted(vec![[id("a"), id("b")], [id("a"), id("c")], [id("b"), id("c")]])
+        );
+        assert!(!plus.reflexive);
+
+        let path = PropertyPath::Sequence(Box::new(PropertyPath::ZeroOrMore(p())), q());
+        let seq = relation(&graph, &path, Interrupt::never());
+        assert_eq!(
+            seq.pairs,
+            sorted(vec![[id("a"), id("d")], [id("b"), id("d")], [id("c"), id("d")]])
+        );
+        assert!(!seq.reflexive);
+
+        let inverse = relation(
+            &graph,
+            &PropertyPath::Inverse(Box::new(PropertyPath::ZeroOrOne(q()))),
+            Interrupt::never(),
+        );
+        assert_eq!(inverse.pairs, vec![[id("d"), id("c")]]);
+        assert!(inverse.reflexive);
+
+        let unknown = relation(
+            &graph,
+            &PropertyPath::OneOrMore(Box::new(PropertyPath::Predicate(ex("r")))),
+            Interrupt::never(),
+        );
+        assert_eq!(unknown, Relation::default());
+        let nodes = nodes(&graph);
+        assert!(["a", "b", "c", "d"].iter().all(|name| nodes.contains(&id(name))));
+    }
+
+    #[test]
+    fn test_reachable() {
+        let graph = InfGraph::new_rdfs(parse_ttl(
+            "@prefix : <http://example.org/> . :a :p :b . :b :p :c . :c :p :a . :c :q :d . :e :q :d .",
+        ))
+        .unwrap();
+        let ex = |name: &str| iri(&format!("http://example.org/{}", name));
+        let p = || Box::new(PropertyPath::Predicate(ex("p")));
+        let q = || Box::new(PropertyPath::Predicate(ex("q")));
+        let paths = vec![
+            PropertyPath::OneOrMore(p()),
+            PropertyPath::ZeroOrMore(Box::new(PropertyPath::Inverse(p()))),
+            PropertyPath::Sequence(
+                Box::new(PropertyPath::ZeroOrMore(p())),
+                Box::new(PropertyPath::Inverse(q())),
+            ),
+            PropertyPath::Alternative(Box::new(PropertyPath::ZeroOrOne(q())), p()),
+            PropertyPath::OneOrMore(Box::new(PropertyPath::Predicate(ex("r")))),
+        ];
+        // the search from either end agrees with the whole relation
+        for path in &paths {
+            let relation = relation(&graph, path, Interrupt::never());
+            for x in nodes(&graph) {
+                let mut forward: Vec<u64> = pairs_with(&relation.pairs, x).iter().map(|[_, y]| *y).collect();
+                let mut backward: Vec<u64> = relation
+                    .pairs
+                    .iter()
+                    .filter(|[_, y]| *y == x)
+                    .map(|[x, _]| *x)
+                    .collect();
+                if relation.reflexive {
+                    forward.push(x);
+                    backward.push(x);
+                }
+                forward.sort_unstable();
+                forward.dedup();
+                backward.sort_unstable();
+                backward.dedup();
+                let from = reachable(&graph, path, &[x], true, Interrupt::never());
+                let to = reachable(&graph, path, &[x], false, Interrupt::never());
+                assert_eq!(from, forward, "{:?} from {}", path, x);
+                assert_eq!(to, backward, "{:?} to {}", path, x);
+            }
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use crate::inferray::*;
+use crate::interrupt::*;
+use crate::observer::*;
 use crate::rules::*;
+use crate::schema::*;
+use crate::sparql::*;
 
 use sophia::parser::turtle::parse_str as parse_ttl;
 use sophia_api::graph::Graph;
+use sophia_api::term::TTerm;
+use sophia_api::triple::Triple;
 use sophia_api::triple::stream::TripleSource;
-use sophia_term::BoxTerm;
+use sophia_term::{ArcTerm, BoxTerm};
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const PREFIXES: &str = r#"@prefix : <http://example.org/> .
 @prefix rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> .
@@ -96,6 +105,7 @@ fn eq_rep_o() -> Result<(), Box<dyn Error>> {
 
         vec![
             RuleProfile::RDFSPlus(),
+            RuleProfile::RDFSPlusCanonical(),
         ],
     )
 }
@@ -133,6 +143,7 @@ fn eq_rep_s() -> Result<(), Box<dyn Error>> {
 
         vec![
             RuleProfile::RDFSPlus(),
+            RuleProfile::RDFSPlusCanonical(),
         ],
     )
 }
@@ -150,6 +161,7 @@ fn eq_sym() -> Result<(), Box<dyn Error>> {
 
         vec![
             RuleProfile::RDFSPlus(),
+            RuleProfile::RDFSPlusCanonical(),
         ],
     )
 }
@@ -168,6 +180,7 @@ fn eq_trans() -> Result<(), Box<dyn Error>> {
 
         vec![
             RuleProfile::RDFSPlus(),
+            RuleProfile::RDFSPlusCanonical(),
         ],
     )
 }
@@ -247,6 +260,53 @@ fn prp_fp() -> Result<(), Box<dyn Error>> {
     )
 }
 
+#[test]
+fn prp_fp_several() -> Result<(), Box<dyn Error>> {
+    test_infer(
+        r#"
+        :unused a owl:FunctionalProperty.
+        :mother a owl:FunctionalProperty.
+        :father a owl:FunctionalProperty.
+        :bart :mother :marge, :mrs_simpson.
+        :bart :father :homer, :mr_simpson.
+        :lisa :father :homer.
+        "#,
+
+        r#"
+        :marge owl:sameAs :mrs_simpson.
+        :mrs_simpson owl:sameAs :marge.
+        :homer owl:sameAs :mr_simpson.
+        :mr_simpson owl:sameAs :homer.
+        "#,
+
+        vec![
+            RuleProfile::RDFSPlus(),
+        ],
+    )
+}
+
+#[test]
+fn prp_fp_large_group() -> Result<(), Box<dyn Error>> {
+    let n = 500;
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(":mother a owl:FunctionalProperty.\n");
+    for i in 0..n {
+        input.push_str(&format!(":bart :mother :m{}.\n", i));
+    }
+    // the rule links every member of the group to a single one, both ways...
+    let raw = InfGraph::new_unprocessed(parse_ttl(&input))?;
+    let output = PRP_FP(RuleInput::new(raw.store(), Interrupt::never())).into_store();
+    assert_eq!(output.size(), 2 * (n - 1));
+    // ... and the rest of the clique is derived by closure or canonicalization
+    let member = BoxTerm::new_iri("http://example.org/m42")?;
+    for profile in &[RuleProfile::RDFSPlus(), RuleProfile::RDFSPlusCanonical()] {
+        let graph = InfGraph::new(parse_ttl(&input), profile)?;
+        assert_eq!(graph.same_as_clique(&member).len(), n, "profile: {}", profile);
+    }
+    Ok(())
+}
+
 #[test]
 fn prp_ifp() -> Result<(), Box<dyn Error>> {
     test_infer(
@@ -680,6 +740,91 @@ fn scm_trans_p_scm_spo() -> Result<(), Box<dyn Error>> {
     )
 }
 
+#[test]
+fn several_transitive_properties() -> Result<(), Box<dyn Error>> {
+    test_infer(
+        r#"
+        :A rdfs:subClassOf :B.
+        :B rdfs:subClassOf :C.
+        :C rdfs:subClassOf :A.
+        :ancestor a owl:TransitiveProperty.
+        :partOf a owl:TransitiveProperty.
+        :bart :ancestor :homer.
+        :homer :ancestor :abe.
+        :abe :ancestor :orville.
+        :springfield :partOf :oregon.
+        :oregon :partOf :usa.
+        :bart owl:sameAs :bartholomew.
+        :bartholomew owl:sameAs :elbarto.
+        "#,
+
+        r#"
+        :A rdfs:subClassOf :A, :B, :C.
+        :C rdfs:subClassOf :B.
+        :bart :ancestor :abe, :orville.
+        :homer :ancestor :orville.
+        :springfield :partOf :usa.
+        :bart owl:sameAs :elbarto.
+        "#,
+
+        vec![
+            RuleProfile::RDFSPlus(),
+        ],
+    )
+}
+
+#[test]
+fn inferred_transitive_property() -> Result<(), Box<dyn Error>> {
+    test_infer(
+        r#"
+        :Lineage rdfs:subClassOf owl:TransitiveProperty.
+        :ancestor a :Lineage.
+        :parent owl:equivalentProperty :directAncestor.
+        :directAncestor rdfs:subPropertyOf :ancestor.
+        :bart :parent :homer.
+        :homer :parent :abe.
+        :abe :parent :orville.
+        :orville :ancestor :jebediah.
+        "#,
+
+        r#"
+        :ancestor a owl:TransitiveProperty.
+        :parent rdfs:subPropertyOf :ancestor.
+        :bart :ancestor :homer, :abe, :orville, :jebediah.
+        :homer :ancestor :jebediah.
+        "#,
+
+        vec![
+            RuleProfile::RDFSPlus(),
+        ],
+    )
+}
+
+#[test]
+fn reclosed_inferred_subclasses() -> Result<(), Box<dyn Error>> {
+    // no rule of RDFS-Plus makes rdfs:subClassOf transitive:
+    // the subclasses inferred from equivalences are only closed by `reclose`
+    test_infer(
+        r#"
+        :D owl:equivalentClass :A.
+        :A owl:equivalentClass :B.
+        :B rdfs:subClassOf :C.
+        :C rdfs:subClassOf :E.
+        "#,
+
+        r#"
+        :D rdfs:subClassOf :A, :B, :C, :E.
+        :A rdfs:subClassOf :D, :B, :C, :E.
+        :B rdfs:subClassOf :D, :A, :C, :E.
+        :D owl:equivalentClass :B.
+        "#,
+
+        vec![
+            RuleProfile::RDFSPlus(),
+        ],
+    )
+}
+
 /// This test does not work, which is strange...
 /// Below is a slightly modified version, which does work.
 #[test]
@@ -822,3 +967,957 @@ fn rich_ontology_hacked() -> Result<(), Box<dyn Error>> {
         ],
     )
 }
+
+// canonical sameAs
+
+#[test]
+fn same_as_canonical() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :a owl:sameAs :b.
+        :b owl:sameAs :c.
+        :c :p :d.
+        :d owl:sameAs :e.
+        :x :q :a.
+        :mother a owl:FunctionalProperty.
+        :bart :mother :marge, :mrs_simpson.
+        "#);
+    let materialized = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFSPlus())?;
+    let canonical = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFSPlusCanonical())?;
+    assert!(canonical.size() < materialized.size());
+    for t in materialized.triples() {
+        let t = t?;
+        assert!(canonical.contains(t.s(), t.p(), t.o())?,
+            "\n  missing triple:\n    {}\n    {}\n    {}\n", t.s(), t.p(), t.o());
+    }
+
+    let clique_of = |graph: &InfGraph, iri: &str| -> Result<Vec<String>, Box<dyn Error>> {
+        let term = BoxTerm::new_iri(format!("http://example.org/{}", iri))?;
+        let mut clique: Vec<String> = graph
+            .same_as_clique(&term)
+            .iter()
+            .map(|t| t.value().to_string())
+            .collect();
+        clique.sort();
+        Ok(clique)
+    };
+    for graph in vec![&materialized, &canonical] {
+        assert_eq!(clique_of(graph, "c")?, vec![
+            "http://example.org/a",
+            "http://example.org/b",
+            "http://example.org/c",
+        ]);
+        assert_eq!(clique_of(graph, "mrs_simpson")?, vec![
+            "http://example.org/marge",
+            "http://example.org/mrs_simpson",
+        ]);
+        assert_eq!(clique_of(graph, "x")?, vec!["http://example.org/x"]);
+        assert!(clique_of(graph, "unknown")?.is_empty());
+    }
+    Ok(())
+}
+
+// equivalences
+
+#[test]
+fn eq_from_cycles() -> Result<(), Box<dyn Error>> {
+    test_infer(
+        r#"
+        :A rdfs:subClassOf :B.
+        :B rdfs:subClassOf :C.
+        :C rdfs:subClassOf :A.
+        :C rdfs:subClassOf :D.
+        :p rdfs:subPropertyOf :q.
+        :q rdfs:subPropertyOf :p.
+        :x :p :y.
+        "#,
+
+        r#"
+        :A owl:equivalentClass :B, :C.
+        :B owl:equivalentClass :A, :C.
+        :C owl:equivalentClass :A, :B.
+        :p owl:equivalentProperty :q.
+        :q owl:equivalentProperty :p.
+        "#,
+
+        vec![
+            RuleProfile::RDFSPlus(),
+        ],
+    )
+}
+
+#[test]
+fn equivalence_classes() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :A rdfs:subClassOf :B.
+        :B rdfs:subClassOf :A.
+        :C rdfs:subClassOf :D.
+        :D owl:equivalentClass :E.
+        :F rdfs:subClassOf :A.
+        "#);
+    let graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFSPlus())?;
+    let sco = BoxTerm::new_iri("http://www.w3.org/2000/01/rdf-schema#subClassOf")?;
+    let mut classes: Vec<Vec<String>> = graph
+        .equivalence_classes(&sco)
+        .into_iter()
+        .map(|c| c.iter().map(|t| t.value().to_string()).collect())
+        .collect();
+    for c in &mut classes {
+        c.sort();
+    }
+    classes.sort();
+    assert_eq!(classes, vec![
+        vec!["http://example.org/A", "http://example.org/B"],
+        vec!["http://example.org/D", "http://example.org/E"],
+    ]);
+    let unknown = BoxTerm::new_iri("http://example.org/unknown")?;
+    assert!(graph.equivalence_classes(&unknown).is_empty());
+    Ok(())
+}
+
+// compiled schema
+
+#[test]
+fn compiled_schema() -> Result<(), Box<dyn Error>> {
+    let mut tbox = String::new();
+    tbox.push_str(PREFIXES);
+    tbox.push_str(r#"
+        :mother rdfs:subPropertyOf :parent ;
+            rdfs:range :Woman.
+        :Woman rdfs:subClassOf :Person.
+        :marge rdfs:seeAlso :mother.
+        "#);
+    let schema = CompiledSchema::new(parse_ttl(&tbox), RuleProfile::RDFS())?;
+    let schema_size = schema.size();
+
+    for child in &[":bart", ":lisa"] {
+        let mut abox = String::new();
+        abox.push_str(PREFIXES);
+        abox.push_str(&format!("{} :mother :marge.", child));
+        let graph = schema.apply(parse_ttl(&abox))?;
+
+        let mut expected = String::new();
+        expected.push_str(PREFIXES);
+        expected.push_str(&format!(r#"
+            {0} :mother :marge.
+            {0} :parent :marge.
+            :marge a :Woman, :Person.
+            :marge rdfs:seeAlso :mother.
+            "#, child));
+        let expected: Vec<[BoxTerm; 3]> = parse_ttl(&expected).collect_triples()?;
+        for [s, p, o] in &expected {
+            assert!(graph.contains(s, p, o)?,
+                "\n  missing triple:\n    {}\n    {}\n    {}\n", s, p, o);
+        }
+    }
+    assert_eq!(schema.size(), schema_size);
+    Ok(())
+}
+
+// snapshots
+
+#[test]
+fn snapshot_roundtrip() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother rdfs:range :Woman.
+        :Woman rdfs:subClassOf :Person.
+        :bart :mother :marge.
+        :marge rdfs:label "Marge"@en, "Marjorie".
+        :marge :age 36.
+        :marge :knows [ :name "Helen" ].
+        "#);
+    let graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFSPlus())?;
+
+    let mut buf = vec![];
+    graph.save(&mut buf)?;
+    let loaded = InfGraph::load(&buf[..])?;
+
+    assert_eq!(loaded.size(), graph.size());
+    let triples: Vec<[BoxTerm; 3]> = graph.triples().collect_triples()?;
+    for [s, p, o] in &triples {
+        assert!(loaded.contains(s, p, o)?,
+            "\n  missing triple:\n    {}\n    {}\n    {}\n", s, p, o);
+    }
+
+    assert!(InfGraph::load(&buf[..buf.len() - 1]).is_err());
+    Ok(())
+}
+
+#[test]
+fn snapshot_corrupt_length() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :bart :mother :marge.
+        "#);
+    let graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFS())?;
+    let mut buf = vec![];
+    graph.save(&mut buf)?;
+
+    // header (12 bytes), number of properties (8 bytes), kind of the first property (1 byte),
+    // then the length of its IRI
+    buf[21..29].copy_from_slice(&u64::MAX.to_le_bytes());
+    let err = InfGraph::load(&buf[..]).err().expect("corrupt snapshot");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    buf[21..29].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    let err = InfGraph::load(&buf[..]).err().expect("corrupt snapshot");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[test]
+fn snapshot_unknown_index() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :bart :mother :marge.
+        "#);
+    let graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFS())?;
+    let mut buf = vec![];
+    graph.save(&mut buf)?;
+    assert!(InfGraph::load(&buf[..]).is_ok());
+
+    // the last chunk is the one of :mother, and the snapshot ends with the sameAs flag (1 byte):
+    // replace :marge by an index beyond the resources of the dictionary
+    let end = buf.len() - 1;
+    buf[end - 8..end].copy_from_slice(&(u32::MAX as u64 + 1000).to_le_bytes());
+    let err = InfGraph::load(&buf[..]).err().expect("corrupt snapshot");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // the same, with an index beyond the properties of the dictionary
+    buf[end - 8..end].copy_from_slice(&(u32::MAX as u64 - 1000).to_le_bytes());
+    let err = InfGraph::load(&buf[..]).err().expect("corrupt snapshot");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mapped_roundtrip() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother rdfs:range :Woman.
+        :Woman rdfs:subClassOf :Person.
+        :bart :mother :marge.
+        :marge rdfs:label "Marge"@en.
+        "#);
+    let graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFS())?;
+
+    let dir = std::env::temp_dir().join(format!("inferrust-test-{}", std::process::id()));
+    graph.save_mapped(&dir)?;
+    let mapped = InfGraph::open_mapped(&dir)?;
+
+    assert_eq!(mapped.size(), graph.size());
+    let triples: Vec<[BoxTerm; 3]> = graph.triples().collect_triples()?;
+    for [s, p, o] in &triples {
+        assert!(mapped.contains(s, p, o)?,
+            "\n  missing triple:\n    {}\n    {}\n    {}\n", s, p, o);
+        assert_eq!(mapped.triples_with_o(o).count(), graph.triples_with_o(o).count());
+    }
+    drop(mapped);
+
+    // unsorted pairs are rejected
+    let so = std::fs::read_dir(&dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .find(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with("so-") && std::fs::metadata(path).map(|m| m.len() >= 32).unwrap_or(false)
+        })
+        .expect("a chunk with several pairs");
+    let mut bytes = std::fs::read(&so)?;
+    let (first, second) = bytes.split_at_mut(16);
+    first.swap_with_slice(&mut second[..16]);
+    std::fs::write(&so, bytes)?;
+    assert!(InfGraph::open_mapped(&dir).is_ok());
+    let err = InfGraph::open_mapped_verified(&dir).err().expect("unsorted pairs");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// compression
+
+#[test]
+fn compressed_graph() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother rdfs:range :Woman; rdfs:subPropertyOf :parent.
+        :Woman rdfs:subClassOf :Person.
+        :bart :mother :marge.
+        :lisa :mother :marge.
+        :marge :mother :jackie.
+        "#);
+    let graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFS())?;
+    let mut compressed = graph.clone();
+    compressed.compress();
+
+    assert_eq!(compressed.size(), graph.size());
+    let triples: Vec<[BoxTerm; 3]> = graph.triples().collect_triples()?;
+    for [s, p, o] in &triples {
+        assert!(compressed.contains(s, p, o)?,
+            "\n  missing triple:\n    {}\n    {}\n    {}\n", s, p, o);
+        assert_eq!(compressed.triples_with_s(s).count(), graph.triples_with_s(s).count());
+        assert_eq!(compressed.triples_with_po(p, o).count(), graph.triples_with_po(p, o).count());
+    }
+    Ok(())
+}
+
+#[test]
+fn compressed_graph_process() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(":knows rdfs:domain :Person.\n");
+    // enough triples for the rules to read the :knows chunk in several parts
+    for i in 0..40_000 {
+        input.push_str(&format!(":p{} :knows :p{}.\n", i, i + 1));
+    }
+    let mut graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFS())?;
+    let expected = graph.size();
+    graph.compress();
+    let heap_sizes = |graph: &InfGraph| -> Vec<(bool, usize)> {
+        graph.store().chunks().iter().map(|c| (c.is_compressed(), c.heap_size())).collect()
+    };
+    let before = heap_sizes(&graph);
+
+    graph.process(&RuleProfile::RDFS());
+    assert_eq!(graph.size(), expected);
+    let after = heap_sizes(&graph);
+    // the chunks that received no triple are still compressed, and no decompressed copy is kept
+    for (i, (compressed, size)) in after.iter().enumerate() {
+        if *compressed {
+            assert_eq!(*size, before[i].1);
+        }
+    }
+    let knows = graph.index_of(&BoxTerm::new_iri("http://example.org/knows")?).unwrap();
+    let knows = &graph.store().chunks()[NodeDictionary::prop_idx_to_offset(knows)];
+    assert!(knows.is_compressed());
+    assert!(knows.heap_size() < knows.len() * std::mem::size_of::<[u64; 2]>() / 2);
+    Ok(())
+}
+
+// parallel loading
+
+#[test]
+fn parallel_load() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :bart :mother :marge.
+        :lisa :mother :marge.
+        :marge :mother :jackie.
+        :marge :knows :mother.
+        :mother rdfs:subPropertyOf :parent.
+        :homer :knows :knows.
+        :homer :parent :abe.
+        "#);
+    let pieces = crate::split_turtle(&input, 4);
+    assert!(pieces.len() > 1);
+    let parallel = InfGraph::new_unprocessed_pieces(
+        pieces.iter().map(|p| parse_ttl(p)).collect(),
+    )?;
+    let sequential = InfGraph::new_unprocessed(parse_ttl(&input))?;
+
+    assert_eq!(parallel.size(), sequential.size());
+    let triples: Vec<[BoxTerm; 3]> = sequential.triples().collect_triples()?;
+    for [s, p, o] in &triples {
+        assert!(parallel.contains(s, p, o)?,
+            "\n  missing triple:\n    {}\n    {}\n    {}\n", s, p, o);
+    }
+    Ok(())
+}
+
+#[test]
+fn parallel_load_bnodes() -> Result<(), Box<dyn Error>> {
+    let sources = [
+        format!("{}_:b :p :x. :s :q :x.", PREFIXES),
+        format!("{}_:b :p :y. :s :q :x.", PREFIXES),
+    ];
+    let p = BoxTerm::new_iri("http://example.org/p")?;
+    let subjects = |graph: &InfGraph| -> Result<Vec<String>, Box<dyn Error>> {
+        let triples: Vec<[BoxTerm; 3]> = graph.triples_with_p(&p).collect_triples()?;
+        let mut subjects: Vec<String> = triples.iter().map(|[s, _, _]| s.value().to_string()).collect();
+        subjects.sort();
+        subjects.dedup();
+        Ok(subjects)
+    };
+
+    // distinct sources: their blank nodes are distinct
+    let merged = InfGraph::new_unprocessed_parallel(sources.iter().map(|s| parse_ttl(s)).collect())?;
+    assert_eq!(merged.size(), 3);
+    assert_eq!(subjects(&merged)?.len(), 2);
+
+    // pieces of a single document: their blank nodes are shared
+    let pieces = InfGraph::new_unprocessed_pieces(sources.iter().map(|s| parse_ttl(s)).collect())?;
+    assert_eq!(pieces.size(), 3);
+    assert_eq!(subjects(&pieces)?, vec!["b".to_string()]);
+    Ok(())
+}
+
+// interruption
+
+#[test]
+fn process_interrupted() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother rdfs:range :Woman; rdfs:subPropertyOf :parent.
+        :parent owl:inverseOf :child.
+        :Woman rdfs:subClassOf :Person.
+        :bart :mother :marge.
+        :marge :mother :jackie.
+        "#);
+    let profile = RuleProfile::RDFSPlus();
+    let raw = InfGraph::new_unprocessed(parse_ttl(&input))?;
+    let expected = InfGraph::new(parse_ttl(&input), &profile)?;
+    let raw_triples: Vec<[BoxTerm; 3]> = raw.triples().collect_triples()?;
+
+    let token = CancelToken::new();
+    token.cancel();
+    let cancelled = ProcessOptions::new().with_cancel_token(token);
+    let timed_out = ProcessOptions::new().with_deadline(Instant::now());
+    let not_interrupted = ProcessOptions::new()
+        .with_cancel_token(CancelToken::new())
+        .with_timeout(Duration::from_secs(3600));
+
+    for (options, error) in vec![
+        (cancelled, ProcessError::Cancelled),
+        (timed_out, ProcessError::TimedOut),
+    ] {
+        let mut graph = raw.clone();
+        assert_eq!(graph.process_with(&profile, &options), Err(error));
+        // the explicit triples are kept...
+        for [s, p, o] in &raw_triples {
+            assert!(graph.contains(s, p, o)?);
+        }
+        // ... and processing again completes the inference
+        assert_eq!(graph.process_with(&profile, &not_interrupted), Ok(()));
+        assert_eq!(graph.size(), expected.size());
+    }
+    Ok(())
+}
+
+#[test]
+fn process_limits() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother a owl:FunctionalProperty; rdfs:subPropertyOf :parent.
+        :parent owl:inverseOf :child.
+        :bart :mother :marge, :marjorie, :mrs_simpson.
+        "#);
+    let profile = RuleProfile::RDFSPlus();
+    let raw = InfGraph::new_unprocessed(parse_ttl(&input))?;
+    let expected = InfGraph::new(parse_ttl(&input), &profile)?;
+
+    let mut graph = raw.clone();
+    let options = ProcessOptions::new().with_max_clique_size(2);
+    match graph.process_with(&profile, &options) {
+        Err(ProcessError::CliqueTooLarge { limit: 2, member, size: 3 }) => {
+            assert!(member.contains("mar") || member.contains("mrs_simpson"));
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let mut graph = raw.clone();
+    let options = ProcessOptions::new().with_max_inferred_triples(2);
+    match graph.process_with(&profile, &options) {
+        Err(ProcessError::TooManyInferences { limit: 2, .. }) => (),
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let mut graph = raw.clone();
+    let options = ProcessOptions::new().with_max_iterations(1);
+    assert_eq!(
+        graph.process_with(&profile, &options),
+        Err(ProcessError::TooManyIterations { limit: 1 }),
+    );
+
+    // generous limits do not prevent the inference from completing
+    let mut graph = raw.clone();
+    let options = ProcessOptions::new()
+        .with_max_clique_size(3)
+        .with_max_inferred_triples(1000)
+        .with_max_iterations(100);
+    assert_eq!(graph.process_with(&profile, &options), Ok(()));
+    assert_eq!(graph.size(), expected.size());
+    Ok(())
+}
+
+/// An observer recording the events it receives.
+#[derive(Default)]
+struct Recorder(Mutex<Vec<(String, usize)>>);
+
+impl Recorder {
+    fn record(&self, event: String, n: usize) {
+        self.0.lock().unwrap().push((event, n));
+    }
+
+    fn events(&self) -> Vec<(String, usize)> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Observer for Recorder {
+    fn triples_loaded(&self, count: usize) {
+        self.record("loaded".into(), count);
+    }
+
+    fn dictionary_built(&self, resources: usize, _properties: usize) {
+        self.record("dictionary".into(), resources);
+    }
+
+    fn closure_started(&self, property: &ArcTerm, triples: usize) {
+        self.record(format!("closure_started {}", property), triples);
+    }
+
+    fn closure_finished(&self, property: &ArcTerm, triples: usize) {
+        self.record(format!("closure_finished {}", property), triples);
+    }
+
+    fn iteration_started(&self, iteration: usize) {
+        self.record("iteration_started".into(), iteration);
+    }
+
+    fn iteration_finished(&self, iteration: usize, added: usize) {
+        self.record(format!("iteration_finished {}", iteration), added);
+    }
+
+    fn after_rules_finished(&self, added: usize) {
+        self.record("after_rules".into(), added);
+    }
+}
+
+#[test]
+fn process_observed() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother rdfs:range :Woman; rdfs:subPropertyOf :parent.
+        :parent owl:inverseOf :child.
+        :Woman rdfs:subClassOf :Person.
+        :Person rdfs:subClassOf :Agent.
+        :bart :mother :marge.
+        "#);
+    let profile = RuleProfile::RDFSPlus();
+    let expected = InfGraph::new(parse_ttl(&input), &profile)?;
+
+    let recorder = Recorder::default();
+    let mut graph = InfGraph::new_unprocessed_observed(parse_ttl(&input), &recorder)?;
+    let events = recorder.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0], ("loaded".to_string(), 6));
+    assert_eq!(events[1].0, "dictionary");
+
+    let recorder = Arc::new(Recorder::default());
+    let options = ProcessOptions::new().with_observer(recorder.clone());
+    graph.process_with(&profile, &options)?;
+    assert_eq!(graph.size(), expected.size());
+
+    let events = recorder.events();
+    let sco = "<http://www.w3.org/2000/01/rdf-schema#subClassOf>";
+    assert!(events.contains(&(format!("closure_started {}", sco), 2)));
+    assert!(events.contains(&(format!("closure_finished {}", sco), 3)));
+    // iterations are numbered from 1, and each of them finishes before the next one starts
+    let iterations: Vec<&(String, usize)> = events
+        .iter()
+        .filter(|(e, _)| e.starts_with("iteration"))
+        .collect();
+    assert!(!iterations.is_empty());
+    for (i, pair) in iterations.chunks(2).enumerate() {
+        assert_eq!(pair[0], &("iteration_started".to_string(), i + 1));
+        assert_eq!(pair[1].0, format!("iteration_finished {}", i + 1));
+    }
+    assert!(iterations.iter().any(|(e, added)| e.starts_with("iteration_finished") && *added > 0));
+    assert_eq!(events.last().map(|(e, _)| e.as_str()), Some("after_rules"));
+
+    // parallel loading and `process` report to their observer as well
+    let pieces = crate::split_turtle(&input, 2);
+    let recorder = Recorder::default();
+    let mut graph = InfGraph::new_unprocessed_parallel_observed(
+        pieces.iter().map(|p| parse_ttl(p)).collect(),
+        &recorder,
+    )?;
+    let events = recorder.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0], ("loaded".to_string(), 6));
+    assert_eq!(events[1].0, "dictionary");
+
+    let recorder = Recorder::default();
+    graph.process_observed(&profile, &recorder);
+    assert_eq!(graph.size(), expected.size());
+    let events = recorder.events();
+    assert!(events.contains(&(format!("closure_finished {}", sco), 3)));
+    assert!(events.contains(&("iteration_started".to_string(), 1)));
+    assert_eq!(events.last().map(|(e, _)| e.as_str()), Some("after_rules"));
+    Ok(())
+}
+
+/// An observer cancelling `token` as soon as a closure starts during the fixpoint,
+/// and recording whether some closure finished afterwards.
+#[derive(Default)]
+struct CancelOnReclose {
+    token: CancelToken,
+    iterations: AtomicUsize,
+    finished_after_cancel: AtomicBool,
+}
+
+impl Observer for CancelOnReclose {
+    fn closure_started(&self, _property: &ArcTerm, _triples: usize) {
+        if self.iterations.load(Ordering::SeqCst) > 0 {
+            self.token.cancel();
+        }
+    }
+
+    fn closure_finished(&self, _property: &ArcTerm, _triples: usize) {
+        if self.token.is_cancelled() {
+            self.finished_after_cancel.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn iteration_started(&self, _iteration: usize) {
+        self.iterations.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn process_cancelled_while_closing() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    // the subclasses inferred from equivalences must be re-closed after the first iteration
+    input.push_str(r#"
+        :D owl:equivalentClass :A.
+        :A owl:equivalentClass :B.
+        :B rdfs:subClassOf :C.
+        :x a :D.
+        "#);
+    let profile = RuleProfile::RDFSPlus();
+    let raw = InfGraph::new_unprocessed(parse_ttl(&input))?;
+    let expected = InfGraph::new(parse_ttl(&input), &profile)?;
+    let raw_triples: Vec<[BoxTerm; 3]> = raw.triples().collect_triples()?;
+
+    let observer = Arc::new(CancelOnReclose::default());
+    let options = ProcessOptions::new()
+        .with_cancel_token(observer.token.clone())
+        .with_observer(observer.clone());
+    let mut graph = raw.clone();
+    assert_eq!(graph.process_with(&profile, &options), Err(ProcessError::Cancelled));
+    assert!(observer.token.is_cancelled());
+    // the closure was given up as soon as the token was cancelled...
+    assert!(!observer.finished_after_cancel.load(Ordering::SeqCst));
+    // ... but the graph is still usable
+    for [s, p, o] in &raw_triples {
+        assert!(graph.contains(s, p, o)?);
+    }
+    assert_eq!(graph.process_with(&profile, &ProcessOptions::new()), Ok(()));
+    assert_eq!(graph.size(), expected.size());
+    Ok(())
+}
+
+/// An observer recording the number of threads of the pool running each iteration.
+#[cfg(feature = "parallel")]
+#[derive(Default)]
+struct ThreadCounter(Mutex<Vec<usize>>);
+
+#[cfg(feature = "parallel")]
+impl Observer for ThreadCounter {
+    fn iteration_started(&self, _iteration: usize) {
+        self.0.lock().unwrap().push(rayon::current_num_threads());
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn process_in_pool() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :mother rdfs:range :Woman; rdfs:subPropertyOf :parent.
+        :parent owl:inverseOf :child.
+        :Woman rdfs:subClassOf :Person.
+        :bart :mother :marge.
+        "#);
+    let profile = RuleProfile::RDFSPlus();
+    let expected = InfGraph::new(parse_ttl(&input), &profile)?;
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build()?;
+    let graph = InfGraph::new_in_pool(parse_ttl(&input), &profile, &pool)?;
+    assert_eq!(graph.size(), expected.size());
+
+    let counter = Arc::new(ThreadCounter::default());
+    let options = ProcessOptions::new()
+        .with_num_threads(3)?
+        .with_observer(counter.clone());
+    let mut graph = InfGraph::new_unprocessed(parse_ttl(&input))?;
+    graph.process_with(&profile, &options)?;
+    assert_eq!(graph.size(), expected.size());
+    let counts = counter.0.lock().unwrap();
+    assert!(!counts.is_empty());
+    assert!(counts.iter().all(|n| *n == 3));
+    Ok(())
+}
+
+#[test]
+fn query_bgp() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :father rdfs:domain :Person; rdfs:subPropertyOf :parent.
+        :mother rdfs:subPropertyOf :parent.
+        :bart :father :homer.
+        :lisa :father :homer.
+        :homer :father :abe; :mother :mona.
+        :homer owl:sameAs :homer_simpson.
+        "#);
+    let ex = |name: &str| format!("<http://example.org/{}>", name);
+    let term = |name: &str| -> PatternTerm<BoxTerm> {
+        PatternTerm::Term(BoxTerm::new_iri(format!("http://example.org/{}", name)).unwrap())
+    };
+    let rdf_type = PatternTerm::Term(BoxTerm::new_iri("http://www.w3.org/1999/02/22-rdf-syntax-ns#type")?);
+    let bgp = vec![
+        [PatternTerm::var("x"), rdf_type.clone(), term("Person")],
+        [PatternTerm::var("x"), term("father"), PatternTerm::var("y")],
+        [PatternTerm::var("y"), term("mother"), PatternTerm::var("z")],
+    ];
+    let mut expected = vec![];
+    for x in &["bart", "lisa"] {
+        for y in &["homer", "homer_simpson"] {
+            expected.push(vec![ex(x), ex(y), ex("mona")]);
+        }
+    }
+
+    // owl:sameAs cliques are expanded the same way, whether they are materialized or not
+    for profile in vec![RuleProfile::RDFSPlus(), RuleProfile::RDFSPlusCanonical()] {
+        let graph = InfGraph::new(parse_ttl(&input), &profile)?;
+        let solutions = graph.query_bgp(&bgp);
+        assert_eq!(solutions.variables(), &["x", "y", "z"]);
+        let mut rows: Vec<Vec<String>> = solutions
+            .iter()
+            .map(|row| row.iter().map(|t| t.to_string()).collect())
+            .collect();
+        rows.sort();
+        assert_eq!(rows, expected);
+
+        // variable predicate
+        let solutions = graph.query_bgp(&[[term("homer"), PatternTerm::var("p"), term("mona")]]);
+        let mut predicates: Vec<String> = solutions.values("p").iter().map(|t| t.to_string()).collect();
+        predicates.sort();
+        assert_eq!(predicates, vec![ex("mother"), ex("parent")]);
+
+        // unknown term
+        let solutions = graph.query_bgp(&[[PatternTerm::var("x"), term("uncle"), PatternTerm::var("y")]]);
+        assert!(solutions.is_empty());
+    }
+
+    // compressed chunks are joined in place
+    let mut graph = InfGraph::new(parse_ttl(&input), &RuleProfile::RDFSPlus())?;
+    graph.compress();
+    let mut rows: Vec<Vec<String>> = graph
+        .query_bgp(&bgp)
+        .iter()
+        .map(|row| row.iter().map(|t| t.to_string()).collect())
+        .collect();
+    rows.sort();
+    assert_eq!(rows, expected);
+    Ok(())
+}
+
+#[test]
+fn sparql_query() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :father rdfs:subPropertyOf :parent.
+        :mother rdfs:subPropertyOf :parent.
+        :parent rdfs:range :Person.
+        :bart :father :homer; :age 10.
+        :lisa :father :homer; :age 8; :nickname "Lis"@en.
+        :homer :father :abe; :mother :mona; :age 39.
+        "#);
+    let graph = InfGraph::new_rdfs(parse_ttl(&input))?;
+    let prologue = "PREFIX : <http://example.org/> PREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>\n";
+    let select = |query: &str| -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        match graph.query(&format!("{}{}", prologue, query))? {
+            QueryResults::Solutions { rows, .. } => Ok(rows
+                .iter()
+                .map(|row| row.iter().map(|t| t.as_ref().map_or("-".to_string(), |t| t.to_string())).collect())
+                .collect()),
+            results => panic!("unexpected results {:?}", results),
+        }
+    };
+    let ex = |name: &str| format!("<http://example.org/{}>", name);
+
+    // inferred types, DISTINCT and ORDER BY
+    assert_eq!(
+        select("SELECT DISTINCT ?p { ?c :parent ?p . ?p a :Person } ORDER BY DESC(?p)")?,
+        vec![vec![ex("mona")], vec![ex("homer")], vec![ex("abe")]]
+    );
+    // OPTIONAL, FILTER, LIMIT and OFFSET
+    assert_eq!(
+        select(r#"SELECT ?c ?n { ?c :father :homer; :age ?a OPTIONAL { ?c :nickname ?n } FILTER(?a >= 8 && ?a < 39) }
+                  ORDER BY ?a"#)?,
+        vec![vec![ex("lisa"), "\"Lis\"@en".to_string()], vec![ex("bart"), "-".to_string()]]
+    );
+    assert_eq!(
+        select("SELECT ?c { ?c :age ?a } ORDER BY ?a LIMIT 1 OFFSET 1")?,
+        vec![vec![ex("bart")]]
+    );
+    // UNION and functions
+    let mut rows = select(
+        r#"SELECT ?x { { ?x :mother ?y } UNION { ?x :nickname ?y FILTER(langMatches(lang(?y), "EN")) }
+           FILTER(!isLiteral(?x) && regex(str(?x), "^http://example.org/"))}"#,
+    )?;
+    rows.sort();
+    assert_eq!(rows, vec![vec![ex("homer")], vec![ex("lisa")]]);
+
+    assert_eq!(graph.query("ASK { ?x ?p 39 }")?, QueryResults::Boolean(true));
+    assert_eq!(graph.query("ASK { ?x ?p 40 }")?, QueryResults::Boolean(false));
+    match graph.query(&format!("{}CONSTRUCT {{ ?p :child ?c }} WHERE {{ ?c :parent ?p }}", prologue))? {
+        QueryResults::Graph(triples) => {
+            let mut triples: Vec<String> = triples.iter().map(|t| format!("{} {} {}", t[0], t[1], t[2])).collect();
+            triples.sort();
+            assert_eq!(triples, vec![
+                format!("{} {} {}", ex("abe"), ex("child"), ex("homer")),
+                format!("{} {} {}", ex("homer"), ex("child"), ex("bart")),
+                format!("{} {} {}", ex("homer"), ex("child"), ex("lisa")),
+                format!("{} {} {}", ex("mona"), ex("child"), ex("homer")),
+            ]);
+        }
+        results => panic!("unexpected results {:?}", results),
+    }
+
+    let err = graph.query("SELECT ?x { ?x :p ?y }").unwrap_err();
+    assert_eq!(err.position, 15);
+    Ok(())
+}
+
+#[test]
+fn sparql_construct_bnodes() -> Result<(), Box<dyn Error>> {
+    // the labels of the graph are those a naive renaming of the template could produce
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :bart :friend _:c0.
+        _:c0 :name "Milhouse".
+        :lisa :friend _:c1.
+        _:c1 :name "Janey".
+        "#);
+    let graph = InfGraph::new_rdfs(parse_ttl(&input))?;
+    let prologue = "PREFIX : <http://example.org/>\n";
+    let friends: Vec<String> = match graph.query(&format!("{}SELECT ?f {{ ?x :friend ?f }}", prologue))? {
+        QueryResults::Solutions { rows, .. } => rows.iter().map(|row| row[0].as_ref().unwrap().to_string()).collect(),
+        results => panic!("unexpected results {:?}", results),
+    };
+    assert_eq!(friends.len(), 2);
+
+    let query = "CONSTRUCT { ?x :knows _:b. _:b :named ?n } WHERE { ?x :friend ?f. ?f :name ?n }";
+    match graph.query(&format!("{}{}", prologue, query))? {
+        QueryResults::Graph(triples) => {
+            assert_eq!(triples.len(), 4);
+            let known: Vec<(String, String)> = triples
+                .iter()
+                .filter(|t| t[1].value().ends_with("knows"))
+                .map(|t| (t[0].to_string(), t[2].to_string()))
+                .collect();
+            let named: Vec<(String, String)> = triples
+                .iter()
+                .filter(|t| t[1].value().ends_with("named"))
+                .map(|t| (t[0].to_string(), t[2].to_string()))
+                .collect();
+            assert_eq!(known.len(), 2);
+            // a new blank node per solution, shared by the triples of the solution
+            assert_ne!(known[0].1, known[1].1);
+            for (_, b) in &known {
+                assert!(!friends.contains(b));
+                assert_eq!(named.iter().filter(|(s, _)| s == b).count(), 1);
+            }
+        }
+        results => panic!("unexpected results {:?}", results),
+    }
+    Ok(())
+}
+
+#[test]
+fn sparql_interrupted() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :a :p :b.
+        :b :p :c.
+        "#);
+    let graph = InfGraph::new_rdfs(parse_ttl(&input))?;
+    let query = Query::parse("SELECT ?x ?y { ?x <http://example.org/p>+ ?y }")?;
+    let results = graph.execute_with(&query, &ProcessOptions::new().with_timeout(Duration::from_secs(3600)))?;
+    assert_eq!(results, graph.execute(&query));
+
+    let token = CancelToken::new();
+    token.cancel();
+    let options = ProcessOptions::new().with_cancel_token(token);
+    assert_eq!(graph.execute_with(&query, &options), Err(ProcessError::Cancelled));
+    let options = ProcessOptions::new().with_deadline(Instant::now());
+    assert_eq!(graph.execute_with(&query, &options), Err(ProcessError::TimedOut));
+    Ok(())
+}
+
+#[test]
+fn sparql_property_paths() -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    input.push_str(PREFIXES);
+    input.push_str(r#"
+        :Student rdfs:subClassOf :Person.
+        :Person rdfs:subClassOf :Agent.
+        :Robot rdfs:subClassOf :Agent.
+        :bart :father :homer.
+        :lisa :father :homer.
+        :homer :father :abe; :mother :mona.
+        :abe :father :orville.
+        "#);
+    let graph = InfGraph::new_rdfs(parse_ttl(&input))?;
+    let size = graph.size();
+    let prologue = "PREFIX : <http://example.org/> PREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>\n";
+    let values = |query: &str| -> Result<Vec<String>, Box<dyn Error>> {
+        match graph.query(&format!("{}{}", prologue, query))? {
+            QueryResults::Solutions { rows, .. } => {
+                let mut values: Vec<String> = rows.iter().map(|row| row[0].as_ref().unwrap().to_string()).collect();
+                values.sort();
+                Ok(values)
+            }
+            results => panic!("unexpected results {:?}", results),
+        }
+    };
+    let ex = |names: &[&str]| -> Vec<String> {
+        names.iter().map(|name| format!("<http://example.org/{}>", name)).collect()
+    };
+
+    assert_eq!(values("SELECT ?c { ?c rdfs:subClassOf* :Agent }")?, ex(&["Agent", "Person", "Robot", "Student"]));
+    // :father is not transitive
+    assert_eq!(values("SELECT ?a { :bart :father+ ?a }")?, ex(&["abe", "homer", "orville"]));
+    assert_eq!(values("SELECT ?x { ?x :father/^:father :bart }")?, ex(&["bart", "lisa"]));
+    assert_eq!(values("SELECT ?y { :homer (:father|:mother)? ?y }")?, ex(&["abe", "homer", "mona"]));
+    assert_eq!(values("SELECT ?x { ?x (:father/:father)+ :orville }")?, ex(&["homer"]));
+    assert_eq!(
+        graph.query(&format!("{}ASK {{ :orville ^:father+ :bart }}", prologue))?,
+        QueryResults::Boolean(true)
+    );
+    // zero-length paths match terms absent from the graph
+    assert_eq!(values("SELECT ?y { :maggie :father* ?y }")?, ex(&["maggie"]));
+    assert_eq!(values("SELECT ?x { ?x :father? :maggie }")?, ex(&["maggie"]));
+    assert_eq!(values("SELECT ?x { ?x :father+ :maggie }")?, ex(&[]));
+    let ask = |query: &str| graph.query(&format!("{}{}", prologue, query));
+    assert_eq!(ask("ASK { :maggie :father? :maggie }")?, QueryResults::Boolean(true));
+    assert_eq!(ask("ASK { :maggie :father* :bart }")?, QueryResults::Boolean(false));
+    assert_eq!(ask("ASK { :bart :father* :bart }")?, QueryResults::Boolean(true));
+    // paths are not materialized
+    assert_eq!(graph.size(), size);
+    Ok(())
+}
@@ -1,21 +1,22 @@
 //! Bunch of utility functions
 
-use itertools::Itertools;
+use crate::par::*;
+
 use std::cmp::{Ord, Ordering};
 
-/// Return the position of the first pair in `pairs` whose first element is `x`.
-///
-/// If no such pair is present, return the lenth of `pairs`.
+/// Split `pairs` into groups of consecutive pairs sharing the same first element.
 ///
 /// # Pre-condition
 /// `pairs` is sorted using the lexicographic order on pairs.
-pub fn first_pair(pairs: &[[u64; 2]], x: u64) -> usize {
-    let len = pairs.len();
-    match pairs.binary_search(&[x, 0]) {
-        Ok(i) => i,
-        Err(i) if i < len && pairs[i][0] == x => i,
-        _ => len,
-    }
+pub fn pair_groups(pairs: &[[u64; 2]]) -> impl Iterator<Item = &[[u64; 2]]> {
+    let mut rest = pairs;
+    std::iter::from_fn(move || {
+        let first = rest.first()?[0];
+        let len = rest.partition_point(|p| p[0] == first);
+        let (group, r) = rest.split_at(len);
+        rest = r;
+        Some(group)
+    })
 }
 
 /// Merge sort a with b, without duplicate.
@@ -60,78 +61,116 @@ where
     r
 }
 
+/// Below this number of pairs, `radix_sort_pairs` does not use multiple threads.
+const RADIX_PAR_THRESHOLD: usize = 1 << 16;
+
 /// Sort the pairs and remove duplicates.
 ///
+/// This is a radix sort, considering each pair as a 128-bits key,
+/// but only on the bytes that actually vary across `pairs`
+/// (so its cost does not depend on the range of the values).
+/// Large inputs are first partitioned on their most significant varying byte,
+/// then each partition is sorted in parallel.
+///
 /// # Return value
 /// Return the new size of pairs (once duplicates have been removed).
-pub fn bucket_sort_pairs(pairs: &mut Vec<[u64; 2]>) -> usize {
-    if pairs.is_empty() {
-        return 0;
-    }
-    let (min, max) = pairs
-        .iter()
-        .map(|pair| pair[0])
-        .minmax()
-        .into_option()
-        .unwrap_or((0, 0));
-    let width = (max - min + 1) as usize;
-    let mut hist: Vec<usize> = vec![0; width];
-    let mut cumul: Vec<usize> = vec![0; width];
-    build_hist(pairs, min, 0, &mut hist);
-    let hist2 = hist.to_vec();
-    build_cumul(&hist, &mut cumul);
-    let len = pairs.len();
-    let mut objects = vec![0; len];
-    for val in pairs.iter() {
-        let val_s = val[0];
-        let val_o = val[1];
-        let idx = (val_s - min) as usize;
-        let pos = cumul[idx];
-        let remaining = hist[idx];
-        let obj_idx = (pos + remaining - 1) as usize;
-        hist[idx] -= 1;
-        objects[obj_idx] = val_o;
-    }
-
-    for i in 0..(width - 1) {
-        quickersort::sort(&mut objects[cumul[i]..cumul[i + 1]]);
-    }
-    quickersort::sort(&mut objects[cumul[width - 1]..len]);
-    let mut j = 0;
-    let mut l = 0;
-    let mut last = 0;
-    for (i, val) in hist2.iter().enumerate() {
-        let s = min + i as u64;
-        for k in 0..*val {
-            let o = objects[l];
-            l += 1;
-            if k == 0 || o != last {
-                pairs[j] = [s, o];
-                j += 1;
+pub fn radix_sort_pairs(pairs: &mut Vec<[u64; 2]>) -> usize {
+    if pairs.len() < 2 {
+        return pairs.len();
+    }
+    let digits = varying_digits(pairs);
+    let mut buf = vec![[0; 2]; pairs.len()];
+    match digits.split_last() {
+        Some((&top, rest)) if pairs.len() >= RADIX_PAR_THRESHOLD && !rest.is_empty() => {
+            let counts = scatter(pairs, &mut buf, top);
+            let mut partitions = Vec::with_capacity(256);
+            let mut buf_rest = &mut buf[..];
+            let mut pairs_rest = &mut pairs[..];
+            for count in counts.iter().filter(|c| **c > 0) {
+                let (b, br) = buf_rest.split_at_mut(*count);
+                let (p, pr) = pairs_rest.split_at_mut(*count);
+                partitions.push((b, p));
+                buf_rest = br;
+                pairs_rest = pr;
             }
-            last = o;
+            partitions
+                .into_par_iter()
+                .for_each(|(b, p)| lsd_sort(b, p, rest));
+            std::mem::swap(pairs, &mut buf);
         }
+        _ => lsd_sort(pairs, &mut buf, &digits),
     }
-    pairs.truncate(j);
-    j
+    pairs.dedup();
+    pairs.len()
+}
+
+/// The `d`-th least significant byte of `pair`, seen as a 128-bits key.
+#[inline]
+fn digit(pair: &[u64; 2], d: u32) -> usize {
+    let val = if d < 8 { pair[1] } else { pair[0] };
+    ((val >> (8 * (d % 8))) & 0xff) as usize
+}
+
+/// The bytes (from least to most significant) that are not the same in all `pairs`.
+fn varying_digits(pairs: &[[u64; 2]]) -> Vec<u32> {
+    let first = pairs[0];
+    let diff = pairs.iter().fold([0, 0], |acc, pair| {
+        [acc[0] | (pair[0] ^ first[0]), acc[1] | (pair[1] ^ first[1])]
+    });
+    (0..16).filter(|d| digit(&diff, *d) != 0).collect()
 }
 
-fn build_hist(pairs: &[[u64; 2]], min: u64, pair_elem: usize, hist: &mut [usize]) {
-    for pair in pairs {
-        hist[(pair[pair_elem] - min) as usize] += 1;
+/// Stable sort of `data` on the given `digits` (from least to most significant),
+/// using `tmp` as scratch space.
+fn lsd_sort(data: &mut [[u64; 2]], tmp: &mut [[u64; 2]], digits: &[u32]) {
+    let mut in_data = true;
+    for d in digits {
+        if in_data {
+            scatter(data, tmp, *d);
+        } else {
+            scatter(tmp, data, *d);
+        }
+        in_data = !in_data;
+    }
+    if !in_data {
+        data.copy_from_slice(tmp);
     }
 }
 
-fn build_cumul(hist: &[usize], cumul: &mut [usize]) {
-    for i in 1..hist.len() {
-        cumul[i] = cumul[i - 1] + hist[i - 1];
+/// Stable copy of `src` into `dst`, ordered by their `d`-th digit.
+///
+/// Return the number of pairs for each value of that digit.
+fn scatter(src: &[[u64; 2]], dst: &mut [[u64; 2]], d: u32) -> [usize; 256] {
+    let mut counts = [0; 256];
+    for pair in src {
+        counts[digit(pair, d)] += 1;
+    }
+    let mut pos = [0; 256];
+    let mut sum = 0;
+    for (p, c) in pos.iter_mut().zip(counts.iter()) {
+        *p = sum;
+        sum += c;
     }
+    for pair in src {
+        let k = digit(pair, d);
+        dst[pos[k]] = *pair;
+        pos[k] += 1;
+    }
+    counts
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_pair_groups() {
+        let pairs = [[1, 2], [1, 3], [2, 1], [4, 4], [4, 5], [4, 6]];
+        let groups: Vec<_> = pair_groups(&pairs).collect();
+        assert_eq!(groups, vec![&pairs[..2], &pairs[2..3], &pairs[3..]]);
+        assert_eq!(pair_groups(&[]).count(), 0);
+    }
+
     #[test]
     fn test_merge_sort() {
         assert_eq!(
@@ -151,23 +190,50 @@ mod test {
     }
 
     #[test]
-    fn test_bucket_sort() {
+    fn test_radix_sort() {
         let mut pairs = vec![[2, 1], [1, 3]];
-        bucket_sort_pairs(&mut pairs);
+        radix_sort_pairs(&mut pairs);
         let expected = [[1, 3], [2, 1]];
         assert_eq!(pairs, expected);
         let mut pairs = vec![[2, 1], [1, 3], [2, 1]];
-        bucket_sort_pairs(&mut pairs);
+        radix_sort_pairs(&mut pairs);
         let expected = [[1, 3], [2, 1]];
         assert_eq!(pairs, expected);
         let mut pairs = vec![[2, 1], [1, 3], [1, 3]];
-        bucket_sort_pairs(&mut pairs);
+        radix_sort_pairs(&mut pairs);
         let expected = [[1, 3], [2, 1]];
         assert_eq!(pairs, expected);
         let mut pairs = vec![[2, 3], [2, 1]];
-        bucket_sort_pairs(&mut pairs);
+        radix_sort_pairs(&mut pairs);
         let expected = [[2, 1], [2, 3]];
         assert_eq!(pairs, expected);
-    }    
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_radix_sort_wide() {
+        // property ids (just below 2^32) mixed with resource ids (just above it)
+        let prop = u32::MAX as u64 - 1;
+        let res = 1 << 32;
+        let mut pairs = vec![
+            [res + 1, prop], [prop - 3, res], [res + 1, prop], [prop, u64::MAX], [0, 7],
+        ];
+        assert_eq!(radix_sort_pairs(&mut pairs), 4);
+        let expected = [[0, 7], [prop - 3, res], [prop, u64::MAX], [res + 1, prop]];
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn test_radix_sort_parallel() {
+        let n = 3 * RADIX_PAR_THRESHOLD as u64;
+        let mut pairs: Vec<[u64; 2]> = (0..n)
+            .map(|i| [(i * 7919) % 1000 + (1 << 32) * (i % 3), (i * 104729) % n])
+            .collect();
+        let dups = pairs[..1000].to_vec();
+        pairs.extend(dups);
+        let mut expected = pairs.clone();
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(radix_sort_pairs(&mut pairs), expected.len());
+        assert_eq!(pairs, expected);
+    }
+}